@@ -0,0 +1,151 @@
+use crate::font_source::{FontFile, FontSource, Session, matcher::FontMatcher};
+use anyhow::{Result, bail};
+use log::{info, warn};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::Write as _,
+    fs,
+    path::Path,
+};
+#[cfg(feature = "archive-zip")]
+use std::{fs::File, io};
+#[cfg(feature = "archive-zip")]
+use zip::{ZipWriter, write::SimpleFileOptions};
+
+/// 清单的文件名，每行是字幕请求的字体、满足请求的文件和文件所在的字体源，用制表符分隔
+const MANIFEST_NAME: &str = "manifest.txt";
+
+/// 把字幕使用的字体复制到 output 文件夹，output 的扩展名是 .zip 时打包为 ZIP 压缩包
+///
+/// 和 GDI 一样按名称和样式匹配已加载的字体，只收集 GDI 会选中的字体，同时写入一份清单。
+pub fn collect(
+    font_sources: &[Box<dyn FontSource>],
+    session: &Session,
+    output: &Path,
+) -> Result<()> {
+    let usages = session.required_usages();
+    if usages.is_empty() {
+        bail!("no fonts are used by subtitles");
+    }
+    let mut fonts: Vec<(&String, &FontFile)> = Vec::new();
+    let mut matcher = FontMatcher::default();
+    for fs in font_sources {
+        for f in fs.get_fonts() {
+            for (i, face) in f.faces.iter().enumerate() {
+                matcher.add((fonts.len(), i), &face.names);
+            }
+            fonts.push((fs.get_path(), f));
+        }
+    }
+
+    // 收集的字体文件的序号和输出的文件名，包括 Type 1 字体的 PFB 等其他文件
+    let mut collected: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+    let mut used_names = HashSet::new();
+    let mut manifest = String::new();
+    let mut missing = 0;
+    for usage in &usages {
+        // 和 GDI 一样为每种样式只选择一个字体，同一家族中没有用到的字重和斜体不会被收集
+        let Some(found) = matcher.find_style(usage, |&(id, i)| fonts[id].1.faces[i].style) else {
+            writeln!(manifest, "{usage}\t\t")?;
+            missing += 1;
+            continue;
+        };
+        let (id, _) = *found.id;
+        let (source, font) = fonts[id];
+        let names = collected
+            .entry(id)
+            .or_insert_with(|| output_names(font, &mut used_names));
+        writeln!(manifest, "{usage}\t{}\t{source}", names[0])?;
+    }
+
+    let files = collected.iter().flat_map(|(&id, names)| {
+        let font = fonts[id].1;
+        names
+            .iter()
+            .zip(std::iter::once(&font.path).chain(&font.companions))
+    });
+    let is_zip = output
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"));
+    if is_zip {
+        write_zip(output, files, &manifest)?;
+    } else {
+        fs::create_dir_all(output)?;
+        for (name, path) in files {
+            fs::copy(path, output.join(name))?;
+        }
+        fs::write(output.join(MANIFEST_NAME), &manifest)?;
+    }
+    info!(
+        "Collected {} font files used by subtitles to \"{}\"",
+        collected.len(),
+        output.to_str().unwrap()
+    );
+    if missing > 0 {
+        warn!("{missing} fonts used by subtitles are not collected, see \"{MANIFEST_NAME}\"");
+    }
+    Ok(())
+}
+
+/// 字体文件在输出中的文件名，重名时给同一个字体的所有文件加上相同的序号前缀，以免拆散 PFM 和 PFB
+fn output_names(font: &FontFile, used_names: &mut HashSet<String>) -> Vec<String> {
+    let file_names: Vec<String> = std::iter::once(&font.path)
+        .chain(&font.companions)
+        .map(|path| {
+            Path::new(path)
+                .file_name()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string()
+        })
+        .collect();
+    for i in 1.. {
+        let names: Vec<String> = file_names
+            .iter()
+            .map(|name| {
+                if i == 1 {
+                    name.clone()
+                } else {
+                    format!("{i}-{name}")
+                }
+            })
+            .collect();
+        // Windows 上的文件名不区分大小写
+        if names
+            .iter()
+            .all(|name| !used_names.contains(&name.to_lowercase()))
+        {
+            used_names.extend(names.iter().map(|name| name.to_lowercase()));
+            return names;
+        }
+    }
+    unreachable!()
+}
+
+#[cfg(feature = "archive-zip")]
+fn write_zip<'a>(
+    output: &Path,
+    files: impl Iterator<Item = (&'a String, &'a String)>,
+    manifest: &str,
+) -> Result<()> {
+    let mut zip = ZipWriter::new(File::create(output)?);
+    let options = SimpleFileOptions::default();
+    for (name, path) in files {
+        zip.start_file(name.as_str(), options)?;
+        io::copy(&mut File::open(path)?, &mut zip)?;
+    }
+    zip.start_file(MANIFEST_NAME, options)?;
+    io::Write::write_all(&mut zip, manifest.as_bytes())?;
+    zip.finish()?;
+    Ok(())
+}
+
+#[cfg(not(feature = "archive-zip"))]
+fn write_zip<'a>(
+    _output: &Path,
+    _files: impl Iterator<Item = (&'a String, &'a String)>,
+    _manifest: &str,
+) -> Result<()> {
+    bail!("ZIP output is not supported in this build");
+}
@@ -0,0 +1,406 @@
+use crate::font_source::{FontSource, Session};
+#[cfg(feature = "subset")]
+use crate::{
+    font_source::{
+        FontFile,
+        matcher::{FontMatcher, match_key},
+        path_is_subtitle,
+    },
+    sfnt,
+    subtitle::{EmbeddedFont, FontRun, FontUsage, Subtitle},
+};
+use anyhow::{Result, bail};
+#[cfg(feature = "subset")]
+use log::{debug, error, info, warn};
+#[cfg(feature = "subset")]
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, hash_map::Entry},
+    fs,
+    path::Path,
+};
+#[cfg(feature = "subset")]
+use xxhash_rust::xxh3::xxh3_64;
+
+/// 子集化后的字体的输出方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmbedMode {
+    /// 嵌入字幕的 [Fonts] 部分，字幕输出为 `<name>.embed.ass`
+    Fonts,
+    /// 输出到字幕旁边的 `<name>.fonts` 文件夹，可以直接作为 MKV 的附件，字幕输出为 `<name>.subset.ass`
+    Folder,
+}
+
+/// 已加载的一个字体，face 是字体在字体文件的 faces 中的序号
+#[cfg(feature = "subset")]
+struct LoadedFace<'a> {
+    font: &'a FontFile,
+    face: usize,
+    source: &'a str,
+}
+
+/// 字幕中使用的一个字体家族
+#[cfg(feature = "subset")]
+struct Family {
+    /// 字幕中的家族名称，去掉了竖排字体的 @
+    name: String,
+    usages: BTreeSet<FontUsage>,
+    /// 用这个家族显示的字符，空格虽然不需要字形，但是需要其中的宽度
+    chars: BTreeSet<char>,
+}
+
+/// 把每个 ASS/SSA 字幕使用的字体子集化为只包含字幕中的字符，并改为新的家族名称
+///
+/// 新的家族名称是原名称和字符的哈希值，不会和完整安装的字体冲突，字幕中的样式和 \fn 标签会改为使用新的名称。
+/// 原字体和 GDI 一样按名称在已加载的字体中查找，输入的字幕文件不会被修改。
+#[cfg(feature = "subset")]
+pub fn embed(
+    font_sources: &[Box<dyn FontSource>],
+    session: &Session,
+    mode: EmbedMode,
+) -> Result<()> {
+    let mut faces = Vec::new();
+    let mut matcher = FontMatcher::default();
+    for fs in font_sources {
+        for f in fs.get_fonts() {
+            for (i, face) in f.faces.iter().enumerate() {
+                matcher.add(faces.len(), &face.names);
+                faces.push(LoadedFace {
+                    font: f,
+                    face: i,
+                    source: fs.get_path(),
+                });
+            }
+        }
+    }
+
+    // MKV 中的字幕轨道无法直接修改，只处理字幕文件
+    let subtitles: Vec<(String, Vec<FontRun>)> = session
+        .subtitle_runs()
+        .into_iter()
+        .filter(|(name, _)| path_is_subtitle(Path::new(name)) && Path::new(name).is_file())
+        .collect();
+    if subtitles.is_empty() {
+        bail!("no ASS/SSA subtitle files to embed fonts into");
+    }
+    // 同一个字体文件可能被多个家族、样式和字幕使用，只读取一次
+    let mut files = HashMap::new();
+    let mut failed = 0;
+    for (name, runs) in &subtitles {
+        if let Err(err) = embed_subtitle(Path::new(name), runs, &faces, &matcher, mode, &mut files)
+        {
+            error!("Failed to embed fonts into \"{name}\": {err}");
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        bail!("failed to embed fonts into {failed} subtitles");
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "subset"))]
+pub fn embed(
+    _font_sources: &[Box<dyn FontSource>],
+    _session: &Session,
+    _mode: EmbedMode,
+) -> Result<()> {
+    bail!("subsetting fonts is not supported in this build");
+}
+
+#[cfg(feature = "subset")]
+fn embed_subtitle(
+    path: &Path,
+    runs: &[FontRun],
+    faces: &[LoadedFace],
+    matcher: &FontMatcher<usize>,
+    mode: EmbedMode,
+    files: &mut HashMap<String, Vec<u8>>,
+) -> Result<()> {
+    let mut families: BTreeMap<String, Family> = BTreeMap::new();
+    for run in runs {
+        let family = run.font.family.trim_start_matches('@');
+        let entry = families.entry(match_key(family)).or_insert_with(|| Family {
+            name: family.to_string(),
+            usages: BTreeSet::new(),
+            chars: BTreeSet::from([' ', '\u{A0}']),
+        });
+        entry.usages.insert(run.font.clone());
+        entry.chars.extend(run.chars());
+    }
+
+    let mut renames: HashMap<String, String> = HashMap::new();
+    let mut subsets: Vec<(String, Vec<u8>)> = Vec::new();
+    for (
+        key,
+        Family {
+            name: family,
+            usages,
+            chars,
+        },
+    ) in &families
+    {
+        // 只嵌入 GDI 为字幕使用的样式选中的字体
+        let mut ids: Vec<usize> = usages
+            .iter()
+            .filter_map(|usage| {
+                matcher
+                    .find_style(usage, |&id| {
+                        let LoadedFace { font, face, .. } = &faces[id];
+                        font.faces[*face].style
+                    })
+                    .map(|found| *found.id)
+            })
+            .collect();
+        ids.sort();
+        ids.dedup();
+        if ids.is_empty() {
+            warn!(
+                "Font {family} used by \"{}\" is not found and not embedded",
+                path.to_str().unwrap()
+            );
+            continue;
+        }
+        let text: String = chars.iter().collect();
+        let new_family = format!(
+            "{:08X}",
+            xxh3_64(format!("{key}\0{text}").as_bytes()) as u32
+        );
+        let mut family_subsets = Vec::new();
+        let mut whole = None;
+        for id in ids {
+            let LoadedFace { font, face, source } = &faces[id];
+            let data = match files.entry(font.path.clone()) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(fs::read(&font.path)?),
+            };
+            match sfnt::subset(data, font.faces[*face].index, chars, &new_family) {
+                Ok(subset) => {
+                    if !subset.subsetted {
+                        // 完整的字体嵌入字幕后会让字幕变得很大，只作为附件输出
+                        if mode == EmbedMode::Fonts {
+                            whole = Some((font.path.as_str(), *source));
+                            break;
+                        }
+                        info!(
+                            "Font \"{}\" from \"{source}\" cannot be subsetted and is written as a whole",
+                            font.path
+                        );
+                    }
+                    let ext = if subset.data.starts_with(b"OTTO") {
+                        "otf"
+                    } else {
+                        "ttf"
+                    };
+                    let name = format!("{new_family}_{}.{ext}", family_subsets.len());
+                    debug!(
+                        "Subsetted font \"{}\" from \"{source}\" to \"{name}\" ({} bytes)",
+                        font.path,
+                        subset.data.len()
+                    );
+                    family_subsets.push((name, subset.data));
+                }
+                Err(err) => {
+                    warn!(
+                        "Failed to subset font \"{}\" from \"{source}\": {err}",
+                        font.path
+                    );
+                }
+            }
+        }
+        if let Some((font, source)) = whole {
+            // 家族中的字体都使用新名称，只嵌入其中一部分会使其他样式变为模拟的粗体或斜体
+            warn!(
+                "Font {family} used by \"{}\" is not embedded because \"{font}\" from \"{source}\" cannot be subsetted, use --embed-folder to attach it as a whole",
+                path.to_str().unwrap()
+            );
+            continue;
+        }
+        if !family_subsets.is_empty() {
+            info!(
+                "Subsetted {} fonts of {family} to {new_family} with {} characters for \"{}\"",
+                family_subsets.len(),
+                chars.len(),
+                path.to_str().unwrap()
+            );
+            renames.insert(key.clone(), new_family);
+            subsets.extend(family_subsets);
+        }
+    }
+    if subsets.is_empty() {
+        bail!("no fonts are embedded");
+    }
+
+    let text = Subtitle::read_text(path)?;
+    let text = Subtitle::rename_fonts(&text, |family| renames.get(&match_key(family)).cloned());
+    let dir = path.parent().unwrap();
+    let stem = path.file_stem().unwrap().to_str().unwrap();
+    let ext = path.extension().unwrap().to_str().unwrap();
+    match mode {
+        EmbedMode::Fonts => {
+            let fonts: Vec<EmbeddedFont> = subsets
+                .iter()
+                .map(|(name, data)| EmbeddedFont::encode(name.clone(), data))
+                .collect();
+            let output = dir.join(format!("{stem}.embed.{ext}"));
+            write_subtitle(&output, &Subtitle::embed_fonts(&text, &fonts))?;
+            info!(
+                "Embedded {} subsetted fonts used by \"{}\" into \"{}\"",
+                subsets.len(),
+                path.to_str().unwrap(),
+                output.to_str().unwrap()
+            );
+        }
+        EmbedMode::Folder => {
+            let folder = dir.join(format!("{stem}.fonts"));
+            fs::create_dir_all(&folder)?;
+            for (name, data) in &subsets {
+                fs::write(folder.join(name), data)?;
+            }
+            let output = dir.join(format!("{stem}.subset.{ext}"));
+            write_subtitle(&output, &text)?;
+            info!(
+                "Wrote {} subsetted fonts used by \"{}\" to \"{}\" and the renamed subtitle to \"{}\"",
+                subsets.len(),
+                path.to_str().unwrap(),
+                folder.to_str().unwrap(),
+                output.to_str().unwrap()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// 以带 BOM 的 UTF-8 写入字幕，VSFilter 和 libass 都能正确识别
+#[cfg(feature = "subset")]
+fn write_subtitle(path: &Path, text: &str) -> Result<()> {
+    let mut data = Vec::with_capacity(text.len() + 3);
+    data.extend_from_slice(b"\xEF\xBB\xBF");
+    data.extend_from_slice(text.as_bytes());
+    fs::write(path, data)?;
+    Ok(())
+}
+
+#[cfg(all(test, feature = "subset"))]
+mod tests {
+    use super::*;
+    use crate::{
+        font_source::{RecordingBackend, from_path},
+        sfnt::{Table, build_collection, test_font},
+    };
+    use tempdir::TempDir;
+
+    const SUBTITLE: &str = "[Script Info]
+ScriptType: v4.00+
+
+[V4+ Styles]
+Format: Name, Fontname, Bold, Italic
+Style: Default,Test Sans,0,0
+Style: Sign,@Test Sans,-1,0
+Style: Title,Test Mono,0,0
+
+[Events]
+Format: Layer, Start, End, Style, Text
+Dialogue: 0,0:00:00.00,0:00:01.00,Default,ab{\\fnTest Serif}c
+Dialogue: 0,0:00:00.00,0:00:01.00,Sign,a
+Dialogue: 0,0:00:00.00,0:00:01.00,Title,b
+";
+
+    /// 在 OS/2 表的 fsType 中设置不允许子集化的标志
+    fn no_subsetting(data: &[u8]) -> Vec<u8> {
+        let font = sfnt::Font::parse(data, 0).unwrap();
+        let tables: Vec<Table> = font
+            .tables
+            .iter()
+            .map(|record| {
+                let mut data = font.table(&record.tag).unwrap().unwrap().to_vec();
+                if &record.tag == b"OS/2" {
+                    data[8] |= 0x01;
+                }
+                Table {
+                    tag: record.tag,
+                    data,
+                }
+            })
+            .collect();
+        build_collection(&[(0x00010000, (0..tables.len()).collect())], &tables)
+    }
+
+    /// 加载 dir 中的字幕和字体后嵌入字体
+    fn embed_dir(dir: &Path, mode: EmbedMode) {
+        let mut sources: Vec<Box<dyn FontSource>> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.is_file())
+            .map(|path| from_path(path.to_str().unwrap().to_string()).unwrap())
+            .collect();
+        let backend = RecordingBackend::new();
+        let session = Session::new(&backend);
+        for source in &mut sources {
+            source.require_fonts(&session).unwrap();
+        }
+        for source in &mut sources {
+            source.prepare(&session).unwrap();
+        }
+        session.select_versions();
+        for source in &mut sources {
+            source.load(&session).unwrap();
+        }
+        embed(&sources, &session, mode).unwrap();
+    }
+
+    #[test]
+    fn embed_renamed_subsets() {
+        let temp = TempDir::new("fontloader-test").unwrap();
+        let dir = temp.path();
+        fs::write(dir.join("ep.ass"), SUBTITLE).unwrap();
+        let fonts = [
+            ("Sans.ttf", test_font("Test Sans", "Regular", 400, "abc")),
+            ("SansBold.ttf", test_font("Test Sans", "Bold", 700, "abc")),
+            ("Serif.ttf", test_font("Test Serif", "Regular", 400, "abc")),
+            (
+                "Mono.ttf",
+                no_subsetting(&test_font("Test Mono", "Regular", 400, "abc")),
+            ),
+        ];
+        for (name, data) in &fonts {
+            fs::write(dir.join(name), data).unwrap();
+        }
+
+        embed_dir(dir, EmbedMode::Fonts);
+        let text = Subtitle::read_text(&dir.join("ep.embed.ass")).unwrap();
+        assert!(text.contains("Style: Sign,@"));
+        let subtitle = Subtitle::parse(&text);
+        // 字幕中的新名称和子集的 name 表中的家族名称相同，无法子集化的家族保留原名称并且不会嵌入
+        let used: BTreeSet<String> = subtitle
+            .font_usages()
+            .into_iter()
+            .map(|usage| usage.family.trim_start_matches('@').to_string())
+            .collect();
+        let mut embedded = BTreeSet::new();
+        for font in &subtitle.fonts {
+            let data = font.decode().unwrap();
+            for face in sfnt::read_faces(&data).unwrap() {
+                embedded.insert(face.unwrap().names.family.unwrap());
+            }
+        }
+        assert_eq!(subtitle.fonts.len(), 3);
+        assert_eq!(embedded.len(), 2);
+        assert!(embedded.iter().all(|family| !family.starts_with("Test")));
+        assert_eq!(
+            used,
+            embedded
+                .iter()
+                .cloned()
+                .chain(["Test Mono".to_string()])
+                .collect()
+        );
+
+        // 输出到文件夹时无法子集化的字体改名后完整输出
+        fs::remove_file(dir.join("ep.embed.ass")).unwrap();
+        embed_dir(dir, EmbedMode::Folder);
+        let text = Subtitle::read_text(&dir.join("ep.subset.ass")).unwrap();
+        assert!(Subtitle::parse(&text).fonts.is_empty());
+        assert!(!text.contains("Test Mono"));
+        assert_eq!(fs::read_dir(dir.join("ep.fonts")).unwrap().count(), 4);
+    }
+}
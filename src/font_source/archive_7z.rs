@@ -1,14 +1,18 @@
-use crate::font_source::{FontFile, FontSource, TEMPDIR_PREFIX, path_is_font};
+use crate::font_source::{
+    FontBackend, FontFile, FontSource, Session, TEMPDIR_PREFIX, extract_fonts, group_companions,
+};
 use anyhow::Result;
 use cfg_if::cfg_if;
 use log::{debug, info, warn};
 #[cfg(feature = "parallel")]
-use rayon::iter::{IntoParallelRefIterator, ParallelExtend, ParallelIterator};
+use rayon::iter::{
+    IntoParallelIterator, IntoParallelRefIterator, ParallelExtend, ParallelIterator,
+};
 use std::{
-    fs::{self, File},
-    io,
+    collections::HashSet,
+    fs::File,
+    io::{self, Read},
     path::Path,
-    time::Instant,
 };
 use tempdir::TempDir;
 
@@ -16,6 +20,10 @@ use tempdir::TempDir;
 pub struct FontArchive7z {
     path: String,
     extract: TempDir,
+    /// 只加载的成员，为 None 时加载所有字体
+    members: Option<HashSet<String>>,
+    /// prepare 选出的需要加载的字体
+    found: Vec<(String, FontFile)>,
     loaded: Vec<(String, FontFile)>,
 }
 
@@ -24,63 +32,65 @@ impl FontArchive7z {
         Self {
             path,
             extract: TempDir::new(TEMPDIR_PREFIX).unwrap(),
+            members: None,
+            found: Vec::new(),
             loaded: Vec::new(),
         }
     }
+
+    /// 依次读取压缩包中 want 返回 true 的每个文件，不会解压到硬盘上
+    pub fn for_each_member(
+        path: &str,
+        want: impl Fn(&Path) -> bool,
+        mut op: impl FnMut(&Path, &mut dyn Read) -> io::Result<()>,
+    ) -> Result<()> {
+        let mut archive =
+            sevenz_rust2::ArchiveReader::new(File::open(path)?, sevenz_rust2::Password::empty())?;
+        archive.for_each_entries(|entry, reader| {
+            if entry.is_directory() {
+                return Ok(true);
+            }
+            let path = Path::new(&entry.name);
+            if want(path) {
+                op(path, reader)?;
+            }
+            // 因为 7z 可以固实压缩，所以就算不需要这个文件或者文件剩下的部分也要解压一遍
+            io::copy(reader, &mut io::sink())?;
+            Ok(true)
+        })?;
+        Ok(())
+    }
 }
 
 impl FontSource for FontArchive7z {
-    fn load(&mut self) -> Result<()> {
-        debug!("Walking 7z \"{}\"", self.path);
-        let mut archive = sevenz_rust2::ArchiveReader::new(
-            File::open(&self.path)?,
-            sevenz_rust2::Password::empty(),
+    fn prepare(&mut self, session: &Session) -> Result<()> {
+        let extracted = extract_fonts(
+            &self.path,
+            "7z",
+            self.members.as_ref(),
+            self.extract.path(),
+            |want, op| Self::for_each_member(&self.path, want, op),
         )?;
-        let mut extracted = vec![];
-        let start = Instant::now();
-        archive
-            .for_each_entries(|entry, reader| {
-                let path = Path::new(&entry.name);
-                if !path_is_font(path) {
-                    // 因为 7z 可以固实压缩，所以就算文件不需要解压到硬盘上也要解压一遍
-                    io::copy(reader, &mut io::sink())?;
-                    return Ok(true);
-                }
 
-                let extract = self.extract.path().join(Path::new(&entry.name));
-                debug!(
-                    "Found font \"{}\" from 7z \"{}\" and extract to \"{}\"",
-                    entry.name,
-                    self.path,
-                    extract.to_str().unwrap(),
-                );
-                if let Some(parent) = &extract.parent()
-                    && !parent.exists()
-                {
-                    fs::create_dir_all(parent)?;
-                }
-                File::create(&extract).and_then(|mut outfile| io::copy(reader, &mut outfile))?;
-                extracted.push((entry.name.clone(), extract.to_str().unwrap().to_string()));
+        let extracted = group_companions(extracted, &self.path);
+        self.found = session.select_fonts(extracted, &self.path);
+        Ok(())
+    }
 
-                Ok(true)
-            })
-            .map(|_| ())?;
-        debug!(
-            "Extracted fonts from 7z \"{}\" in {}s",
-            self.path,
-            start.elapsed().as_secs_f64()
-        );
+    fn load(&mut self, session: &Session) -> Result<()> {
+        let extracted = std::mem::take(&mut self.found);
 
-        let extracted_op = |name_extract: &(String, String)| -> Option<(String, FontFile)> {
-            let (name, extract) = name_extract;
-            let mut f = FontFile::new(extract.clone());
-            match f.load() {
-                Ok(_) => {
+        let extracted_op = |(name, f): (String, FontFile)| -> Option<(String, FontFile)> {
+            match session.register(&name, &self.path, &f) {
+                Ok(false) => None,
+                Ok(true) => {
                     info!(
-                        "Extracted font \"{}\" from \"{}\" and loaded",
-                        name, self.path
+                        "Extracted font \"{}\" [{}] from \"{}\" and loaded",
+                        name,
+                        f.describe(),
+                        self.path
                     );
-                    Some((name.clone(), f))
+                    Some((name, f))
                 }
                 Err(err) => {
                     warn!(
@@ -93,10 +103,10 @@ impl FontSource for FontArchive7z {
         };
         cfg_if! {
             if #[cfg(feature = "parallel")] {
-                let iter = extracted.par_iter();
+                let iter = extracted.into_par_iter();
                 self.loaded.par_extend(iter.filter_map(extracted_op));
             } else {
-                let iter = extracted.iter();
+                let iter = extracted.into_iter();
                 self.loaded.extend(iter.filter_map(extracted_op));
             }
         }
@@ -104,7 +114,7 @@ impl FontSource for FontArchive7z {
         Ok(())
     }
 
-    fn unload(&self) {
+    fn unload(&self, backend: &dyn FontBackend) {
         cfg_if! {
             if #[cfg(feature = "parallel")] {
                 let iter = self.loaded.par_iter();
@@ -117,15 +127,26 @@ impl FontSource for FontArchive7z {
                 "Unload font \"{}\" (\"{}\") from 7z \"{}\"",
                 f.path, name, self.path
             );
-            f.unload();
+            f.unload(backend);
             info!(
-                "Unloaded extracted font \"{}\" from \"{}\"",
-                name, self.path
+                "Unloaded extracted font \"{}\" [{}] from \"{}\"",
+                name,
+                f.describe(),
+                self.path
             );
         });
     }
 
+    #[cfg(feature = "library")]
+    fn select_members(&mut self, members: HashSet<String>) {
+        self.members = Some(members);
+    }
+
     fn get_path(&self) -> &String {
         &self.path
     }
+
+    fn get_fonts(&self) -> Vec<&FontFile> {
+        self.loaded.iter().map(|(_, f)| f).collect()
+    }
 }
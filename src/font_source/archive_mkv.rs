@@ -0,0 +1,247 @@
+use crate::{
+    font_source::{
+        FontBackend, FontFile, FontSource, Session, TEMPDIR_PREFIX, extract_if_font,
+        group_companions, path_is_font, subtitle::require_subtitle_fonts,
+    },
+    matroska,
+    subtitle::Subtitle,
+};
+use anyhow::Result;
+use cfg_if::cfg_if;
+use log::{debug, info, warn};
+#[cfg(feature = "parallel")]
+use rayon::iter::{
+    IntoParallelIterator, IntoParallelRefIterator, ParallelExtend, ParallelIterator,
+};
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::Path,
+    time::Instant,
+};
+use tempdir::TempDir;
+
+/// 字体附件常用的 MIME 类型对应的扩展名
+fn font_mime_extension(mime_type: &str) -> Option<&'static str> {
+    match mime_type.to_ascii_lowercase().as_str() {
+        "application/x-truetype-font"
+        | "application/x-font-ttf"
+        | "font/ttf"
+        | "font/sfnt"
+        | "application/font-sfnt" => Some("ttf"),
+        "application/vnd.ms-opentype" | "application/x-font-otf" | "font/otf" => Some("otf"),
+        "font/collection" => Some("ttc"),
+        "font/woff" | "application/font-woff" => Some("woff"),
+        "font/woff2" => Some("woff2"),
+        _ => None,
+    }
+}
+
+/// 带有字体附件的 Matroska 文件（MKV/MKA/MKS）
+pub struct FontArchiveMkv {
+    path: String,
+    extract: TempDir,
+    /// prepare 选出的需要加载的字体
+    found: Vec<(String, FontFile)>,
+    loaded: Vec<(String, FontFile)>,
+}
+
+impl FontArchiveMkv {
+    pub fn new(path: String) -> Self {
+        Self {
+            path,
+            extract: TempDir::new(TEMPDIR_PREFIX).unwrap(),
+            found: Vec::new(),
+            loaded: Vec::new(),
+        }
+    }
+}
+
+impl FontSource for FontArchiveMkv {
+    fn require_fonts(&mut self, session: &Session) -> Result<()> {
+        if !session.muxed_subtitles {
+            return Ok(());
+        }
+        debug!("Reading subtitle tracks from mkv \"{}\"", self.path);
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        for track in matroska::read_subtitle_tracks(&mut reader)? {
+            let subtitle = Subtitle::parse_matroska(&track.header, &track.events);
+            let name = format!("{} (track {})", self.path, track.number);
+            require_subtitle_fonts(&name, &subtitle, session);
+        }
+        Ok(())
+    }
+
+    fn prepare(&mut self, session: &Session) -> Result<()> {
+        debug!("Walking mkv \"{}\"", self.path);
+        // MKV 的附件是其中的字幕自带的字体
+        session.attach(&self.path);
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        let attachments = matroska::read_attachments(&mut reader)?;
+        let mut extracted = vec![];
+        let start = Instant::now();
+        for (i, attachment) in attachments.iter().enumerate() {
+            // MIME 类型是字体但是文件名没有字体扩展名时，按照 MIME 类型补上扩展名
+            let name = match font_mime_extension(&attachment.mime_type) {
+                Some(ext) if !path_is_font(Path::new(&attachment.name)) => {
+                    format!("{}.{ext}", attachment.name)
+                }
+                _ => attachment.name.clone(),
+            };
+            let file_name = Path::new(&name)
+                .file_name()
+                .map_or("attachment".into(), |name| name.to_os_string());
+            // 附件的文件名可能重复
+            let extract = self.extract.path().join(i.to_string()).join(file_name);
+            reader.seek(SeekFrom::Start(attachment.offset))?;
+            let mut data = (&mut reader).take(attachment.size);
+            let Some(extract) = extract_if_font(Path::new(&name), &mut data, &extract)? else {
+                continue;
+            };
+            debug!(
+                "Found font \"{}\" ({}) from mkv \"{}\" and extracted to \"{}\"",
+                attachment.name,
+                attachment.mime_type,
+                self.path,
+                extract.to_str().unwrap(),
+            );
+            extracted.push((
+                attachment.name.clone(),
+                FontFile::new(extract.to_str().unwrap().to_string()),
+            ));
+        }
+        debug!(
+            "Extracted fonts from mkv \"{}\" in {}s",
+            self.path,
+            start.elapsed().as_secs_f64()
+        );
+
+        let extracted = group_companions(extracted, &self.path);
+        self.found = session.select_fonts(extracted, &self.path);
+        Ok(())
+    }
+
+    fn load(&mut self, session: &Session) -> Result<()> {
+        let extracted = std::mem::take(&mut self.found);
+
+        let extracted_op = |(name, f): (String, FontFile)| -> Option<(String, FontFile)> {
+            match session.register(&name, &self.path, &f) {
+                Ok(false) => None,
+                Ok(true) => {
+                    info!(
+                        "Extracted font \"{}\" [{}] from \"{}\" and loaded",
+                        name,
+                        f.describe(),
+                        self.path
+                    );
+                    Some((name, f))
+                }
+                Err(err) => {
+                    warn!(
+                        "Skipped font \"{}\" from \"{}\" failed to load: {}",
+                        name, self.path, err
+                    );
+                    None
+                }
+            }
+        };
+        cfg_if! {
+            if #[cfg(feature = "parallel")] {
+                let iter = extracted.into_par_iter();
+                self.loaded.par_extend(iter.filter_map(extracted_op));
+            } else {
+                let iter = extracted.into_iter();
+                self.loaded.extend(iter.filter_map(extracted_op));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn unload(&self, backend: &dyn FontBackend) {
+        cfg_if! {
+            if #[cfg(feature = "parallel")] {
+                let iter = self.loaded.par_iter();
+            } else {
+                let iter = self.loaded.iter();
+            }
+        }
+        iter.for_each(|(name, f)| {
+            debug!(
+                "Unload font \"{}\" (\"{}\") from mkv \"{}\"",
+                f.path, name, self.path
+            );
+            f.unload(backend);
+            info!(
+                "Unloaded extracted font \"{}\" [{}] from \"{}\"",
+                name,
+                f.describe(),
+                self.path
+            );
+        });
+    }
+
+    fn get_path(&self) -> &String {
+        &self.path
+    }
+
+    fn get_fonts(&self) -> Vec<&FontFile> {
+        self.loaded.iter().map(|(_, f)| f).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{font_source::RecordingBackend, matroska::test_mkv, sfnt::test_font};
+    use std::fs;
+
+    #[test]
+    fn fonts_required_by_muxed_subtitles() {
+        let temp = TempDir::new("fontloader-test").unwrap();
+        let data = test_mkv(
+            "[Script Info]\n\n[V4+ Styles]\nFormat: Name, Fontname\nStyle: Default,Test Sans",
+            &["0,0,Default,,0,0,0,,Hello"],
+            &[
+                (
+                    "TestSans",
+                    "application/x-truetype-font",
+                    &test_font("Test Sans", "Regular", 400, "Helo"),
+                ),
+                (
+                    "Serif.ttf",
+                    "font/ttf",
+                    &test_font("Test Serif", "Regular", 400, "a"),
+                ),
+                ("cover.jpg", "image/jpeg", b"\xFF\xD8\xFF"),
+            ],
+            true,
+        );
+        let path = temp.path().join("ep.mkv");
+        fs::write(&path, data).unwrap();
+        let path = path.to_str().unwrap().to_string();
+
+        let backend = RecordingBackend::new();
+        let mut session = Session::new(&backend);
+        session.muxed_subtitles = true;
+        let mut source = FontArchiveMkv::new(path.clone());
+        source.require_fonts(&session).unwrap();
+        source.prepare(&session).unwrap();
+        session.select_versions();
+        source.load(&session).unwrap();
+
+        // 没有扩展名的附件按照 MIME 类型补上扩展名
+        let fonts = source.get_fonts();
+        assert_eq!(fonts.len(), 1);
+        assert!(fonts[0].path.ends_with("TestSans.ttf"));
+        assert!(session.missing().is_empty());
+        assert!(session.uncovered().is_empty());
+        let unused = session.unused();
+        assert_eq!(unused.len(), 1);
+        assert_eq!(
+            unused[0].name,
+            Path::new(&path).join("Serif.ttf").to_str().unwrap()
+        );
+        assert!(unused[0].attached);
+    }
+}
@@ -1,16 +1,32 @@
-use crate::font_source::{FontFile, FontSource, TEMPDIR_PREFIX, path_is_font};
+use crate::font_source::{
+    FontBackend, FontFile, FontSource, Session, TEMPDIR_PREFIX, extract_fonts, group_companions,
+    path_is_font,
+};
 use anyhow::Result;
 use cfg_if::cfg_if;
 use log::{debug, info, warn};
 #[cfg(feature = "parallel")]
-use rayon::iter::{IntoParallelRefIterator, ParallelExtend, ParallelIterator};
-use std::{fs, path::Path, time::Instant};
+use rayon::iter::{
+    IntoParallelIterator, IntoParallelRefIterator, ParallelExtend, ParallelIterator,
+};
+use std::{
+    collections::HashSet,
+    io::{self, Read},
+    path::Path,
+};
 use tempdir::TempDir;
 
+/// 检查没有字体扩展名的文件内容时最多读取的文件大小
+const SNIFF_MAX_SIZE: u64 = 64 * 1024 * 1024;
+
 /// 包含字体的 RAR 压缩包
 pub struct FontArchiveRar {
     path: String,
     extract: TempDir,
+    /// 只加载的成员，为 None 时加载所有字体
+    members: Option<HashSet<String>>,
+    /// prepare 选出的需要加载的字体
+    found: Vec<(String, FontFile)>,
     loaded: Vec<(String, FontFile)>,
 }
 
@@ -19,55 +35,66 @@ impl FontArchiveRar {
         Self {
             path,
             extract: TempDir::new(TEMPDIR_PREFIX).unwrap(),
+            members: None,
+            found: Vec::new(),
             loaded: Vec::new(),
         }
     }
-}
 
-impl FontSource for FontArchiveRar {
-    fn load(&mut self) -> Result<()> {
-        debug!("Walking rar \"{}\"", self.path);
-        let mut archive = unrar::Archive::new(&self.path).open_for_processing()?;
-        let mut extracted = vec![];
-        let start = Instant::now();
+    /// 依次读取压缩包中 want 返回 true 的每个文件，不会解压到硬盘上
+    pub fn for_each_member(
+        path: &str,
+        want: impl Fn(&Path) -> bool,
+        mut op: impl FnMut(&Path, &mut dyn Read) -> io::Result<()>,
+    ) -> Result<()> {
+        let mut archive = unrar::Archive::new(path).open_for_processing()?;
         while let Some(header) = archive.read_header()? {
-            let path = Path::new(&header.entry().filename);
-            let path_str = path.to_str().unwrap().to_string();
-            if !path_is_font(path) {
+            let path = header.entry().filename.clone();
+            // 没有字体扩展名的大文件不太可能是字体，不读取到内存中检查
+            if !header.entry().is_file()
+                || !want(&path)
+                || (header.entry().unpacked_size > SNIFF_MAX_SIZE && !path_is_font(&path))
+            {
                 archive = header.skip()?;
                 continue;
             }
-            let extract = self.extract.path().join(path);
-            debug!(
-                "Found font \"{}\" from rar \"{}\" and extract to \"{}\"",
-                path_str,
-                self.path,
-                extract.to_str().unwrap(),
-            );
-            if let Some(parent) = &extract.parent()
-                && !parent.exists()
-            {
-                fs::create_dir_all(parent)?;
-            }
-            archive = header.extract_to(&extract)?;
-            extracted.push((path_str, extract.to_str().unwrap().to_string()));
+            let (data, next) = header.read()?;
+            archive = next;
+            op(&path, &mut data.as_slice())?;
         }
-        debug!(
-            "Extracted fonts from rar \"{}\" in {}s",
-            self.path,
-            start.elapsed().as_secs_f64()
-        );
+        Ok(())
+    }
+}
 
-        let extracted_op = |name_extract: &(String, String)| -> Option<(String, FontFile)> {
-            let (name, extract) = name_extract;
-            let mut f = FontFile::new(extract.clone());
-            match f.load() {
-                Ok(_) => {
+impl FontSource for FontArchiveRar {
+    fn prepare(&mut self, session: &Session) -> Result<()> {
+        let extracted = extract_fonts(
+            &self.path,
+            "rar",
+            self.members.as_ref(),
+            self.extract.path(),
+            |want, op| Self::for_each_member(&self.path, want, op),
+        )?;
+
+        let extracted = group_companions(extracted, &self.path);
+        self.found = session.select_fonts(extracted, &self.path);
+        Ok(())
+    }
+
+    fn load(&mut self, session: &Session) -> Result<()> {
+        let extracted = std::mem::take(&mut self.found);
+
+        let extracted_op = |(name, f): (String, FontFile)| -> Option<(String, FontFile)> {
+            match session.register(&name, &self.path, &f) {
+                Ok(false) => None,
+                Ok(true) => {
                     info!(
-                        "Extracted font \"{}\" from \"{}\" and loaded",
-                        name, self.path
+                        "Extracted font \"{}\" [{}] from \"{}\" and loaded",
+                        name,
+                        f.describe(),
+                        self.path
                     );
-                    Some((name.clone(), f))
+                    Some((name, f))
                 }
                 Err(err) => {
                     warn!(
@@ -80,10 +107,10 @@ impl FontSource for FontArchiveRar {
         };
         cfg_if! {
             if #[cfg(feature = "parallel")] {
-                let iter = extracted.par_iter();
+                let iter = extracted.into_par_iter();
                 self.loaded.par_extend(iter.filter_map(extracted_op));
             } else {
-                let iter = extracted.iter();
+                let iter = extracted.into_iter();
                 self.loaded.extend(iter.filter_map(extracted_op));
             }
         }
@@ -91,7 +118,7 @@ impl FontSource for FontArchiveRar {
         Ok(())
     }
 
-    fn unload(&self) {
+    fn unload(&self, backend: &dyn FontBackend) {
         cfg_if! {
             if #[cfg(feature = "parallel")] {
                 let iter = self.loaded.par_iter();
@@ -104,15 +131,26 @@ impl FontSource for FontArchiveRar {
                 "Unload font \"{}\" (\"{}\") from rar \"{}\"",
                 f.path, name, self.path
             );
-            f.unload();
+            f.unload(backend);
             info!(
-                "Unloaded extracted font \"{}\" from \"{}\"",
-                name, self.path
+                "Unloaded extracted font \"{}\" [{}] from \"{}\"",
+                name,
+                f.describe(),
+                self.path
             );
         });
     }
 
+    #[cfg(feature = "library")]
+    fn select_members(&mut self, members: HashSet<String>) {
+        self.members = Some(members);
+    }
+
     fn get_path(&self) -> &String {
         &self.path
     }
+
+    fn get_fonts(&self) -> Vec<&FontFile> {
+        self.loaded.iter().map(|(_, f)| f).collect()
+    }
 }
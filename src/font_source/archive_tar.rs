@@ -1,15 +1,20 @@
-use crate::font_source::{FontFile, FontSource, TEMPDIR_PREFIX, path_is_font};
+use crate::font_source::{
+    FontBackend, FontFile, FontSource, Session, TEMPDIR_PREFIX, extract_fonts, group_companions,
+};
 use anyhow::Result;
 use bzip2::read::BzDecoder;
 use cfg_if::cfg_if;
 use flate2::read::GzDecoder;
 use log::{debug, info, warn};
 #[cfg(feature = "parallel")]
-use rayon::iter::{IntoParallelRefIterator, ParallelExtend, ParallelIterator};
+use rayon::iter::{
+    IntoParallelIterator, IntoParallelRefIterator, ParallelExtend, ParallelIterator,
+};
 use std::{
-    fs::{self, File},
+    collections::HashSet,
+    fs::File,
     io::{self, Read},
-    time::Instant,
+    path::Path,
 };
 use tempdir::TempDir;
 use xz2::read::XzDecoder;
@@ -28,6 +33,10 @@ pub struct FontArchiveTar {
     path: String,
     compression: TarCompression,
     extract: TempDir,
+    /// 只加载的成员，为 None 时加载所有字体
+    members: Option<HashSet<String>>,
+    /// prepare 选出的需要加载的字体
+    found: Vec<(String, FontFile)>,
     loaded: Vec<(String, FontFile)>,
 }
 
@@ -37,16 +46,21 @@ impl FontArchiveTar {
             path,
             compression,
             extract: TempDir::new(TEMPDIR_PREFIX).unwrap(),
+            members: None,
+            found: Vec::new(),
             loaded: Vec::new(),
         }
     }
-}
 
-impl FontSource for FontArchiveTar {
-    fn load(&mut self) -> Result<()> {
-        debug!("Walking tar \"{}\"", self.path);
-        let archive = File::open(&self.path)?;
-        let archive: Box<dyn Read> = match self.compression {
+    /// 依次读取 tarball 中 want 返回 true 的每个文件，不会解压到硬盘上
+    pub fn for_each_member(
+        path: &str,
+        compression: &TarCompression,
+        want: impl Fn(&Path) -> bool,
+        mut op: impl FnMut(&Path, &mut dyn Read) -> io::Result<()>,
+    ) -> Result<()> {
+        let archive = File::open(path)?;
+        let archive: Box<dyn Read> = match compression {
             TarCompression::None => Box::new(archive),
             TarCompression::GZ => Box::new(GzDecoder::new(archive)),
             TarCompression::BZ2 => Box::new(BzDecoder::new(archive)),
@@ -54,47 +68,49 @@ impl FontSource for FontArchiveTar {
             TarCompression::Zstd => Box::new(zstd::Decoder::new(archive)?),
         };
         let mut archive = tar::Archive::new(archive);
-        let mut extracted = vec![];
-        let start = Instant::now();
         for entry in archive.entries()? {
             let mut entry = entry?;
-            let path = entry.path()?;
-            if !path_is_font(&path) {
+            if !entry.header().entry_type().is_file() {
                 continue;
             }
-
-            let extract = self.extract.path().join(&path);
-            let path_str = path.to_str().unwrap().to_string();
-            debug!(
-                "Found font \"{}\" from tar \"{}\" and extract to \"{}\"",
-                path_str,
-                self.path,
-                extract.to_str().unwrap(),
-            );
-            if let Some(parent) = &extract.parent()
-                && !parent.exists()
-            {
-                fs::create_dir_all(parent)?;
+            let path = entry.path()?.into_owned();
+            if want(&path) {
+                op(&path, &mut entry)?;
             }
-            File::create(&extract).and_then(|mut outfile| io::copy(&mut entry, &mut outfile))?;
-            extracted.push((path_str, extract.to_str().unwrap().to_string()));
         }
-        debug!(
-            "Extracted fonts from tar \"{}\" in {}s",
-            self.path,
-            start.elapsed().as_secs_f64()
-        );
+        Ok(())
+    }
+}
 
-        let extracted_op = |name_extract: &(String, String)| -> Option<(String, FontFile)> {
-            let (name, extract) = name_extract;
-            let mut f = FontFile::new(extract.clone());
-            match f.load() {
-                Ok(_) => {
+impl FontSource for FontArchiveTar {
+    fn prepare(&mut self, session: &Session) -> Result<()> {
+        let extracted = extract_fonts(
+            &self.path,
+            "tar",
+            self.members.as_ref(),
+            self.extract.path(),
+            |want, op| Self::for_each_member(&self.path, &self.compression, want, op),
+        )?;
+
+        let extracted = group_companions(extracted, &self.path);
+        self.found = session.select_fonts(extracted, &self.path);
+        Ok(())
+    }
+
+    fn load(&mut self, session: &Session) -> Result<()> {
+        let extracted = std::mem::take(&mut self.found);
+
+        let extracted_op = |(name, f): (String, FontFile)| -> Option<(String, FontFile)> {
+            match session.register(&name, &self.path, &f) {
+                Ok(false) => None,
+                Ok(true) => {
                     info!(
-                        "Extracted font \"{}\" from \"{}\" and loaded",
-                        name, self.path
+                        "Extracted font \"{}\" [{}] from \"{}\" and loaded",
+                        name,
+                        f.describe(),
+                        self.path
                     );
-                    Some((name.clone(), f))
+                    Some((name, f))
                 }
                 Err(err) => {
                     warn!(
@@ -107,10 +123,10 @@ impl FontSource for FontArchiveTar {
         };
         cfg_if! {
             if #[cfg(feature = "parallel")] {
-                let iter = extracted.par_iter();
+                let iter = extracted.into_par_iter();
                 self.loaded.par_extend(iter.filter_map(extracted_op));
             } else {
-                let iter = extracted.iter();
+                let iter = extracted.into_iter();
                 self.loaded.extend(iter.filter_map(extracted_op));
             }
         }
@@ -118,7 +134,7 @@ impl FontSource for FontArchiveTar {
         Ok(())
     }
 
-    fn unload(&self) {
+    fn unload(&self, backend: &dyn FontBackend) {
         cfg_if! {
             if #[cfg(feature = "parallel")] {
                 let iter = self.loaded.par_iter();
@@ -131,15 +147,26 @@ impl FontSource for FontArchiveTar {
                 "Unload font \"{}\" (\"{}\") from tar \"{}\"",
                 f.path, name, self.path
             );
-            f.unload();
+            f.unload(backend);
             info!(
-                "Unloaded extracted font \"{}\" from \"{}\"",
-                name, self.path
+                "Unloaded extracted font \"{}\" [{}] from \"{}\"",
+                name,
+                f.describe(),
+                self.path
             );
         });
     }
 
+    #[cfg(feature = "library")]
+    fn select_members(&mut self, members: HashSet<String>) {
+        self.members = Some(members);
+    }
+
     fn get_path(&self) -> &String {
         &self.path
     }
+
+    fn get_fonts(&self) -> Vec<&FontFile> {
+        self.loaded.iter().map(|(_, f)| f).collect()
+    }
 }
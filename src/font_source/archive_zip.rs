@@ -1,14 +1,18 @@
-use crate::font_source::{FontFile, FontSource, TEMPDIR_PREFIX, path_is_font};
+use crate::font_source::{
+    FontBackend, FontFile, FontSource, Session, TEMPDIR_PREFIX, extract_fonts, group_companions,
+};
 use anyhow::Result;
 use cfg_if::cfg_if;
 use log::{debug, info, warn};
 #[cfg(feature = "parallel")]
-use rayon::iter::{IntoParallelRefIterator, ParallelExtend, ParallelIterator};
+use rayon::iter::{
+    IntoParallelIterator, IntoParallelRefIterator, ParallelExtend, ParallelIterator,
+};
 use std::{
-    fs::{self, File},
-    io,
+    collections::HashSet,
+    fs::File,
+    io::{self, Read},
     path::Path,
-    time::Instant,
 };
 use tempdir::TempDir;
 use zip::ZipArchive;
@@ -17,6 +21,10 @@ use zip::ZipArchive;
 pub struct FontArchiveZip {
     path: String,
     extract: TempDir,
+    /// 只加载的成员，为 None 时加载所有字体
+    members: Option<HashSet<String>>,
+    /// prepare 选出的需要加载的字体
+    found: Vec<(String, FontFile)>,
     loaded: Vec<(String, FontFile)>,
 }
 
@@ -25,55 +33,67 @@ impl FontArchiveZip {
         Self {
             path,
             extract: TempDir::new(TEMPDIR_PREFIX).unwrap(),
+            members: None,
+            found: Vec::new(),
             loaded: Vec::new(),
         }
     }
-}
 
-impl FontSource for FontArchiveZip {
-    fn load(&mut self) -> Result<()> {
-        debug!("Walking zip \"{}\"", self.path);
-        let mut archive = ZipArchive::new(File::open(&self.path)?)?;
-        let file_names: Vec<String> = archive
-            .file_names()
-            .filter(|name| path_is_font(Path::new(name)))
-            .map(String::from)
-            .collect();
-        let mut extracted = vec![];
-        let start = Instant::now();
-        for name in file_names {
-            let extract = self.extract.path().join(Path::new(&name));
-            debug!(
-                "Found font \"{}\" from zip \"{}\" and extract to \"{}\"",
-                name,
-                self.path,
-                extract.to_str().unwrap(),
-            );
-            let mut file = archive.by_name(&name)?;
-            if let Some(parent) = &extract.parent()
-                && !parent.exists()
+    /// 依次读取压缩包中 want 返回 true 的每个文件，不会解压到硬盘上
+    pub fn for_each_member(
+        path: &str,
+        want: impl Fn(&Path) -> bool,
+        mut op: impl FnMut(&Path, &mut dyn Read) -> io::Result<()>,
+    ) -> Result<()> {
+        let mut archive = ZipArchive::new(File::open(path)?)?;
+        for i in 0..archive.len() {
+            // ZIP 可以直接跳过不需要的文件，不用解压
+            if !archive
+                .name_for_index(i)
+                .is_some_and(|name| want(Path::new(name)))
             {
-                fs::create_dir_all(parent)?;
+                continue;
+            }
+            let mut file = archive.by_index(i)?;
+            if !file.is_file() {
+                continue;
             }
-            File::create(&extract).and_then(|mut outfile| io::copy(&mut file, &mut outfile))?;
-            extracted.push((name, extract.to_str().unwrap().to_string()));
+            let name = file.name().to_string();
+            op(Path::new(&name), &mut file)?;
         }
-        debug!(
-            "Extracted fonts from zip \"{}\" in {}s",
-            self.path,
-            start.elapsed().as_secs_f64()
-        );
+        Ok(())
+    }
+}
 
-        let extracted_op = |name_extract: &(String, String)| -> Option<(String, FontFile)> {
-            let (name, extract) = name_extract;
-            let mut f = FontFile::new(extract.clone());
-            match f.load() {
-                Ok(_) => {
+impl FontSource for FontArchiveZip {
+    fn prepare(&mut self, session: &Session) -> Result<()> {
+        let extracted = extract_fonts(
+            &self.path,
+            "zip",
+            self.members.as_ref(),
+            self.extract.path(),
+            |want, op| Self::for_each_member(&self.path, want, op),
+        )?;
+
+        let extracted = group_companions(extracted, &self.path);
+        self.found = session.select_fonts(extracted, &self.path);
+        Ok(())
+    }
+
+    fn load(&mut self, session: &Session) -> Result<()> {
+        let extracted = std::mem::take(&mut self.found);
+
+        let extracted_op = |(name, f): (String, FontFile)| -> Option<(String, FontFile)> {
+            match session.register(&name, &self.path, &f) {
+                Ok(false) => None,
+                Ok(true) => {
                     info!(
-                        "Extracted font \"{}\" from \"{}\" and loaded",
-                        name, self.path
+                        "Extracted font \"{}\" [{}] from \"{}\" and loaded",
+                        name,
+                        f.describe(),
+                        self.path
                     );
-                    Some((name.clone(), f))
+                    Some((name, f))
                 }
                 Err(err) => {
                     warn!(
@@ -86,10 +106,10 @@ impl FontSource for FontArchiveZip {
         };
         cfg_if! {
             if #[cfg(feature = "parallel")] {
-                let iter = extracted.par_iter();
+                let iter = extracted.into_par_iter();
                 self.loaded.par_extend(iter.filter_map(extracted_op));
             } else {
-                let iter = extracted.iter();
+                let iter = extracted.into_iter();
                 self.loaded.extend(iter.filter_map(extracted_op));
             }
         }
@@ -97,7 +117,7 @@ impl FontSource for FontArchiveZip {
         Ok(())
     }
 
-    fn unload(&self) {
+    fn unload(&self, backend: &dyn FontBackend) {
         cfg_if! {
             if #[cfg(feature = "parallel")] {
                 let iter = self.loaded.par_iter();
@@ -110,15 +130,26 @@ impl FontSource for FontArchiveZip {
                 "Unload font \"{}\" (\"{}\") from zip \"{}\"",
                 f.path, name, self.path
             );
-            f.unload();
+            f.unload(backend);
             info!(
-                "Unloaded extracted font \"{}\" from \"{}\"",
-                name, self.path
+                "Unloaded extracted font \"{}\" [{}] from \"{}\"",
+                name,
+                f.describe(),
+                self.path
             );
         });
     }
 
+    #[cfg(feature = "library")]
+    fn select_members(&mut self, members: HashSet<String>) {
+        self.members = Some(members);
+    }
+
     fn get_path(&self) -> &String {
         &self.path
     }
+
+    fn get_fonts(&self) -> Vec<&FontFile> {
+        self.loaded.iter().map(|(_, f)| f).collect()
+    }
 }
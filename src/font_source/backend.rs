@@ -0,0 +1,15 @@
+use anyhow::Result;
+
+/// 注册字体的后端，所有字体源都通过它来加载和卸载字体
+pub trait FontBackend: Send + Sync {
+    /// 注册字体文件，使其对其他程序可见
+    ///
+    /// 和 AddFontResourceW 一样，path 可以是用 `|` 分隔的组成同一个字体的多个文件，例如 `a.pfm|a.pfb`。
+    fn register(&self, path: &str) -> Result<()>;
+    /// 取消注册字体文件
+    fn unregister(&self, path: &str) -> Result<()>;
+    /// 在一批字体注册或取消注册后通知其他程序字体发生了变化
+    fn notify_changed(&self);
+    /// 获取后端的名称，主要用于日志输出
+    fn name(&self) -> &'static str;
+}
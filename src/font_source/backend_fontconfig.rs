@@ -0,0 +1,367 @@
+use crate::font_source::FontBackend;
+use anyhow::{Context, Result, bail};
+use log::{debug, info, warn};
+use std::{
+    collections::HashMap,
+    env,
+    ffi::CString,
+    fs,
+    os::unix::{ffi::OsStrExt, fs::symlink},
+    path::{Path, PathBuf},
+    sync::{
+        Mutex, OnceLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+/// 会话专用的文件夹和 fontconfig 配置的文件名前缀，后面是进程 ID
+const SESSION_PREFIX: &str = "fontloader-";
+const INCLUDE_PREFIX: &str = "99-fontloader-";
+
+/// 收到信号时需要删除的 fontconfig 配置，信号处理函数中只能使用预先准备好的路径
+static SIGNAL_INCLUDE: OnceLock<CString> = OnceLock::new();
+
+/// 通过 fontconfig 让字体只在当前会话中可见的后端
+///
+/// 注册字体时会把字体链接到会话专用的文件夹，并生成一个把这个文件夹加入字体搜索路径的 fontconfig 配置，
+/// 后端被释放时会删除文件夹和配置。被 Ctrl+C 等信号结束时会删除配置，以免其他程序继续读取它；
+/// 之前异常退出的进程留下的配置和文件夹会在下次创建后端时删除。
+pub struct FontconfigBackend {
+    session_dir: PathBuf,
+    include: PathBuf,
+    linked: Mutex<HashMap<String, Vec<PathBuf>>>,
+    counter: AtomicUsize,
+}
+
+impl FontconfigBackend {
+    /// 在 $XDG_RUNTIME_DIR 中创建会话专用的文件夹，在 $XDG_CONFIG_HOME/fontconfig/conf.d 中创建配置
+    pub fn new() -> Result<Self> {
+        let runtime_dir = env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(env::temp_dir);
+        let config_dir = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+            .context("Unable to find the fontconfig user config directory")?
+            .join("fontconfig")
+            .join("conf.d");
+        let backend = Self::with_dirs(&runtime_dir, &config_dir)?;
+        backend.remove_include_on_signal();
+        Ok(backend)
+    }
+
+    /// 在 runtime_dir 中创建会话专用的文件夹，在 config_dir 中创建配置
+    fn with_dirs(runtime_dir: &Path, config_dir: &Path) -> Result<Self> {
+        remove_stale(runtime_dir, config_dir);
+        let pid = std::process::id();
+        let session_dir = runtime_dir.join(format!("{SESSION_PREFIX}{pid}"));
+        let include = config_dir.join(format!("{INCLUDE_PREFIX}{pid}.conf"));
+
+        fs::create_dir_all(&session_dir)?;
+        fs::create_dir_all(config_dir)?;
+        let session_dir_str = session_dir.to_str().unwrap();
+        debug!(
+            "Create fontconfig include \"{}\" for session dir \"{session_dir_str}\"",
+            include.to_str().unwrap()
+        );
+        fs::write(&include, include_config(session_dir_str))?;
+
+        Ok(Self {
+            session_dir,
+            include,
+            linked: Mutex::new(HashMap::new()),
+            counter: AtomicUsize::new(0),
+        })
+    }
+
+    /// 收到 SIGINT、SIGTERM 和 SIGHUP 时删除 fontconfig 配置，然后按默认的方式结束进程
+    ///
+    /// 信号处理函数中只调用 unlink，会话专用的文件夹留到下次创建后端时删除。
+    fn remove_include_on_signal(&self) {
+        let include = CString::new(self.include.as_os_str().as_bytes()).unwrap();
+        if SIGNAL_INCLUDE.set(include).is_err() {
+            return;
+        }
+        extern "C" fn handler(signal: libc::c_int) {
+            if let Some(include) = SIGNAL_INCLUDE.get() {
+                unsafe { libc::unlink(include.as_ptr()) };
+            }
+            unsafe {
+                libc::signal(signal, libc::SIG_DFL);
+                libc::raise(signal);
+            }
+        }
+        for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+            unsafe {
+                libc::signal(
+                    signal,
+                    handler as extern "C" fn(libc::c_int) as libc::sighandler_t,
+                )
+            };
+        }
+    }
+
+    /// 把一个文件链接到会话专用的文件夹，返回链接的路径
+    fn link(&self, file: &str) -> Result<PathBuf> {
+        let source = fs::canonicalize(file)?;
+        let Some(file_name) = source.file_name() else {
+            bail!("Invalid font path \"{file}\"");
+        };
+        // 加上序号避免不同文件夹中的同名文件冲突
+        let link = self.session_dir.join(format!(
+            "{}-{}",
+            self.counter.fetch_add(1, Ordering::Relaxed),
+            file_name.to_str().unwrap()
+        ));
+        debug!(
+            "Link \"{file}\" to fontconfig session dir as \"{}\"",
+            link.to_str().unwrap()
+        );
+        symlink(&source, &link)
+            .with_context(|| format!("Failed to link \"{file}\" to fontconfig session dir"))?;
+        Ok(link)
+    }
+}
+
+impl FontBackend for FontconfigBackend {
+    fn register(&self, path: &str) -> Result<()> {
+        let mut links = Vec::new();
+        for file in path.split('|') {
+            match self.link(file) {
+                Ok(link) => links.push(link),
+                Err(err) => {
+                    // 组成同一个字体的文件要么全部链接，要么全部不链接
+                    links.iter().for_each(|link| _ = fs::remove_file(link));
+                    return Err(err);
+                }
+            }
+        }
+        self.linked.lock().unwrap().insert(path.to_string(), links);
+        Ok(())
+    }
+
+    fn unregister(&self, path: &str) -> Result<()> {
+        let Some(links) = self.linked.lock().unwrap().remove(path) else {
+            bail!("Font \"{path}\" is not registered to fontconfig session dir");
+        };
+        for link in links {
+            debug!(
+                "Remove \"{}\" from fontconfig session dir",
+                link.to_str().unwrap()
+            );
+            fs::remove_file(&link).with_context(|| {
+                format!("Failed to remove \"{path}\" from fontconfig session dir")
+            })?;
+        }
+        Ok(())
+    }
+
+    /// 不调用 fc-cache，以免每个会话专用的文件夹都在用户的 fontconfig 缓存中留下一条记录
+    ///
+    /// 链接和删除字体会更新文件夹的修改时间，fontconfig 检查配置是否过期时会发现变化并重新扫描这个文件夹。
+    fn notify_changed(&self) {
+        debug!(
+            "Fontconfig session dir \"{}\" changed, applications rescan it on their own",
+            self.session_dir.to_str().unwrap()
+        );
+    }
+
+    fn name(&self) -> &'static str {
+        "fontconfig"
+    }
+}
+
+/// 把 session_dir 加入字体搜索路径的 fontconfig 配置
+fn include_config(session_dir: &str) -> String {
+    format!(
+        concat!(
+            "<?xml version=\"1.0\"?>\n",
+            "<!DOCTYPE fontconfig SYSTEM \"urn:fontconfig:fonts.dtd\">\n",
+            "<fontconfig>\n",
+            "  <dir>{}</dir>\n",
+            "</fontconfig>\n",
+        ),
+        session_dir
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+    )
+}
+
+/// 进程是否仍在运行，没有权限向它发送信号的进程也视为在运行
+fn is_running(pid: i32) -> bool {
+    let result = unsafe { libc::kill(pid, 0) };
+    result == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// 删除已经结束的进程留下的 fontconfig 配置和会话专用的文件夹
+///
+/// 被信号结束的进程已经删除了配置，只会留下文件夹。
+fn remove_stale(runtime_dir: &Path, config_dir: &Path) {
+    for include in stale(config_dir, INCLUDE_PREFIX, ".conf") {
+        info!(
+            "Remove stale fontconfig include \"{}\"",
+            include.to_str().unwrap()
+        );
+        if let Err(err) = fs::remove_file(&include) {
+            warn!(
+                "Failed to remove stale fontconfig include \"{}\": {err}",
+                include.to_str().unwrap()
+            );
+        }
+    }
+    for session_dir in stale(runtime_dir, SESSION_PREFIX, "") {
+        debug!(
+            "Remove stale fontconfig session dir \"{}\"",
+            session_dir.to_str().unwrap()
+        );
+        if let Err(err) = fs::remove_dir_all(&session_dir) {
+            warn!(
+                "Failed to remove stale fontconfig session dir \"{}\": {err}",
+                session_dir.to_str().unwrap()
+            );
+        }
+    }
+}
+
+/// dir 中名为 prefix、进程 ID 和 suffix，并且进程已经结束的文件和文件夹
+fn stale(dir: &Path, prefix: &str, suffix: &str) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|e| e.ok())
+        .filter_map(|entry| {
+            let pid = entry
+                .file_name()
+                .to_str()?
+                .strip_prefix(prefix)?
+                .strip_suffix(suffix)?
+                .parse::<i32>()
+                .ok()?;
+            (pid > 0 && !is_running(pid)).then(|| entry.path())
+        })
+        .collect()
+}
+
+impl Drop for FontconfigBackend {
+    fn drop(&mut self) {
+        debug!(
+            "Remove fontconfig include \"{}\"",
+            self.include.to_str().unwrap()
+        );
+        if let Err(err) = fs::remove_file(&self.include) {
+            warn!(
+                "Failed to remove fontconfig include \"{}\": {err}",
+                self.include.to_str().unwrap()
+            );
+        }
+        if let Err(err) = fs::remove_dir_all(&self.session_dir) {
+            warn!(
+                "Failed to remove fontconfig session dir \"{}\": {err}",
+                self.session_dir.to_str().unwrap()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn links_and_config() {
+        let temp = TempDir::new("fontloader-test").unwrap();
+        let (runtime_dir, config_dir) = (temp.path().join("runtime"), temp.path().join("conf.d"));
+        let fonts = temp.path().join("Fonts");
+        fs::create_dir_all(&fonts).unwrap();
+        for name in ["A.pfm", "A.pfb", "B.ttf"] {
+            fs::write(fonts.join(name), name).unwrap();
+        }
+        let backend = FontconfigBackend::with_dirs(&runtime_dir, &config_dir).unwrap();
+        let session_dir = backend.session_dir.clone();
+        let include = backend.include.clone();
+        assert_eq!(
+            fs::read_to_string(&include).unwrap(),
+            include_config(session_dir.to_str().unwrap())
+        );
+        assert!(include_config("/tmp/a&b").contains("<dir>/tmp/a&amp;b</dir>"));
+
+        let path = |name: &str| fonts.join(name).to_str().unwrap().to_string();
+        let type1 = format!("{}|{}", path("A.pfm"), path("A.pfb"));
+        backend.register(&type1).unwrap();
+        backend.register(&path("B.ttf")).unwrap();
+        let mut links: Vec<(String, PathBuf)> = fs::read_dir(&session_dir)
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                (
+                    entry.file_name().to_str().unwrap().to_string(),
+                    fs::read_link(entry.path()).unwrap(),
+                )
+            })
+            .collect();
+        links.sort();
+        let fonts = fs::canonicalize(&fonts).unwrap();
+        assert_eq!(
+            links,
+            [
+                ("0-A.pfm".to_string(), fonts.join("A.pfm")),
+                ("1-A.pfb".to_string(), fonts.join("A.pfb")),
+                ("2-B.ttf".to_string(), fonts.join("B.ttf")),
+            ]
+        );
+        // 组成同一个字体的文件有一个链接失败时都不会链接
+        assert!(
+            backend
+                .register(&format!("{}|/nonexistent.pfb", path("B.ttf")))
+                .is_err()
+        );
+        assert_eq!(fs::read_dir(&session_dir).unwrap().count(), 3);
+
+        backend.unregister(&type1).unwrap();
+        assert_eq!(fs::read_dir(&session_dir).unwrap().count(), 1);
+        assert!(backend.unregister(&type1).is_err());
+        drop(backend);
+        assert!(!include.exists() && !session_dir.exists());
+    }
+
+    #[test]
+    fn stale_includes() {
+        let temp = TempDir::new("fontloader-test").unwrap();
+        let (runtime_dir, config_dir) = (temp.path().join("runtime"), temp.path().join("conf.d"));
+        // 不存在的进程留下的配置和文件夹会被删除，仍在运行的进程的配置不会被删除
+        let dead = i32::MAX;
+        let alive = std::os::unix::process::parent_id();
+        for pid in [dead as u32, alive] {
+            fs::create_dir_all(runtime_dir.join(format!("{SESSION_PREFIX}{pid}"))).unwrap();
+            fs::create_dir_all(&config_dir).unwrap();
+            fs::write(config_dir.join(format!("{INCLUDE_PREFIX}{pid}.conf")), "").unwrap();
+        }
+        fs::write(config_dir.join("50-user.conf"), "").unwrap();
+        // 被信号结束的进程只留下文件夹
+        let signaled = runtime_dir.join(format!("{SESSION_PREFIX}{}", dead - 1));
+        fs::create_dir_all(&signaled).unwrap();
+        let backend = FontconfigBackend::with_dirs(&runtime_dir, &config_dir).unwrap();
+        assert!(
+            !config_dir
+                .join(format!("{INCLUDE_PREFIX}{dead}.conf"))
+                .exists()
+        );
+        assert!(!runtime_dir.join(format!("{SESSION_PREFIX}{dead}")).exists());
+        assert!(
+            config_dir
+                .join(format!("{INCLUDE_PREFIX}{alive}.conf"))
+                .exists()
+        );
+        assert!(
+            runtime_dir
+                .join(format!("{SESSION_PREFIX}{alive}"))
+                .exists()
+        );
+        assert!(config_dir.join("50-user.conf").exists());
+        assert!(!signaled.exists());
+        drop(backend);
+    }
+}
@@ -0,0 +1,49 @@
+use crate::font_source::FontBackend;
+use anyhow::{Result, bail};
+use log::{debug, warn};
+use windows::Win32::{
+    Foundation::{LPARAM, WPARAM},
+    Graphics::Gdi::{AddFontResourceW, RemoveFontResourceW},
+    UI::WindowsAndMessaging::{HWND_BROADCAST, PostMessageW, WM_FONTCHANGE},
+};
+use windows_strings::HSTRING;
+
+/// 使用 AddFontResourceW 和 RemoveFontResourceW 注册字体的后端
+pub struct GdiBackend;
+
+impl FontBackend for GdiBackend {
+    fn register(&self, path: &str) -> Result<()> {
+        debug!("Call AddFontResourceW for \"{path}\"");
+        unsafe {
+            if AddFontResourceW(&HSTRING::from(path)) == 0 {
+                bail!("Failed to call AddFontResourceW for \"{path}\"")
+            }
+        }
+        Ok(())
+    }
+
+    fn unregister(&self, path: &str) -> Result<()> {
+        debug!("Call RemoveFontResourceW for \"{path}\"");
+        unsafe {
+            if !RemoveFontResourceW(&HSTRING::from(path)).as_bool() {
+                bail!("Failed to call RemoveFontResourceW for \"{path}\"")
+            }
+        }
+        Ok(())
+    }
+
+    fn notify_changed(&self) {
+        debug!("Call PostMessageW WM_FONTCHANGE");
+        unsafe {
+            if let Err(err) =
+                PostMessageW(Some(HWND_BROADCAST), WM_FONTCHANGE, WPARAM(0), LPARAM(0))
+            {
+                warn!("Failed to call PostMessageW WM_FONTCHANGE: {err}");
+            }
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "gdi"
+    }
+}
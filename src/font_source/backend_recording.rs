@@ -0,0 +1,58 @@
+use crate::font_source::FontBackend;
+use anyhow::Result;
+use log::info;
+use std::sync::Mutex;
+
+/// 对后端的一次调用
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BackendCall {
+    Register(String),
+    Unregister(String),
+    NotifyChanged,
+}
+
+/// 只在内存中记录调用而不实际注册字体的后端，可以在任何系统上运行
+#[derive(Default)]
+pub struct RecordingBackend {
+    calls: Mutex<Vec<BackendCall>>,
+}
+
+impl RecordingBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 按调用顺序返回记录的所有调用
+    pub fn calls(&self) -> Vec<BackendCall> {
+        self.calls.lock().unwrap().clone()
+    }
+}
+
+impl FontBackend for RecordingBackend {
+    fn register(&self, path: &str) -> Result<()> {
+        info!("Record register \"{path}\"");
+        self.calls
+            .lock()
+            .unwrap()
+            .push(BackendCall::Register(path.to_string()));
+        Ok(())
+    }
+
+    fn unregister(&self, path: &str) -> Result<()> {
+        info!("Record unregister \"{path}\"");
+        self.calls
+            .lock()
+            .unwrap()
+            .push(BackendCall::Unregister(path.to_string()));
+        Ok(())
+    }
+
+    fn notify_changed(&self) {
+        info!("Record fonts changed notification");
+        self.calls.lock().unwrap().push(BackendCall::NotifyChanged);
+    }
+
+    fn name(&self) -> &'static str {
+        "recording"
+    }
+}
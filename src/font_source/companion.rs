@@ -0,0 +1,229 @@
+use crate::font_source::FontFile;
+use log::{debug, warn};
+use std::{fs, path::Path};
+
+/// 文件名的小写扩展名
+fn extension(name: &str) -> String {
+    Path::new(name)
+        .extension()
+        .map(|ext| ext.to_str().unwrap().to_ascii_lowercase())
+        .unwrap_or_default()
+}
+
+/// 两个文件是否在同一个文件夹中，并且 same 对两者的文件名成立
+fn is_sibling(a: &str, b: &str, same: impl Fn(&Path, &Path) -> bool) -> bool {
+    let (a, b) = (Path::new(a), Path::new(b));
+    let parent = |p: &Path| p.parent().map(|p| p.to_str().unwrap().to_lowercase());
+    parent(a) == parent(b) && same(a, b)
+}
+
+/// 文件名去掉扩展名后是否相同，不区分大小写
+fn same_stem(a: &Path, b: &Path) -> bool {
+    let stem = |p: &Path| p.file_stem().map(|s| s.to_str().unwrap().to_lowercase());
+    stem(a) == stem(b)
+}
+
+/// 读取 FOT 文件引用的 TTF 文件名
+///
+/// FOT 中保存的是 TTF 的路径字符串，这里只取其中的文件名部分。
+fn fot_target(path: &str) -> Option<String> {
+    let data = fs::read(path).ok()?;
+    data.split(|&b| !b.is_ascii_graphic() && b != b' ')
+        .filter_map(|s| std::str::from_utf8(s).ok())
+        .find(|s| s.to_ascii_lowercase().ends_with(".ttf"))
+        .and_then(|s| s.rsplit(['\\', '/', ':']).next())
+        .map(|s| s.to_lowercase())
+}
+
+/// 把组成同一个字体的文件合并为一个字体文件
+///
+/// files 中是文件在源中的名称和字体文件，名称用于查找同一个文件夹中的文件和输出日志。
+/// Type 1 字体的 PFM 和同名的 PFB 会被合并，FOT 会和它引用的 TTF 合并，合并后的名称用 `|` 连接。
+/// 缺少另一半时会输出警告，只有 PFM 或 FOT 时无法加载会被跳过，只有 PFB 时仍然尝试加载。
+pub fn group_companions(files: Vec<(String, FontFile)>, source: &str) -> Vec<(String, FontFile)> {
+    let mut files: Vec<Option<(String, FontFile)>> = files.into_iter().map(Some).collect();
+    let mut grouped = Vec::new();
+    let find = |files: &[Option<(String, FontFile)>], pred: &dyn Fn(&str) -> bool| {
+        files
+            .iter()
+            .position(|f| f.as_ref().is_some_and(|(name, _)| pred(name)))
+    };
+
+    for i in 0..files.len() {
+        let Some((name, _)) = &files[i] else {
+            continue;
+        };
+        let name = name.clone();
+        match extension(&name).as_str() {
+            "pfm" => {
+                let pfb = find(&files, &|other| {
+                    extension(other) == "pfb" && is_sibling(&name, other, same_stem)
+                });
+                let (_, mut pfm) = files[i].take().unwrap();
+                let Some(pfb) = pfb else {
+                    warn!(
+                        "Skipped Type 1 font metrics \"{name}\" from \"{source}\" without the matching PFB file"
+                    );
+                    continue;
+                };
+                let (pfb_name, pfb) = files[pfb].take().unwrap();
+                debug!("Paired Type 1 font \"{name}\" with \"{pfb_name}\" from \"{source}\"");
+                pfm.companions.push(pfb.path);
+                grouped.push((format!("{name}|{pfb_name}"), pfm));
+            }
+            "fot" => {
+                let (_, fot) = files[i].take().unwrap();
+                let target = fot_target(&fot.path);
+                let is_target = |a: &Path, b: &Path| {
+                    let file_name = b.file_name().unwrap().to_str().unwrap().to_lowercase();
+                    target.as_ref().is_some_and(|t| *t == file_name) || same_stem(a, b)
+                };
+                let ttf = find(&files, &|other| {
+                    extension(other) == "ttf" && is_sibling(&name, other, is_target)
+                });
+                let Some(ttf) = ttf else {
+                    warn!(
+                        "Skipped font resource \"{name}\" from \"{source}\" without the TrueType font {} it refers to",
+                        target.map_or("file".to_string(), |t| format!("\"{t}\""))
+                    );
+                    continue;
+                };
+                let (ttf_name, mut ttf) = files[ttf].take().unwrap();
+                debug!("Paired font resource \"{name}\" with \"{ttf_name}\" from \"{source}\"");
+                ttf.companions.push(fot.path);
+                grouped.push((format!("{name}|{ttf_name}"), ttf));
+            }
+            _ => {}
+        }
+    }
+
+    for (name, file) in files.into_iter().flatten() {
+        if extension(&name) == "pfb" {
+            warn!(
+                "Type 1 font \"{name}\" from \"{source}\" has no matching PFM file and may fail to load"
+            );
+        }
+        grouped.push((name, file));
+    }
+    grouped
+}
+
+/// 为单独输入的 PFM/PFB/FOT 文件在同一个文件夹中查找组成同一个字体的文件
+///
+/// 不是这几种文件时原样返回，缺少另一半而无法加载时返回 None。
+pub fn with_companions(path: String) -> Option<FontFile> {
+    if !matches!(extension(&path).as_str(), "pfm" | "pfb" | "fot") {
+        return Some(FontFile::new(path));
+    }
+    let p = Path::new(&path);
+    let parent = match p.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut files = vec![(path.clone(), FontFile::new(path.clone()))];
+    for entry in fs::read_dir(parent).into_iter().flatten().flatten() {
+        let sibling = p.with_file_name(entry.file_name());
+        let sibling = sibling.to_str().unwrap().to_string();
+        // FOT 引用的 TTF 可能和它不同名，其他文件只需要同名的另一半
+        let is_companion = match extension(&sibling).as_str() {
+            "pfm" | "pfb" => same_stem(p, Path::new(&sibling)),
+            "ttf" => extension(&path) == "fot",
+            _ => false,
+        };
+        if sibling != path && is_companion {
+            files.push((sibling.clone(), FontFile::new(sibling)));
+        }
+    }
+    group_companions(files, parent.to_str().unwrap())
+        .into_iter()
+        .map(|(_, file)| file)
+        .find(|file| file.path == path || file.companions.contains(&path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn group(names: &[&str], paths: &[&str]) -> Vec<(String, String)> {
+        let files = names
+            .iter()
+            .zip(paths)
+            .map(|(name, path)| (name.to_string(), FontFile::new(path.to_string())))
+            .collect();
+        group_companions(files, "source")
+            .into_iter()
+            .map(|(name, file)| (name, file.resource()))
+            .collect()
+    }
+
+    #[test]
+    fn type1_pairs() {
+        let names = [
+            "Fonts/Foo.PFM",
+            "Fonts/Orphan.pfm",
+            "fonts/foo.pfb",
+            "Fonts/Lone.pfb",
+            "Other/Bar.pfm",
+            "Fonts/Bar.pfb",
+        ];
+        let paths = ["1", "2", "3", "4", "5", "6"];
+        assert_eq!(
+            group(&names, &paths),
+            [
+                ("Fonts/Foo.PFM|fonts/foo.pfb".to_string(), "1|3".to_string()),
+                ("Fonts/Lone.pfb".to_string(), "4".to_string()),
+                ("Fonts/Bar.pfb".to_string(), "6".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn fot_pairs() {
+        let temp = TempDir::new("fontloader-test").unwrap();
+        let fot = temp.path().join("Narrow.fot");
+        fs::write(&fot, b"FONTRES\0\0C:\\WINDOWS\\SYSTEM\\ARIALN.TTF\0").unwrap();
+        let fot_path = fot.to_str().unwrap();
+        let names = [
+            "Fonts/Narrow.fot",
+            "Fonts/Same.FOT",
+            "Fonts/Missing.fot",
+            "Fonts/same.ttf",
+            "Fonts/ArialN.ttf",
+        ];
+        let paths = [fot_path, "missing.fot", "missing.fot", "3", "4"];
+        let grouped = group(&names, &paths);
+        // FOT 不参与注册，只注册 TTF
+        assert_eq!(
+            grouped,
+            [
+                (
+                    "Fonts/Narrow.fot|Fonts/ArialN.ttf".to_string(),
+                    "4".to_string()
+                ),
+                ("Fonts/Same.FOT|Fonts/same.ttf".to_string(), "3".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn companions_of_single_file() {
+        let temp = TempDir::new("fontloader-test").unwrap();
+        let dir = temp.path();
+        for name in ["Foo.PFM", "foo.pfb", "Orphan.pfm", "Plain.ttf"] {
+            fs::write(dir.join(name), name).unwrap();
+        }
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+        let pfm = with_companions(path("foo.pfb")).unwrap();
+        assert_eq!(pfm.path, path("Foo.PFM"));
+        assert_eq!(pfm.companions, [path("foo.pfb")]);
+        assert_eq!(
+            with_companions(path("Foo.PFM")).unwrap().path,
+            path("Foo.PFM")
+        );
+        assert!(with_companions(path("Orphan.pfm")).is_none());
+        let plain = with_companions(path("Plain.ttf")).unwrap();
+        assert!(plain.companions.is_empty());
+    }
+}
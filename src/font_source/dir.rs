@@ -1,69 +1,183 @@
-use crate::font_source::{FontFile, FontSource, path_is_font};
+use crate::font_source::{
+    FontBackend, FontFile, FontSource, Session, file_font_kind, group_companions, path_is_subtitle,
+    require_subtitle, sniff::ContentKind,
+};
+#[cfg(feature = "woff")]
+use crate::font_source::{TEMPDIR_PREFIX, decode_web_font};
 use anyhow::Result;
 use cfg_if::cfg_if;
 use log::{debug, info, warn};
 #[cfg(feature = "parallel")]
-use rayon::iter::{IntoParallelRefIterator, ParallelBridge, ParallelExtend, ParallelIterator};
+use rayon::iter::{
+    IntoParallelIterator, IntoParallelRefIterator, ParallelBridge, ParallelIterator,
+};
+use std::path::Path;
+#[cfg(feature = "woff")]
+use std::{fs, io, path::PathBuf, sync::Mutex};
+#[cfg(feature = "woff")]
+use tempdir::TempDir;
 use walkdir::{DirEntry, WalkDir};
 
+/// 遍历文件夹和所有子文件夹中的文件，跳过无法读取的项目
+pub fn walk_files(path: &str) -> impl Iterator<Item = DirEntry> + Send {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+}
+
 /// 包含字体的文件夹
 pub struct FontDir {
     path: String,
-    loaded: Vec<FontFile>,
+    /// 解码 WOFF/WOFF2 字体得到的字体文件所在的临时文件夹，解码第一个 WOFF/WOFF2 字体时创建
+    #[cfg(feature = "woff")]
+    decoded: Mutex<Option<TempDir>>,
+    /// prepare 选出的需要加载的字体
+    found: Vec<(String, FontFile)>,
+    loaded: Vec<(String, FontFile)>,
 }
 
 impl FontDir {
     pub fn new(path: String) -> Self {
         Self {
             path,
+            #[cfg(feature = "woff")]
+            decoded: Mutex::new(None),
+            found: Vec::new(),
             loaded: Vec::new(),
         }
     }
+
+    /// 解码 WOFF/WOFF2 字体的临时文件夹，还没有创建时创建
+    #[cfg(feature = "woff")]
+    fn decoded_dir(&self) -> io::Result<PathBuf> {
+        let mut decoded = self.decoded.lock().unwrap();
+        if decoded.is_none() {
+            *decoded = Some(TempDir::new(TEMPDIR_PREFIX)?);
+        }
+        Ok(decoded.as_ref().unwrap().path().to_path_buf())
+    }
+
+    /// 得到需要加载的字体文件，WOFF/WOFF2 字体会先被解码到临时文件夹
+    #[cfg_attr(not(feature = "woff"), allow(unused_variables))]
+    fn font_file(&self, path: &Path, kind: ContentKind) -> Option<FontFile> {
+        let path_str = path.to_str().unwrap().to_string();
+        #[cfg(feature = "woff")]
+        if matches!(kind, ContentKind::Woff | ContentKind::Woff2) {
+            // 直接加载单个文件时 path 和 self.path 相同
+            let relative = path
+                .strip_prefix(&self.path)
+                .ok()
+                .filter(|p| !p.as_os_str().is_empty())
+                .unwrap_or_else(|| Path::new(path.file_name().unwrap()));
+            return match self
+                .decoded_dir()
+                .and_then(|dir| Ok((dir, fs::read(path)?)))
+                .map_err(anyhow::Error::from)
+                .and_then(|(dir, data)| decode_web_font(path, &data, &dir.join(relative)))
+            {
+                Ok(decoded) => Some(FontFile::decoded(
+                    decoded.to_str().unwrap().to_string(),
+                    path_str,
+                )),
+                Err(err) => {
+                    warn!(
+                        "Skipped font \"{}\" from dir \"{}\" failed to decode: {}",
+                        path_str, self.path, err
+                    );
+                    None
+                }
+            };
+        }
+        Some(FontFile::new(path_str))
+    }
 }
 
 impl FontSource for FontDir {
-    fn load(&mut self) -> Result<()> {
+    /// 检查、收集和嵌入字体时，文件夹中直接包含的字幕都需要加载，例如一季动画的所有字幕
+    fn require_fonts(&mut self, session: &Session) -> Result<()> {
+        if !session.folder_subtitles {
+            return Ok(());
+        }
+        let subtitles = WalkDir::new(&self.path)
+            .max_depth(1)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file() && path_is_subtitle(e.path()));
+        for entry in subtitles {
+            if let Err(err) = require_subtitle(entry.path(), session) {
+                warn!(
+                    "Unable to read subtitle \"{}\" from dir \"{}\": {err}",
+                    entry.path().to_str().unwrap(),
+                    self.path
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn prepare(&mut self, session: &Session) -> Result<()> {
         debug!("Walking dir \"{}\"", self.path);
 
-        let entry_op = |entry: DirEntry| -> Option<FontFile> {
+        let entry_op = |entry: DirEntry| -> Option<(String, FontFile)> {
             let path = entry.path();
-            if path_is_font(path) {
-                let path_str = path.to_str().unwrap().to_string();
-                debug!("Found font \"{path_str}\" from dir \"{}\"", self.path);
-                let mut f = FontFile::new(path_str.clone());
-                match f.load() {
-                    Ok(_) => {
-                        info!(
-                            "Found font \"{path_str}\" from \"{}\" and loaded",
-                            self.path
-                        );
-                        return Some(f);
-                    }
-                    Err(err) => {
-                        warn!(
-                            "Skipped font \"{}\" from dir \"{}\" failed to load: {}",
-                            path_str, self.path, err
-                        );
-                    }
+            let kind = file_font_kind(path)?;
+            let path_str = path.to_str().unwrap().to_string();
+            debug!("Found font \"{path_str}\" from dir \"{}\"", self.path);
+            Some((path_str, self.font_file(path, kind)?))
+        };
+
+        // 先找到所有字体，合并 Type 1 等由多个文件组成的字体后选出需要的字体
+        let iter = walk_files(&self.path);
+        cfg_if! {
+            if #[cfg(feature = "parallel")] {
+                let found = iter.par_bridge().filter_map(entry_op).collect();
+            } else {
+                let found = iter.filter_map(entry_op).collect();
+            }
+        }
+        let found = group_companions(found, &self.path);
+        self.found = session.select_fonts(found, &self.path);
+
+        Ok(())
+    }
+
+    fn load(&mut self, session: &Session) -> Result<()> {
+        let found_op = |(name, f): (String, FontFile)| -> Option<(String, FontFile)> {
+            match session.register(&name, &self.path, &f) {
+                Ok(false) => None,
+                Ok(true) => {
+                    info!(
+                        "Found font \"{name}\" [{}] from \"{}\" and loaded",
+                        f.describe(),
+                        self.path
+                    );
+                    Some((name, f))
+                }
+                Err(err) => {
+                    warn!(
+                        "Skipped font \"{}\" from dir \"{}\" failed to load: {}",
+                        name, self.path, err
+                    );
+                    None
                 }
             }
-            None
         };
 
-        let iter = WalkDir::new(&self.path).into_iter().filter_map(|e| e.ok());
+        let found = std::mem::take(&mut self.found);
         cfg_if! {
             if #[cfg(feature = "parallel")] {
-                let iter = iter.par_bridge();
-                self.loaded.par_extend(iter.filter_map(entry_op));
+                let loaded: Vec<_> = found.into_par_iter().filter_map(found_op).collect();
             } else {
-                self.loaded.extend(iter.filter_map(entry_op));
+                let loaded: Vec<_> = found.into_iter().filter_map(found_op).collect();
             }
         }
+        self.loaded.extend(loaded);
 
         Ok(())
     }
 
-    fn unload(&self) {
+    fn unload(&self, backend: &dyn FontBackend) {
         cfg_if! {
             if #[cfg(feature = "parallel")] {
                 let iter = self.loaded.par_iter();
@@ -71,13 +185,22 @@ impl FontSource for FontDir {
                 let iter = self.loaded.iter();
             }
         }
-        iter.for_each(|f| {
-            f.unload();
-            info!("Unloaded font \"{}\" from dir \"{}\"", f.path, self.path);
+        iter.for_each(|(name, f)| {
+            f.unload(backend);
+            info!(
+                "Unloaded font \"{}\" [{}] from dir \"{}\"",
+                name,
+                f.describe(),
+                self.path
+            );
         });
     }
 
     fn get_path(&self) -> &String {
         &self.path
     }
+
+    fn get_fonts(&self) -> Vec<&FontFile> {
+        self.loaded.iter().map(|(_, f)| f).collect()
+    }
 }
@@ -1,42 +1,275 @@
-use crate::font_source::FontSource;
-use anyhow::{Result, bail};
+use crate::{
+    font_source::{
+        FontBackend, FontSource, Session,
+        sniff::{ContentKind, sniff},
+    },
+    sfnt::{self, Face},
+};
+use anyhow::{Result, anyhow, bail};
 use log::{debug, warn};
-use windows::Win32::Graphics::Gdi::{AddFontResourceW, RemoveFontResourceW};
-use windows_strings::HSTRING;
+use std::{fs, io};
+use xxhash_rust::xxh3::Xxh3;
 
 /// 一个字体文件
 pub struct FontFile {
     pub path: String,
+    /// 解码得到这个字体的 WOFF/WOFF2 文件
+    pub origin: Option<String>,
+    /// 和 path 组成同一个字体的其他文件，例如 Type 1 字体的 PFB 和引用了 TTF 的 FOT
+    pub companions: Vec<String>,
+    /// 直接输入的字体和已加载的字体内容相同或者字幕不需要而没有加载
+    skipped: bool,
+    /// 从 name 表中读取了名称的字体，字体集合中会有多个，无法读取时为空
+    pub faces: Vec<Face>,
 }
 
 impl FontFile {
     pub fn new(path: String) -> Self {
-        Self { path }
+        Self {
+            path,
+            origin: None,
+            companions: Vec::new(),
+            skipped: false,
+            faces: Vec::new(),
+        }
+    }
+
+    /// 从 origin 解码得到的字体文件
+    #[cfg(feature = "woff")]
+    pub fn decoded(path: String, origin: String) -> Self {
+        Self {
+            path,
+            origin: Some(origin),
+            companions: Vec::new(),
+            skipped: false,
+            faces: Vec::new(),
+        }
+    }
+
+    /// 字体文件的原始路径，对于解码得到的字体是解码前的路径
+    pub fn original_path(&self) -> &String {
+        self.origin.as_ref().unwrap_or(&self.path)
+    }
+
+    /// 注册字体时使用的路径，Type 1 字体是用 `|` 分隔的 PFM 和 PFB
+    ///
+    /// FOT 只是指向 TTF 的资源文件，而且引用的往往是原来所在的电脑上的路径，所以只注册 TTF。
+    pub fn resource(&self) -> String {
+        let mut files = vec![self.path.as_str()];
+        files.extend(
+            self.companions
+                .iter()
+                .map(|c| c.as_str())
+                .filter(|c| !c.to_ascii_lowercase().ends_with(".fot")),
+        );
+        files.join("|")
+    }
+
+    /// 组成这个字体的所有文件的内容哈希
+    pub fn content_hash(&self) -> io::Result<u128> {
+        let mut hasher = Xxh3::new();
+        for file in std::iter::once(&self.path).chain(&self.companions) {
+            hasher.update(&fs::read(file)?);
+        }
+        Ok(hasher.digest128())
+    }
+
+    /// 通过后端注册字体，需要先调用 inspect 检查字体
+    pub fn register(&self, backend: &dyn FontBackend) -> Result<()> {
+        backend.register(&self.resource())
+    }
+
+    /// 加载前检查字体的结构并读取名称和版本
+    ///
+    /// 结构有问题的 TrueType/OpenType 字体会返回错误，读取名称失败时只输出日志。
+    pub fn inspect(&mut self) -> Result<()> {
+        self.read_faces()
+            .map_err(|err| anyhow!("invalid font file: {err}"))
+    }
+
+    fn read_faces(&mut self) -> Result<()> {
+        let data = fs::read(&self.path)?;
+        if !matches!(sniff(&data), ContentKind::Sfnt | ContentKind::Collection) {
+            // Type 1 等其他格式的字体交给系统处理
+            return Ok(());
+        }
+        let checked = sfnt::validate(&data)?;
+        let count = checked.len();
+        let mut valid = Vec::with_capacity(count);
+        let mut errors = Vec::new();
+        for (index, result) in checked.into_iter().enumerate() {
+            match result {
+                Ok(issues) => {
+                    for issue in issues {
+                        if count > 1 {
+                            warn!(
+                                "Face #{index} of {count} in \"{}\" has {issue}",
+                                self.original_path()
+                            );
+                        } else {
+                            warn!("Font \"{}\" has {issue}", self.original_path());
+                        }
+                    }
+                    valid.push(index);
+                }
+                Err(err) if count > 1 => errors.push((index, err)),
+                Err(err) => return Err(err),
+            }
+        }
+        if valid.is_empty() {
+            let errors: Vec<String> = errors
+                .into_iter()
+                .map(|(index, err)| format!("face #{index}: {err}"))
+                .collect();
+            bail!("{}", errors.join("; "));
+        }
+        for (index, err) in errors {
+            warn!(
+                "Rejected face #{index} of {count} in \"{}\": {err}",
+                self.original_path()
+            );
+        }
+        let faces = match sfnt::read_faces(&data) {
+            Ok(faces) => faces,
+            Err(err) => {
+                debug!(
+                    "Unable to read names from \"{}\": {err}",
+                    self.original_path()
+                );
+                return Ok(());
+            }
+        };
+        let count = faces.len();
+        for (index, face) in faces.into_iter().enumerate() {
+            match face {
+                Ok(face) if valid.contains(&face.index) => self.faces.push(face),
+                Ok(_) => {}
+                Err(err) if count > 1 => warn!(
+                    "Unable to read names of face #{index} of {count} from \"{}\": {err}",
+                    self.original_path()
+                ),
+                Err(err) => debug!(
+                    "Unable to read names from \"{}\": {err}",
+                    self.original_path()
+                ),
+            }
+        }
+        Ok(())
+    }
+
+    /// 字体的名称，主要用于日志输出
+    pub fn describe(&self) -> String {
+        if self.faces.is_empty() {
+            "unknown name".to_string()
+        } else {
+            self.faces
+                .iter()
+                .map(|face| {
+                    if self.faces.len() > 1 {
+                        format!("#{} {}", face.index, face.names)
+                    } else {
+                        face.names.to_string()
+                    }
+                })
+                .collect::<Vec<String>>()
+                .join("; ")
+        }
     }
 }
 
 impl FontSource for FontFile {
-    fn load(&mut self) -> Result<()> {
-        debug!("Call AddFontResourceW for \"{}\"", self.path);
-        unsafe {
-            if AddFontResourceW(&HSTRING::from(&self.path)) == 0 {
-                bail!("Failed to call AddFontResourceW for \"{}\"", self.path)
-            } else {
-                Ok(())
-            }
+    fn prepare(&mut self, session: &Session) -> Result<()> {
+        self.inspect()?;
+        if !session.is_required(&self.path, &self.path, self) {
+            debug!(
+                "Skipped font \"{}\" which is not required by subtitles",
+                self.path
+            );
+            self.skipped = true;
+            return Ok(());
         }
+        session.offer(&self.path, &self.path, self);
+        Ok(())
     }
 
-    fn unload(&self) {
-        debug!("Call RemoveFontResourceW for \"{}\"", self.path);
-        unsafe {
-            if !RemoveFontResourceW(&HSTRING::from(&self.path)).as_bool() {
-                warn!("Failed to call RemoveFontResourceW for \"{}\"", self.path);
-            }
+    fn load(&mut self, session: &Session) -> Result<()> {
+        if !self.skipped && !session.register(&self.path, &self.path, self)? {
+            self.skipped = true;
+        }
+        Ok(())
+    }
+
+    fn unload(&self, backend: &dyn FontBackend) {
+        if self.skipped {
+            return;
+        }
+        if let Err(err) = backend.unregister(&self.resource()) {
+            warn!("{err}");
         }
     }
 
     fn get_path(&self) -> &String {
         &self.path
     }
+
+    fn get_fonts(&self) -> Vec<&FontFile> {
+        if self.skipped { Vec::new() } else { vec![self] }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sfnt::{Font, Table, build_collection, test_font};
+    use tempdir::TempDir;
+
+    /// 读取单个字体的所有表，broken 时把 loca 中最后一个字形的结尾改到 glyf 表外
+    fn tables(data: &[u8], broken: bool) -> Vec<Table> {
+        let font = Font::parse(data, 0).unwrap();
+        let mut tables: Vec<Table> = font
+            .tables
+            .iter()
+            .map(|record| Table {
+                tag: record.tag,
+                data: font.table(&record.tag).unwrap().unwrap().to_vec(),
+            })
+            .collect();
+        if broken {
+            let loca = tables.iter_mut().find(|t| &t.tag == b"loca").unwrap();
+            *loca.data.last_mut().unwrap() = 100;
+        }
+        tables
+    }
+
+    /// 把字体集合写入临时文件后检查
+    fn inspect(name: &str, faces: &[(&str, bool)]) -> (FontFile, Result<()>) {
+        let mut all = Vec::new();
+        let mut fonts = Vec::new();
+        for (family, broken) in faces {
+            let start = all.len();
+            all.extend(tables(&test_font(family, "Regular", 400, "a"), *broken));
+            fonts.push((0x00010000, (start..all.len()).collect()));
+        }
+        let temp = TempDir::new("fontloader-test").unwrap();
+        let path = temp.path().join(format!("{name}.ttc"));
+        fs::write(&path, build_collection(&fonts, &all)).unwrap();
+        let mut file = FontFile::new(path.to_string_lossy().into_owned());
+        let result = file.inspect();
+        (file, result)
+    }
+
+    #[test]
+    fn collection_with_broken_face() {
+        let (file, result) = inspect("partial", &[("Good", false), ("Broken", true)]);
+        result.unwrap();
+        assert_eq!(file.faces.len(), 1);
+        assert_eq!(file.faces[0].index, 0);
+        assert_eq!(file.faces[0].names.family.as_deref(), Some("Good"));
+
+        let (file, result) = inspect("broken", &[("Broken", true), ("Also Broken", true)]);
+        let err = result.unwrap_err().to_string();
+        assert!(err.starts_with("invalid font file: face #0: truncated glyf table"));
+        assert!(err.contains("; face #1: truncated glyf table"));
+        assert!(file.faces.is_empty());
+    }
 }
@@ -0,0 +1,342 @@
+use crate::{
+    sfnt::{
+        FontNames, FontStyle, NAME_ID_FAMILY, NAME_ID_FULL_NAME, NAME_ID_TYPOGRAPHIC_FAMILY,
+        WIDTH_NORMAL,
+    },
+    subtitle::FontUsage,
+};
+use std::collections::HashMap;
+
+/// LOGFONT 中 lfFaceName 的长度 LF_FACESIZE 去掉结尾的 NUL，GDI 只比较名称的前 31 个 UTF-16 字符
+const FACE_NAME_LEN: usize = 31;
+
+/// 名称是通过哪种记录匹配的
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MatchKind {
+    /// name ID 1 的家族名称
+    Family,
+    /// name ID 4 的全名
+    FullName,
+    /// name ID 16 的 typographic family，GDI 不使用这个名称
+    TypographicFamily,
+}
+
+impl MatchKind {
+    /// GDI 和 VSFilter 是否能通过这种名称找到字体
+    pub fn is_gdi(&self) -> bool {
+        !matches!(self, Self::TypographicFamily)
+    }
+}
+
+/// 和 GDI 一样比较名称时使用的键
+///
+/// 去掉竖排字体名称开头的 @，截断为 31 个 UTF-16 字符，再转换为小写。
+pub fn match_key(name: &str) -> String {
+    let name = name.strip_prefix('@').unwrap_or(name);
+    let units: Vec<u16> = name.encode_utf16().take(FACE_NAME_LEN).collect();
+    String::from_utf16_lossy(&units).to_lowercase()
+}
+
+/// 字体可以被匹配的所有名称的键，包括所有语言的记录
+///
+/// GDI 只读取 Windows 平台的记录，只有 Mac 平台记录的字体才使用其他平台的记录。
+pub fn name_keys(names: &FontNames) -> Vec<(String, MatchKind)> {
+    let windows = names.records.iter().any(|r| r.platform_id == 3);
+    let mut keys: Vec<(String, MatchKind)> = names
+        .records
+        .iter()
+        .filter(|r| !windows || r.platform_id == 3)
+        .filter_map(|r| {
+            let kind = match r.name_id {
+                NAME_ID_FAMILY => MatchKind::Family,
+                NAME_ID_FULL_NAME => MatchKind::FullName,
+                NAME_ID_TYPOGRAPHIC_FAMILY => MatchKind::TypographicFamily,
+                _ => return None,
+            };
+            Some((match_key(&r.value), kind))
+        })
+        .collect();
+    // 同一个名称有多种记录时只保留 GDI 优先使用的那种
+    keys.sort();
+    keys.dedup_by(|a, b| a.0 == b.0);
+    keys
+}
+
+/// 请求的字重比字体的字重大这么多时，渲染器会模拟加粗，和 libass 的判断相同
+const FAKE_BOLD_THRESHOLD: u16 = 150;
+
+/// 按样式从同名的字体中选出的字体，以及渲染器需要模拟的粗体和斜体
+#[derive(Debug)]
+pub struct StyleMatch<'a, T> {
+    pub id: &'a T,
+    pub fake_bold: bool,
+    pub fake_italic: bool,
+}
+
+/// 根据名称查找字体的索引，id 是调用者用来区分字体的值
+pub struct FontMatcher<T> {
+    names: HashMap<String, Vec<(T, MatchKind)>>,
+}
+
+impl<T> Default for FontMatcher<T> {
+    fn default() -> Self {
+        Self {
+            names: HashMap::new(),
+        }
+    }
+}
+
+impl<T: Clone> FontMatcher<T> {
+    /// 添加一个字体的名称
+    pub fn add(&mut self, id: T, names: &FontNames) {
+        for (key, kind) in name_keys(names) {
+            self.names.entry(key).or_default().push((id.clone(), kind));
+        }
+    }
+
+    /// 查找名称对应的字体，包括只有 typographic family 匹配的字体
+    pub fn find(&self, name: &str) -> &[(T, MatchKind)] {
+        self.names.get(&match_key(name)).map_or(&[], |v| v)
+    }
+
+    /// 查找 GDI 能够找到的字体
+    pub fn find_gdi(&self, name: &str) -> impl Iterator<Item = &T> {
+        self.find(name)
+            .iter()
+            .filter(|(_, kind)| kind.is_gdi())
+            .map(|(id, _)| id)
+    }
+
+    /// 和 GDI 一样从 GDI 能够找到的同名字体中选出最接近 usage 的样式的字体，style 返回每个字体的样式
+    ///
+    /// 优先选择斜体和请求相同的字体，再选择字重最接近的字体，距离相同时请求粗体选择较粗的字体，
+    /// 否则选择较细的字体，最后选择宽度最接近正常宽度的字体。
+    /// 请求斜体但是只有正体时会模拟斜体，反过来则直接使用斜体，不会变为正体。
+    pub fn find_style(
+        &self,
+        usage: &FontUsage,
+        style: impl Fn(&T) -> FontStyle,
+    ) -> Option<StyleMatch<'_, T>> {
+        let (id, style) = self
+            .find_gdi(&usage.family)
+            .map(|id| (id, style(id)))
+            .min_by_key(|(_, style)| {
+                let weight = style.weight as i32;
+                (
+                    style.italic != usage.italic,
+                    weight.abs_diff(usage.weight as i32),
+                    if usage.weight >= 600 { -weight } else { weight },
+                    style.width.abs_diff(WIDTH_NORMAL),
+                )
+            })?;
+        Some(StyleMatch {
+            id,
+            fake_bold: usage.weight > style.weight + FAKE_BOLD_THRESHOLD,
+            fake_italic: usage.italic && !style.italic,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sfnt::NameRecord;
+
+    /// (platform_id, language_id, name_id, value)
+    type Record = (u16, u16, u16, &'static str);
+
+    fn names(records: &[Record]) -> FontNames {
+        FontNames {
+            records: records
+                .iter()
+                .map(|&(platform_id, language_id, name_id, value)| NameRecord {
+                    platform_id,
+                    language_id,
+                    name_id,
+                    value: value.to_string(),
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// 思源黑体 Light 的名称，legacy family 包含字重，typographic family 不包含
+    const SOURCE_HAN_LIGHT: &[Record] = &[
+        (3, 0x0409, 1, "Source Han Sans SC Light"),
+        (3, 0x0409, 4, "Source Han Sans SC Light"),
+        (3, 0x0409, 16, "Source Han Sans SC"),
+        (3, 0x0804, 1, "思源黑体 Light"),
+        (3, 0x0804, 4, "思源黑体 Light"),
+        (3, 0x0804, 16, "思源黑体"),
+    ];
+
+    /// 家族名称超过 31 个字符的字体
+    const LONG_NAME: &[Record] = &[
+        (3, 0x0409, 1, "FOT-Tsukuardgothic Std E Extra Long"),
+        (3, 0x0409, 4, "FOT-Tsukuardgothic Std E Extra Long Regular"),
+    ];
+
+    /// 只有 Mac 平台记录的字体
+    const MAC_ONLY: &[Record] = &[(1, 0, 1, "Old Mac Font"), (1, 0, 4, "Old Mac Font Bold")];
+
+    /// Windows 平台的记录和 Mac 平台的名称不同
+    const MIXED_PLATFORM: &[Record] = &[
+        (1, 0, 1, "Mac Name"),
+        (3, 0x0409, 1, "Windows Name"),
+        (3, 0x0411, 1, "日本語名"),
+    ];
+
+    #[test]
+    fn gdi_matching() {
+        let cases: &[(&[Record], &str, Option<MatchKind>)] = &[
+            (
+                SOURCE_HAN_LIGHT,
+                "Source Han Sans SC Light",
+                Some(MatchKind::Family),
+            ),
+            (
+                SOURCE_HAN_LIGHT,
+                "source han sans sc light",
+                Some(MatchKind::Family),
+            ),
+            (SOURCE_HAN_LIGHT, "思源黑体 Light", Some(MatchKind::Family)),
+            (SOURCE_HAN_LIGHT, "@思源黑体 Light", Some(MatchKind::Family)),
+            (SOURCE_HAN_LIGHT, "@@思源黑体 Light", None),
+            (
+                SOURCE_HAN_LIGHT,
+                "Source Han Sans SC",
+                Some(MatchKind::TypographicFamily),
+            ),
+            (
+                SOURCE_HAN_LIGHT,
+                "思源黑体",
+                Some(MatchKind::TypographicFamily),
+            ),
+            (SOURCE_HAN_LIGHT, "Source Han Sans", None),
+            (SOURCE_HAN_LIGHT, " Source Han Sans SC Light", None),
+            (
+                LONG_NAME,
+                "FOT-Tsukuardgothic Std E Extra Long",
+                Some(MatchKind::Family),
+            ),
+            (
+                LONG_NAME,
+                "FOT-Tsukuardgothic Std E Extra ",
+                Some(MatchKind::Family),
+            ),
+            (
+                LONG_NAME,
+                "FOT-Tsukuardgothic Std E Extra Short",
+                Some(MatchKind::Family),
+            ),
+            (LONG_NAME, "FOT-Tsukuardgothic Std E Extra", None),
+            (
+                LONG_NAME,
+                "@FOT-Tsukuardgothic Std E Extra Long",
+                Some(MatchKind::Family),
+            ),
+            (MAC_ONLY, "Old Mac Font", Some(MatchKind::Family)),
+            (MAC_ONLY, "Old Mac Font Bold", Some(MatchKind::FullName)),
+            (MIXED_PLATFORM, "Windows Name", Some(MatchKind::Family)),
+            (MIXED_PLATFORM, "日本語名", Some(MatchKind::Family)),
+            (MIXED_PLATFORM, "Mac Name", None),
+        ];
+        for (i, &(records, query, expected)) in cases.iter().enumerate() {
+            let mut matcher = FontMatcher::default();
+            matcher.add(i, &names(records));
+            let found = matcher.find(query).first().map(|&(_, kind)| kind);
+            assert_eq!(found, expected, "case #{i}: {query}");
+            assert_eq!(
+                matcher.find_gdi(query).next().is_some(),
+                expected.is_some_and(|kind| kind.is_gdi()),
+                "case #{i}: {query}"
+            );
+        }
+    }
+
+    #[test]
+    fn family_preferred_over_full_name() {
+        let records = [(3, 0x0409, 1, "Same"), (3, 0x0409, 4, "Same")];
+        assert_eq!(
+            name_keys(&names(&records)),
+            vec![("same".to_string(), MatchKind::Family)]
+        );
+    }
+
+    #[test]
+    fn several_fonts_with_the_same_name() {
+        let mut matcher = FontMatcher::default();
+        matcher.add("regular", &names(&[(3, 0x0409, 1, "Foo")]));
+        matcher.add(
+            "bold",
+            &names(&[(3, 0x0409, 1, "Foo"), (3, 0x0409, 4, "Foo Bold")]),
+        );
+        assert_eq!(
+            matcher.find_gdi("FOO").collect::<Vec<_>>(),
+            [&"regular", &"bold"]
+        );
+        assert_eq!(matcher.find_gdi("foo bold").collect::<Vec<_>>(), [&"bold"]);
+        assert!(matcher.find("bar").is_empty());
+    }
+
+    #[test]
+    fn style_selection() {
+        let style = |weight, italic, width| FontStyle {
+            weight,
+            italic,
+            width,
+        };
+        let faces = [
+            ("regular", style(400, false, WIDTH_NORMAL)),
+            ("semibold italic", style(600, true, WIDTH_NORMAL)),
+            ("bold", style(700, false, WIDTH_NORMAL)),
+            ("bold condensed", style(700, false, 3)),
+            ("black", style(900, false, WIDTH_NORMAL)),
+        ];
+        let mut matcher = FontMatcher::default();
+        for (i, _) in faces.iter().enumerate() {
+            matcher.add(i, &names(&[(3, 0x0409, 1, "Foo")]));
+        }
+        let usage = |weight, italic| FontUsage {
+            family: "Foo".to_string(),
+            weight,
+            italic,
+        };
+        let cases = [
+            (usage(400, false), "regular", false, false),
+            (usage(700, false), "bold", false, false),
+            (usage(800, false), "black", false, false),
+            (usage(500, false), "regular", false, false),
+            (usage(600, false), "bold", false, false),
+            (usage(700, true), "semibold italic", false, false),
+            (usage(400, true), "semibold italic", false, false),
+            (usage(900, true), "semibold italic", true, false),
+        ];
+        for (usage, expected, fake_bold, fake_italic) in cases {
+            let found = matcher.find_style(&usage, |&i| faces[i].1).unwrap();
+            assert_eq!(faces[*found.id].0, expected, "{usage}");
+            assert_eq!(found.fake_bold, fake_bold, "{usage}");
+            assert_eq!(found.fake_italic, fake_italic, "{usage}");
+        }
+
+        let mut matcher = FontMatcher::default();
+        matcher.add(0, &names(&[(3, 0x0409, 1, "Foo")]));
+        let found = matcher
+            .find_style(&usage(700, true), |_| faces[0].1)
+            .unwrap();
+        assert!(found.fake_bold && found.fake_italic);
+        let other = FontUsage {
+            family: "Bar".to_string(),
+            ..usage(400, false)
+        };
+        assert!(matcher.find_style(&other, |_| faces[0].1).is_none());
+    }
+
+    #[test]
+    fn truncation_keeps_utf16_units() {
+        let name = "字".repeat(40);
+        assert_eq!(match_key(&name).chars().count(), 31);
+        let name = "😀".repeat(20);
+        assert_eq!(match_key(&name).encode_utf16().count(), 31);
+    }
+}
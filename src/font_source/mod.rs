@@ -1,14 +1,104 @@
+#[cfg(feature = "woff")]
+use crate::sfnt;
 use anyhow::Result;
+#[cfg(any(
+    feature = "archive-zip",
+    feature = "archive-rar",
+    feature = "archive-7z",
+    feature = "archive-tar",
+))]
+use log::debug;
+#[cfg(feature = "woff")]
+use log::info;
 use log::warn;
-use std::{collections::HashSet, path::Path, sync::LazyLock};
+#[cfg(any(
+    feature = "archive-zip",
+    feature = "archive-rar",
+    feature = "archive-7z",
+    feature = "archive-tar",
+    feature = "archive-mkv",
+    feature = "ass-fonts",
+    feature = "library",
+))]
+use std::io::{self, Read};
+#[cfg(any(
+    feature = "archive-zip",
+    feature = "archive-rar",
+    feature = "archive-7z",
+    feature = "archive-tar",
+    feature = "archive-mkv",
+    feature = "ass-fonts",
+    feature = "woff",
+))]
+use std::path::PathBuf;
+use std::{collections::HashSet, fs::File, path::Path, sync::LazyLock};
+#[cfg(any(
+    feature = "archive-zip",
+    feature = "archive-rar",
+    feature = "archive-7z",
+    feature = "archive-tar",
+    feature = "archive-mkv",
+    feature = "ass-fonts",
+))]
+use std::{fs, io::Write};
+#[cfg(any(
+    feature = "archive-zip",
+    feature = "archive-rar",
+    feature = "archive-7z",
+    feature = "archive-tar",
+))]
+use std::{path::Component, time::Instant};
+
+mod backend;
+pub use backend::FontBackend;
+
+#[cfg(windows)]
+mod backend_gdi;
+#[cfg(windows)]
+pub use backend_gdi::GdiBackend;
+
+#[cfg(unix)]
+mod backend_fontconfig;
+#[cfg(unix)]
+pub use backend_fontconfig::FontconfigBackend;
+
+mod backend_recording;
+pub use backend_recording::{BackendCall, RecordingBackend};
 
 mod file;
-use file::FontFile;
+pub use file::FontFile;
+
+mod companion;
+use companion::{group_companions, with_companions};
+
+pub mod matcher;
+
+mod session;
+pub use session::{Session, remove_overlapping};
+
+mod subtitle;
+use subtitle::SubtitleFile;
+#[cfg(any(feature = "dir", feature = "subset"))]
+pub use subtitle::path_is_subtitle;
+#[cfg(feature = "dir")]
+use subtitle::require_subtitle;
+
+#[cfg(feature = "sidecar")]
+mod sidecar;
+#[cfg(feature = "sidecar")]
+pub use sidecar::with_sidecars;
+
+pub mod sniff;
+#[cfg(feature = "dir")]
+use sniff::file_font_kind;
+use sniff::{is_font, read_head, sniff};
 
 #[cfg(feature = "dir")]
 mod dir;
 #[cfg(feature = "dir")]
 use dir::FontDir;
+#[cfg(feature = "library")]
+pub use dir::walk_files;
 
 #[cfg(feature = "archive-zip")]
 mod archive_zip;
@@ -30,14 +120,32 @@ mod archive_tar;
 #[cfg(feature = "archive-tar")]
 use archive_tar::{FontArchiveTar, TarCompression};
 
-/// AddFontResourceW 支持的字体文件扩展名
+#[cfg(feature = "archive-mkv")]
+mod archive_mkv;
+#[cfg(feature = "archive-mkv")]
+use archive_mkv::FontArchiveMkv;
+
+/// AddFontResourceW 支持的字体文件扩展名，以及同样是字体集合的 OTC 和解码后才能加载的 WOFF/WOFF2
 ///
 /// 参见：
 /// AddFontResourceW 函数 （wingdi.h） - Win32 apps | Microsoft Learn
 /// https://learn.microsoft.com/zh-cn/windows/win32/api/wingdi/nf-wingdi-addfontresourcew
 static FONT_EXTENSION: LazyLock<HashSet<String>> = LazyLock::new(|| {
     [
-        "fon", "fnt", "ttf", "ttc", "fot", "otf", "mmm", "pfb", "pfm",
+        "fon",
+        "fnt",
+        "ttf",
+        "ttc",
+        "otc",
+        "fot",
+        "otf",
+        "mmm",
+        "pfb",
+        "pfm",
+        #[cfg(feature = "woff")]
+        "woff",
+        #[cfg(feature = "woff")]
+        "woff2",
     ]
     .iter()
     .map(|&s| s.to_string())
@@ -49,6 +157,9 @@ static FONT_EXTENSION: LazyLock<HashSet<String>> = LazyLock::new(|| {
     feature = "archive-rar",
     feature = "archive-7z",
     feature = "archive-tar",
+    feature = "archive-mkv",
+    feature = "ass-fonts",
+    feature = "woff",
 ))]
 const TEMPDIR_PREFIX: &str = ".fontloader";
 
@@ -59,17 +170,248 @@ fn path_is_font(path: &Path) -> bool {
         .unwrap_or(false)
 }
 
+/// 读取压缩包中的文件的开头检查是否为字体，是字体时解压到 extract 并返回解压后的路径
+///
+/// WOFF/WOFF2 字体会被解码，解压后的路径会加上 TTF/OTF 等扩展名。
+/// 不是字体时只读取了文件的开头，对于固实压缩的格式需要由调用者读取剩下的部分。
+#[cfg(any(
+    feature = "archive-zip",
+    feature = "archive-rar",
+    feature = "archive-7z",
+    feature = "archive-tar",
+    feature = "archive-mkv",
+    feature = "ass-fonts",
+))]
+fn extract_if_font(
+    name: &Path,
+    reader: &mut (impl Read + ?Sized),
+    extract: &Path,
+) -> io::Result<Option<PathBuf>> {
+    let head = read_head(reader)?;
+    if !is_font(name, &head) {
+        return Ok(None);
+    }
+    #[cfg(feature = "woff")]
+    if matches!(
+        sniff(&head),
+        sniff::ContentKind::Woff | sniff::ContentKind::Woff2
+    ) {
+        let mut data = head;
+        reader.read_to_end(&mut data)?;
+        return match decode_web_font(name, &data, extract) {
+            Ok(decoded) => Ok(Some(decoded)),
+            Err(err) => {
+                warn!(
+                    "Skipped font \"{}\" failed to decode: {err}",
+                    name.to_str().unwrap()
+                );
+                Ok(None)
+            }
+        };
+    }
+    if let Some(parent) = extract.parent()
+        && !parent.exists()
+    {
+        fs::create_dir_all(parent)?;
+    }
+    let mut outfile = File::create(extract)?;
+    outfile.write_all(&head)?;
+    io::copy(reader, &mut outfile)?;
+    Ok(Some(extract.to_path_buf()))
+}
+
+/// 解压压缩包 path 中 members 里的字体，members 为 None 时解压所有字体，kind 是日志中显示的压缩包类型
+///
+/// walk 用 want 筛选并依次读取压缩包中的文件。启用了解压缓存时解压到缓存中，
+/// 缓存中已经有需要的所有字体时直接使用，不会读取压缩包。
+#[cfg(any(
+    feature = "archive-zip",
+    feature = "archive-rar",
+    feature = "archive-7z",
+    feature = "archive-tar",
+))]
+fn extract_fonts(
+    path: &str,
+    kind: &str,
+    members: Option<&HashSet<String>>,
+    extract: &Path,
+    walk: impl FnOnce(
+        &dyn Fn(&Path) -> bool,
+        &mut dyn FnMut(&Path, &mut dyn Read) -> io::Result<()>,
+    ) -> Result<()>,
+) -> Result<Vec<(String, FontFile)>> {
+    #[cfg(feature = "cache")]
+    let mut cache = crate::library::cache::open(path);
+    #[cfg(feature = "cache")]
+    if let Some(hit) = cache.as_ref().and_then(|cache| cache.lookup(members)) {
+        return Ok(hit
+            .into_iter()
+            .map(|(name, file)| (name, FontFile::new(file.to_str().unwrap().to_string())))
+            .collect());
+    }
+    #[cfg(feature = "cache")]
+    let extract = cache.as_ref().map_or(extract, |cache| cache.dir());
+
+    debug!("Walking {kind} \"{path}\"");
+    let mut extracted = vec![];
+    let start = Instant::now();
+    walk(
+        &|name| members.is_none_or(|members| members.contains(name.to_str().unwrap())),
+        &mut |name, reader| {
+            let Some(relative) = member_path(name) else {
+                warn!(
+                    "Skipped \"{}\" from {kind} \"{path}\" which would be extracted outside the folder",
+                    name.to_str().unwrap()
+                );
+                return Ok(());
+            };
+            let Some(file) = extract_if_font(name, reader, &extract.join(relative))? else {
+                return Ok(());
+            };
+            let name = name.to_str().unwrap().to_string();
+            debug!(
+                "Found font \"{}\" from {kind} \"{path}\" and extracted to \"{}\"",
+                name,
+                file.to_str().unwrap(),
+            );
+            extracted.push((name, file));
+            Ok(())
+        },
+    )?;
+    debug!(
+        "Extracted fonts from {kind} \"{path}\" in {}s",
+        start.elapsed().as_secs_f64()
+    );
+
+    #[cfg(feature = "cache")]
+    if let Some(cache) = &mut cache
+        && let Err(err) = cache.store(&extracted, members.is_none())
+    {
+        warn!("Unable to save fonts of \"{path}\" to the cache: {err:#}");
+    }
+    Ok(extracted
+        .into_iter()
+        .map(|(name, file)| (name, FontFile::new(file.to_str().unwrap().to_string())))
+        .collect())
+}
+
+/// 压缩包中的成员 name 解压时相对于解压文件夹的路径，只保留普通的部分
+///
+/// 含有 ..、根目录或者盘符的路径会被解压到文件夹之外，返回 None。
+#[cfg(any(
+    feature = "archive-zip",
+    feature = "archive-rar",
+    feature = "archive-7z",
+    feature = "archive-tar",
+))]
+fn member_path(name: &Path) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+    for component in name.components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    (!relative.as_os_str().is_empty()).then_some(relative)
+}
+
+/// 把名为 name 的 WOFF/WOFF2 字体解码后保存到 out 加上对应的扩展名的路径，返回解码后的文件路径
+#[cfg(feature = "woff")]
+fn decode_web_font(name: &Path, data: &[u8], out: &Path) -> Result<PathBuf> {
+    let (font, extension) = sfnt::decode_web_font(data)?;
+    let mut decoded = out.as_os_str().to_os_string();
+    decoded.push(".");
+    decoded.push(extension);
+    let decoded = PathBuf::from(decoded);
+    if let Some(parent) = decoded.parent()
+        && !parent.exists()
+    {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&decoded, font)?;
+    info!(
+        "Decoded web font \"{}\" to \"{}\"",
+        name.to_str().unwrap(),
+        decoded.to_str().unwrap()
+    );
+    Ok(decoded)
+}
+
 /// 加载字体文件的源
 pub trait FontSource: Send + Sync {
-    /// 加载字体
-    fn load(&mut self) -> Result<()>;
-    /// 卸载字体
-    fn unload(&self);
+    /// 读取字幕中使用的字体并记录到会话中，在加载任何字体源之前调用
+    fn require_fonts(&mut self, _session: &Session) -> Result<()> {
+        Ok(())
+    }
+    /// 找到源中的字体并选出需要加载的字体，记录到会话中比较版本，在加载任何字体源之前调用
+    fn prepare(&mut self, session: &Session) -> Result<()>;
+    /// 通过会话的后端加载 prepare 选出的字体，跳过被更新版本取代的字体和与已加载的字体内容相同的字体
+    fn load(&mut self, session: &Session) -> Result<()>;
+    /// 通过后端卸载字体
+    fn unload(&self, backend: &dyn FontBackend);
+    /// 只加载压缩包中的这些成员，例如根据字体库的索引找到的字幕需要的字体，其他文件不会被解压
+    #[cfg(feature = "library")]
+    fn select_members(&mut self, _members: HashSet<String>) {}
     /// 获取源的路径，主要用于日志输出
     fn get_path(&self) -> &String;
+    /// 获取源中已加载的字体文件
+    fn get_fonts(&self) -> Vec<&FontFile>;
+}
+
+/// 依次读取 kind 类型的压缩包 path 中 want 返回 true 的每个文件，不会解压到硬盘上
+///
+/// 不是支持的压缩包格式时返回 false，MKV 等不是字体压缩包的格式也不支持。
+#[cfg(feature = "library")]
+#[cfg_attr(
+    not(any(
+        feature = "archive-zip",
+        feature = "archive-rar",
+        feature = "archive-7z",
+        feature = "archive-tar",
+    )),
+    allow(unused_variables)
+)]
+pub fn for_each_member(
+    path: &str,
+    kind: sniff::ContentKind,
+    want: impl Fn(&Path) -> bool,
+    op: impl FnMut(&Path, &mut dyn Read) -> io::Result<()>,
+) -> Result<bool> {
+    match kind {
+        #[cfg(feature = "archive-zip")]
+        sniff::ContentKind::Zip => FontArchiveZip::for_each_member(path, want, op).map(|_| true),
+        #[cfg(feature = "archive-7z")]
+        sniff::ContentKind::SevenZip => {
+            FontArchive7z::for_each_member(path, want, op).map(|_| true)
+        }
+        #[cfg(feature = "archive-rar")]
+        sniff::ContentKind::Rar => FontArchiveRar::for_each_member(path, want, op).map(|_| true),
+        #[cfg(feature = "archive-tar")]
+        sniff::ContentKind::Tar => {
+            FontArchiveTar::for_each_member(path, &TarCompression::None, want, op).map(|_| true)
+        }
+        #[cfg(feature = "archive-tar")]
+        sniff::ContentKind::Gzip => {
+            FontArchiveTar::for_each_member(path, &TarCompression::GZ, want, op).map(|_| true)
+        }
+        #[cfg(feature = "archive-tar")]
+        sniff::ContentKind::Bzip2 => {
+            FontArchiveTar::for_each_member(path, &TarCompression::BZ2, want, op).map(|_| true)
+        }
+        #[cfg(feature = "archive-tar")]
+        sniff::ContentKind::Xz => {
+            FontArchiveTar::for_each_member(path, &TarCompression::XZ, want, op).map(|_| true)
+        }
+        #[cfg(feature = "archive-tar")]
+        sniff::ContentKind::Zstd => {
+            FontArchiveTar::for_each_member(path, &TarCompression::Zstd, want, op).map(|_| true)
+        }
+        _ => Ok(false),
+    }
 }
 
-/// 输入路径得到字体源，可能是文件、文件夹或压缩包等等
+/// 输入路径得到字体源，可能是文件、文件夹、压缩包或字幕等等
 /// 如果不是任何一种可以处理的源则返回 None
 pub fn from_path(path: String) -> Option<Box<dyn FontSource>> {
     let p = Path::new(&path);
@@ -82,49 +424,233 @@ pub fn from_path(path: String) -> Option<Box<dyn FontSource>> {
         return Some(Box::new(FontDir::new(path)));
     }
     if p.is_file() {
-        match p
-            .extension()
-            .map(|ext| ext.to_str().unwrap().to_ascii_lowercase())
-        {
-            Some(ext) => {
-                if FONT_EXTENSION.contains(&ext) {
-                    return Some(Box::new(FontFile::new(path)));
-                }
-                #[cfg(feature = "archive-zip")]
-                if ext == "zip" {
-                    return Some(Box::new(FontArchiveZip::new(path)));
-                }
-                #[cfg(feature = "archive-7z")]
-                if ext == "7z" {
-                    return Some(Box::new(FontArchive7z::new(path)));
-                }
-                #[cfg(feature = "archive-rar")]
-                if ext == "rar" {
-                    return Some(Box::new(FontArchiveRar::new(path)));
-                }
-                #[cfg(feature = "archive-tar")]
-                if ext == "tar" {
-                    return Some(Box::new(FontArchiveTar::new(path, TarCompression::None)));
-                }
-            }
-            None => {
+        let head = match File::open(p).and_then(|mut f| read_head(&mut f)) {
+            Ok(head) => head,
+            Err(err) => {
+                warn!("Unable to read \"{path}\": {err}");
                 return None;
             }
+        };
+        // 根据内容而不是扩展名判断压缩包、字幕和字体的类型
+        match sniff(&head) {
+            sniff::ContentKind::Subtitle => return Some(Box::new(SubtitleFile::new(path))),
+            #[cfg(feature = "archive-zip")]
+            sniff::ContentKind::Zip => return Some(Box::new(FontArchiveZip::new(path))),
+            #[cfg(feature = "archive-7z")]
+            sniff::ContentKind::SevenZip => return Some(Box::new(FontArchive7z::new(path))),
+            #[cfg(feature = "archive-rar")]
+            sniff::ContentKind::Rar => return Some(Box::new(FontArchiveRar::new(path))),
+            #[cfg(feature = "archive-tar")]
+            sniff::ContentKind::Tar => {
+                return Some(Box::new(FontArchiveTar::new(path, TarCompression::None)));
+            }
+            #[cfg(feature = "archive-tar")]
+            sniff::ContentKind::Gzip => {
+                return Some(Box::new(FontArchiveTar::new(path, TarCompression::GZ)));
+            }
+            #[cfg(feature = "archive-tar")]
+            sniff::ContentKind::Bzip2 => {
+                return Some(Box::new(FontArchiveTar::new(path, TarCompression::BZ2)));
+            }
+            #[cfg(feature = "archive-tar")]
+            sniff::ContentKind::Xz => {
+                return Some(Box::new(FontArchiveTar::new(path, TarCompression::XZ)));
+            }
+            #[cfg(feature = "archive-tar")]
+            sniff::ContentKind::Zstd => {
+                return Some(Box::new(FontArchiveTar::new(path, TarCompression::Zstd)));
+            }
+            #[cfg(feature = "archive-mkv")]
+            sniff::ContentKind::Matroska => return Some(Box::new(FontArchiveMkv::new(path))),
+            _ => {}
         }
-        #[cfg(feature = "archive-tar")]
-        {
-            let path_lowercase = path.to_ascii_lowercase();
-            for (ext, compression) in [
-                (".tar.gz", TarCompression::GZ),
-                (".tar.bz2", TarCompression::BZ2),
-                (".tar.xz", TarCompression::XZ),
-                (".tar.zst", TarCompression::Zstd),
-            ] {
-                if path_lowercase.ends_with(ext) {
-                    return Some(Box::new(FontArchiveTar::new(path, compression)));
-                }
+        if is_font(p, &head) {
+            // 单个 WOFF/WOFF2 字体也需要解码到临时文件夹，当作只有一个文件的文件夹处理
+            #[cfg(feature = "woff")]
+            if matches!(
+                sniff(&head),
+                sniff::ContentKind::Woff | sniff::ContentKind::Woff2
+            ) {
+                return Some(Box::new(FontDir::new(path)));
             }
+            // 单独输入的 PFM/PFB/FOT 需要和同一个文件夹中的另一半一起加载
+            return with_companions(path).map(|f| Box::new(f) as Box<dyn FontSource>);
         }
     }
     None
 }
+
+#[cfg(all(test, feature = "dir", feature = "archive-zip"))]
+mod tests {
+    use super::*;
+    use crate::sfnt::test_font;
+    use tempdir::TempDir;
+    use zip::{ZipWriter, write::SimpleFileOptions};
+
+    /// 把记录的调用按通知分成几批，每批中的路径只保留文件名并排序，并行加载时同一批中的顺序不固定
+    fn batches(calls: &[BackendCall]) -> Vec<Vec<String>> {
+        let mut batches = vec![Vec::new()];
+        for call in calls {
+            let (kind, path) = match call {
+                BackendCall::Register(path) => ("register", path),
+                BackendCall::Unregister(path) => ("unregister", path),
+                BackendCall::NotifyChanged => {
+                    batches.last_mut().unwrap().sort();
+                    batches.push(Vec::new());
+                    continue;
+                }
+            };
+            let name = Path::new(path).file_name().unwrap().to_str().unwrap();
+            batches.last_mut().unwrap().push(format!("{kind} {name}"));
+        }
+        batches
+    }
+
+    #[test]
+    fn load_dir_and_zip_with_recording_backend() {
+        let temp = TempDir::new("fontloader-test").unwrap();
+        let dir = temp.path().join("Fonts");
+        fs::create_dir_all(dir.join("Bold")).unwrap();
+        let regular = test_font("Test Sans", "Regular", 400, "abc");
+        fs::write(dir.join("TestSans-Regular.ttf"), &regular).unwrap();
+        fs::write(
+            dir.join("Bold").join("TestSans-Bold.ttf"),
+            test_font("Test Sans", "Bold", 700, "abc"),
+        )
+        .unwrap();
+        fs::write(dir.join("readme.txt"), "not a font").unwrap();
+
+        let zip_path = temp.path().join("Pack.zip");
+        let mut zip = ZipWriter::new(File::create(&zip_path).unwrap());
+        let options = SimpleFileOptions::default();
+        zip.start_file("Pack/TestSerif.ttf", options).unwrap();
+        zip.write_all(&test_font("Test Serif", "Regular", 400, "abc"))
+            .unwrap();
+        // 和文件夹中的字体内容相同，不会重复注册
+        zip.start_file("Pack/Copy.ttf", options).unwrap();
+        zip.write_all(&regular).unwrap();
+        zip.start_file("Pack/readme.txt", options).unwrap();
+        zip.write_all(b"not a font").unwrap();
+        zip.finish().unwrap();
+
+        let backend = RecordingBackend::new();
+        let session = Session::new(&backend);
+        let mut sources: Vec<Box<dyn FontSource>> = [dir, zip_path]
+            .iter()
+            .map(|path| from_path(path.to_str().unwrap().to_string()).unwrap())
+            .collect();
+        // 先加载文件夹，压缩包中内容相同的字体才会被当作重复的字体
+        for source in &mut sources {
+            source.prepare(&session).unwrap();
+        }
+        session.select_versions();
+        for source in &mut sources {
+            source.load(&session).unwrap();
+        }
+        backend.notify_changed();
+        assert_eq!(sources[0].get_fonts().len(), 2);
+        assert_eq!(sources[1].get_fonts().len(), 1);
+        assert_eq!(session.duplicates().len(), 1);
+        for source in &sources {
+            source.unload(&backend);
+        }
+        backend.notify_changed();
+
+        assert_eq!(
+            batches(&backend.calls()),
+            [
+                vec![
+                    "register TestSans-Bold.ttf",
+                    "register TestSans-Regular.ttf",
+                    "register TestSerif.ttf",
+                ],
+                vec![
+                    "unregister TestSans-Bold.ttf",
+                    "unregister TestSans-Regular.ttf",
+                    "unregister TestSerif.ttf",
+                ],
+                vec![],
+            ]
+        );
+    }
+
+    #[test]
+    fn unsafe_member_paths() {
+        assert_eq!(
+            member_path(Path::new("./Pack/A.ttf")),
+            Some(Path::new("Pack").join("A.ttf"))
+        );
+        assert_eq!(member_path(Path::new("Pack/../../A.ttf")), None);
+        assert_eq!(member_path(Path::new("/tmp/A.ttf")), None);
+        assert_eq!(member_path(Path::new(".")), None);
+
+        let temp = TempDir::new("fontloader-test").unwrap();
+        let zip_path = temp.path().join("Pack.zip");
+        let mut zip = ZipWriter::new(File::create(&zip_path).unwrap());
+        let options = SimpleFileOptions::default();
+        for (name, family) in [
+            ("Pack/Safe.ttf", "Safe"),
+            ("../Parent.ttf", "Parent"),
+            ("Pack/../../Nested.ttf", "Nested"),
+            ("/Root.ttf", "Root"),
+        ] {
+            zip.start_file(name, options).unwrap();
+            zip.write_all(&test_font(family, "Regular", 400, "abc"))
+                .unwrap();
+        }
+        zip.finish().unwrap();
+        let archive = zip::ZipArchive::new(File::open(&zip_path).unwrap()).unwrap();
+        assert!(archive.file_names().any(|name| name == "../Parent.ttf"));
+
+        let backend = RecordingBackend::new();
+        let session = Session::new(&backend);
+        let mut source = from_path(zip_path.to_str().unwrap().to_string()).unwrap();
+        source.prepare(&session).unwrap();
+        session.select_versions();
+        source.load(&session).unwrap();
+        let fonts = source.get_fonts();
+        assert_eq!(fonts.len(), 1);
+        assert!(fonts[0].path.ends_with("Safe.ttf"));
+        assert!(!temp.path().join("Parent.ttf").exists());
+    }
+
+    #[test]
+    fn subtitles_in_dropped_folder() {
+        let temp = TempDir::new("fontloader-test").unwrap();
+        fs::write(
+            temp.path().join("readme.ass"),
+            "[Script Info]\n[V4+ Styles]\nFormat: Name, Fontname\nStyle: Default,Test Sans\n\n[Events]\nFormat: Layer, Style, Text\nDialogue: 0,Default,abc\n",
+        )
+        .unwrap();
+        fs::write(
+            temp.path().join("Other.ttf"),
+            test_font("Other", "Regular", 400, "abc"),
+        )
+        .unwrap();
+        let path = temp.path().to_str().unwrap().to_string();
+
+        // 加载字体时文件夹中的字幕不会使其他字体被跳过
+        let backend = RecordingBackend::new();
+        let session = Session::new(&backend);
+        let mut source = from_path(path.clone()).unwrap();
+        source.require_fonts(&session).unwrap();
+        assert!(!session.has_subtitles());
+        source.prepare(&session).unwrap();
+        session.select_versions();
+        source.load(&session).unwrap();
+        assert_eq!(source.get_fonts().len(), 1);
+
+        let mut session = Session::new(&backend);
+        session.folder_subtitles = true;
+        let mut source = from_path(path).unwrap();
+        source.require_fonts(&session).unwrap();
+        assert!(session.has_subtitles());
+        source.prepare(&session).unwrap();
+        session.select_versions();
+        source.load(&session).unwrap();
+        assert!(source.get_fonts().is_empty());
+        let unused = session.unused();
+        assert_eq!(unused.len(), 1);
+        assert!(!unused[0].attached);
+    }
+}
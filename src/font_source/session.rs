@@ -0,0 +1,845 @@
+#[cfg(feature = "library")]
+use crate::sfnt::FontNames;
+use crate::{
+    font_source::{
+        FontBackend, FontFile,
+        matcher::{FontMatcher, match_key, name_keys},
+        sniff,
+    },
+    sfnt::{CharMap, Face, FontStyle, FontVersion},
+    subtitle::{FontRun, FontUsage},
+};
+use anyhow::Result;
+use cfg_if::cfg_if;
+use log::{debug, info, warn};
+#[cfg(feature = "parallel")]
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, hash_map::Entry},
+    fs::{self, File},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// 因为和已加载的字体内容相同而被跳过的字体
+pub struct Duplicate {
+    /// 被跳过的字体
+    pub name: String,
+    /// 已加载的内容相同的字体
+    pub original: String,
+}
+
+/// 因为有相同家族和样式的更新版本而被跳过的字体
+pub struct Superseded {
+    pub name: String,
+    pub version: FontVersion,
+    /// 取代它的字体
+    pub newer: String,
+    pub newer_version: FontVersion,
+}
+
+/// 因为字幕不需要而跳过的字体
+pub struct Unused {
+    pub name: String,
+    /// 是否为字幕自带的字体，即 MKV 的附件或者 ASS 字幕的 [Fonts] 部分中的字体
+    pub attached: bool,
+}
+
+/// 字幕需要的一个字体家族
+#[derive(Default)]
+struct Required {
+    /// 字幕中使用这个家族的字体和样式
+    usages: BTreeSet<FontUsage>,
+    /// 需要这个家族的字幕
+    subtitles: BTreeSet<String>,
+    /// 用这个家族的每种样式显示的字符
+    chars: BTreeMap<FontUsage, BTreeSet<char>>,
+}
+
+/// 字体源中选出的等待比较版本的字体文件
+struct Candidate {
+    name: String,
+    /// 每个字体用于比较版本的家族和样式名称以及版本
+    faces: Vec<(Option<(String, String)>, FontVersion)>,
+}
+
+/// 字幕需要的字体中已加载的一个字体
+struct SelectedFace {
+    name: String,
+    style: FontStyle,
+    chars: Option<CharMap>,
+}
+
+/// 字幕需要但是在输入的字体中没有找到的字体家族
+pub struct Missing {
+    pub usages: Vec<FontUsage>,
+    pub subtitles: Vec<String>,
+    /// 只有 typographic family 匹配的字体，这些字体已经加载，但是 GDI 和 VSFilter 找不到
+    pub typographic: Vec<String>,
+}
+
+/// 字幕需要的字体家族中没有字形的字符
+pub struct Uncovered {
+    pub family: String,
+    /// GDI 选中的缺少这些字形的字体
+    pub fonts: Vec<String>,
+    pub chars: Vec<char>,
+    pub subtitles: Vec<String>,
+}
+
+/// 字幕需要的样式没有对应的字体，渲染器会用同一家族的其他字体模拟粗体或斜体
+pub struct Synthetic {
+    pub usage: FontUsage,
+    /// GDI 选中的字体
+    pub font: String,
+    pub bold: bool,
+    pub italic: bool,
+    pub subtitles: Vec<String>,
+}
+
+/// 字幕中一行对话里字体没有字形的字符，这些字符会显示为方框或者用其他字体代替
+pub struct Tofu {
+    pub subtitle: String,
+    pub line: usize,
+    pub family: String,
+    pub chars: String,
+}
+
+/// 一次运行中所有字体源共享的状态
+///
+/// 内容相同的字体在一次运行中只会加载一次，无论它来自哪个字体源。
+/// 输入了字幕时，所有字体源都只加载字幕需要的字体。
+pub struct Session<'a> {
+    pub backend: &'a dyn FontBackend,
+    /// 已加载的字体的内容哈希和名称
+    loaded: Mutex<HashMap<u128, String>>,
+    duplicates: Mutex<Vec<Duplicate>>,
+    superseded: Mutex<Vec<Superseded>>,
+    /// 所有字体源中选出的字体，加载前在它们之间比较版本
+    candidates: Mutex<Vec<Candidate>>,
+    /// 被更新版本取代而不会加载的字体的名称
+    superseded_names: Mutex<HashSet<String>>,
+    /// 字幕需要的字体家族，键是用于匹配的名称
+    required: Mutex<HashMap<String, Required>>,
+    /// 每个字幕中使用同一种字体的文字，用于找出缺少字形的行
+    runs: Mutex<Vec<(String, Vec<FontRun>)>>,
+    /// 字幕需要的字体中已加载的字体的名称
+    selected: Mutex<FontMatcher<Arc<SelectedFace>>>,
+    /// 因为字幕不需要而跳过的字体
+    unused: Mutex<Vec<Unused>>,
+    /// 字体是字幕自带的字体的字体源
+    attached: Mutex<HashSet<String>>,
+    /// 是否读取 MKV 中的字幕轨道，需要读取整个文件，所以只在检查模式中使用
+    pub muxed_subtitles: bool,
+    /// 是否把拖入的文件夹中直接包含的字幕当作输入的字幕，只在检查、收集和嵌入字体时使用，
+    /// 加载字体时只使用直接拖入的字幕，以免字体库中的字幕使其他字体都不会加载
+    pub folder_subtitles: bool,
+}
+
+impl<'a> Session<'a> {
+    pub fn new(backend: &'a dyn FontBackend) -> Self {
+        Self {
+            backend,
+            loaded: Mutex::new(HashMap::new()),
+            duplicates: Mutex::new(Vec::new()),
+            superseded: Mutex::new(Vec::new()),
+            candidates: Mutex::new(Vec::new()),
+            superseded_names: Mutex::new(HashSet::new()),
+            required: Mutex::new(HashMap::new()),
+            runs: Mutex::new(Vec::new()),
+            selected: Mutex::new(FontMatcher::default()),
+            unused: Mutex::new(Vec::new()),
+            attached: Mutex::new(HashSet::new()),
+            muxed_subtitles: false,
+            folder_subtitles: false,
+        }
+    }
+
+    /// 记录字幕 subtitle 中使用的字体和用每个字体显示的文字
+    pub fn require(&self, subtitle: &str, runs: Vec<FontRun>) {
+        let mut required = self.required.lock().unwrap();
+        if required.is_empty() && !runs.is_empty() {
+            info!("Subtitle \"{subtitle}\" enabled loading only the fonts required by subtitles");
+        }
+        for run in &runs {
+            let entry = required.entry(match_key(&run.font.family)).or_default();
+            entry.subtitles.insert(subtitle.to_string());
+            entry.usages.insert(run.font.clone());
+            entry
+                .chars
+                .entry(run.font.clone())
+                .or_default()
+                .extend(run.chars());
+        }
+        self.runs.lock().unwrap().push((subtitle.to_string(), runs));
+    }
+
+    /// 记录用 --family 等方式直接要求的字体，source 是要求的来源，这些字体没有需要检查字形的文字
+    #[cfg(feature = "library")]
+    pub fn require_usages(&self, source: &str, usages: Vec<FontUsage>) {
+        let mut required = self.required.lock().unwrap();
+        if required.is_empty() && !usages.is_empty() {
+            info!("{source} enabled loading only the required fonts");
+        }
+        for usage in usages {
+            let entry = required.entry(match_key(&usage.family)).or_default();
+            entry.subtitles.insert(source.to_string());
+            entry.usages.insert(usage);
+        }
+    }
+
+    /// 获取字幕使用的所有字体，按名称排序
+    pub fn required_usages(&self) -> Vec<FontUsage> {
+        let required = self.required.lock().unwrap();
+        let mut usages: Vec<FontUsage> = required
+            .values()
+            .flat_map(|r| r.usages.iter().cloned())
+            .collect();
+        usages.sort();
+        usages
+    }
+
+    /// 每个字幕中用每个字体显示的文字，按字幕名称排序
+    #[cfg(feature = "subset")]
+    pub fn subtitle_runs(&self) -> Vec<(String, Vec<FontRun>)> {
+        let mut runs = self.runs.lock().unwrap().clone();
+        runs.sort_by(|(a, _), (b, _)| a.cmp(b));
+        runs
+    }
+
+    /// 是否输入了使用字体的字幕
+    pub fn has_subtitles(&self) -> bool {
+        !self.required.lock().unwrap().is_empty()
+    }
+
+    /// 记录字体源 source 中的字体是字幕自带的字体
+    pub fn attach(&self, source: &str) {
+        self.attached.lock().unwrap().insert(source.to_string());
+    }
+
+    /// 检查字体源 source 中名为 name 的字体是否为字幕需要的字体，没有输入字幕时所有字体都是需要的
+    ///
+    /// 和 GDI 一样匹配所有语言的家族名称和全名。只有 typographic family 匹配的字体也会加载，
+    /// 供其他渲染器使用，但是仍然会被当作没有找到的字体报告。
+    pub fn is_required(&self, name: &str, source: &str, font: &FontFile) -> bool {
+        let required = self.required.lock().unwrap();
+        if required.is_empty() {
+            return true;
+        }
+        let is_required = font.faces.iter().any(|face| {
+            name_keys(&face.names)
+                .iter()
+                .any(|(key, _)| required.contains_key(key))
+        });
+        if !is_required {
+            self.push_unused(name.to_string(), source);
+        }
+        is_required
+    }
+
+    /// 检查名称为 names 的字体是否为字幕需要的字体，和 is_required 相同但是不记录未使用的字体
+    #[cfg(feature = "library")]
+    pub fn requires_names(&self, names: &FontNames) -> bool {
+        let required = self.required.lock().unwrap();
+        required.is_empty()
+            || name_keys(names)
+                .iter()
+                .any(|(key, _)| required.contains_key(key))
+    }
+
+    /// 记录字体源 source 中因为字幕不需要而没有读取的字体 name
+    #[cfg(feature = "library")]
+    pub fn skip_unused(&self, name: &str, source: &str) {
+        self.push_unused(display_name(name, source), source);
+    }
+
+    /// 记录字体源 source 中因为字幕不需要而跳过的字体 name
+    fn push_unused(&self, name: String, source: &str) {
+        let attached = self.attached.lock().unwrap().contains(source);
+        self.unused.lock().unwrap().push(Unused { name, attached });
+    }
+
+    /// 获取字幕需要但是 GDI 无法在加载的字体中找到的字体，按名称排序
+    pub fn missing(&self) -> Vec<Missing> {
+        let selected = self.selected.lock().unwrap();
+        let mut missing: Vec<Missing> = self
+            .required
+            .lock()
+            .unwrap()
+            .values()
+            .filter_map(|r| {
+                let family = &r.usages.first()?.family;
+                if selected.find_gdi(family).next().is_some() {
+                    return None;
+                }
+                let mut typographic: Vec<String> = selected
+                    .find(family)
+                    .iter()
+                    .map(|(face, _)| face.name.clone())
+                    .collect();
+                typographic.dedup();
+                Some(Missing {
+                    usages: r.usages.iter().cloned().collect(),
+                    subtitles: r.subtitles.iter().cloned().collect(),
+                    typographic,
+                })
+            })
+            .collect();
+        missing.sort_by(|a, b| a.usages.cmp(&b.usages));
+        missing
+    }
+
+    /// 获取字幕需要的字体家族中，GDI 为每种样式选中的字体没有字形的字符，按名称排序
+    ///
+    /// 有无法读取 cmap 表的字体时不检查这种样式。
+    pub fn uncovered(&self) -> Vec<Uncovered> {
+        let selected = self.selected.lock().unwrap();
+        let mut uncovered: Vec<Uncovered> = self
+            .required
+            .lock()
+            .unwrap()
+            .values()
+            .filter_map(|r| {
+                let mut fonts: Vec<String> = Vec::new();
+                let mut missing: BTreeSet<char> = BTreeSet::new();
+                for (usage, chars) in &r.chars {
+                    let Some(found) = selected.find_style(usage, |face| face.style) else {
+                        continue;
+                    };
+                    let Some(face_chars) = &found.id.chars else {
+                        continue;
+                    };
+                    let before = missing.len();
+                    missing.extend(chars.iter().filter(|&&c| !face_chars.contains(c)));
+                    if missing.len() > before && !fonts.contains(&found.id.name) {
+                        fonts.push(found.id.name.clone());
+                    }
+                }
+                if missing.is_empty() {
+                    return None;
+                }
+                Some(Uncovered {
+                    family: r.usages.first()?.family.clone(),
+                    fonts,
+                    chars: missing.into_iter().collect(),
+                    subtitles: r.subtitles.iter().cloned().collect(),
+                })
+            })
+            .collect();
+        uncovered.sort_by(|a, b| a.family.cmp(&b.family));
+        uncovered
+    }
+
+    /// 获取字幕需要的样式中，GDI 找不到真正的粗体或斜体而会模拟的样式，按样式排序
+    pub fn synthetic(&self) -> Vec<Synthetic> {
+        let selected = self.selected.lock().unwrap();
+        let mut synthetic: Vec<Synthetic> = self
+            .required
+            .lock()
+            .unwrap()
+            .values()
+            .flat_map(|r| {
+                r.usages.iter().filter_map(|usage| {
+                    let found = selected.find_style(usage, |face| face.style)?;
+                    (found.fake_bold || found.fake_italic).then(|| Synthetic {
+                        usage: usage.clone(),
+                        font: found.id.name.clone(),
+                        bold: found.fake_bold,
+                        italic: found.fake_italic,
+                        subtitles: r.subtitles.iter().cloned().collect(),
+                    })
+                })
+            })
+            .collect();
+        synthetic.sort_by(|a, b| a.usage.cmp(&b.usage));
+        synthetic
+    }
+
+    /// 获取字幕中字体没有字形的字符所在的行，按字幕和行号排序
+    ///
+    /// 和 uncovered 一样只检查 GDI 为这种样式选中并且能够读取 cmap 表的字体。
+    pub fn tofu(&self) -> Vec<Tofu> {
+        let selected = self.selected.lock().unwrap();
+        let mut runs = self.runs.lock().unwrap();
+        runs.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let mut tofu: Vec<Tofu> = Vec::new();
+        for (subtitle, runs) in runs.iter() {
+            for run in runs {
+                let Some(chars) = selected
+                    .find_style(&run.font, |face| face.style)
+                    .and_then(|found| found.id.chars.as_ref())
+                else {
+                    continue;
+                };
+                let missing: Vec<char> = run.chars().filter(|&c| !chars.contains(c)).collect();
+                if missing.is_empty() {
+                    continue;
+                }
+                // 同一行中同一家族的不同样式合并为一项
+                let merge = tofu.last().is_some_and(|last| {
+                    last.subtitle == *subtitle
+                        && last.line == run.line
+                        && last.family == run.font.family
+                });
+                if !merge {
+                    tofu.push(Tofu {
+                        subtitle: subtitle.clone(),
+                        line: run.line,
+                        family: run.font.family.clone(),
+                        chars: String::new(),
+                    });
+                }
+                let last = tofu.last_mut().unwrap();
+                for c in missing {
+                    if !last.chars.contains(c) {
+                        last.chars.push(c);
+                    }
+                }
+            }
+        }
+        tofu
+    }
+
+    /// 获取因为字幕不需要而跳过的字体，按名称排序
+    pub fn unused(&self) -> Vec<Unused> {
+        let mut unused = std::mem::take(&mut *self.unused.lock().unwrap());
+        unused.sort_by(|a, b| a.name.cmp(&b.name));
+        unused
+    }
+
+    /// 通过后端注册字体源 source 中名为 name 的字体，被更新版本取代或者和已加载的字体内容相同时跳过并返回 false
+    ///
+    /// 注册成功后才把字体记录为字幕选中的字体。注册前先记录字体的内容哈希，使并行加载的内容相同的字体只注册一次，注册失败时再去掉，
+    /// 之后内容相同的字体仍然会尝试加载。
+    pub fn register(&self, name: &str, source: &str, font: &FontFile) -> Result<bool> {
+        let name = display_name(name, source);
+        if self.superseded_names.lock().unwrap().contains(&name) {
+            return Ok(false);
+        }
+        let hash = match font.content_hash() {
+            Ok(hash) => Some(hash),
+            Err(err) => {
+                debug!("Unable to hash \"{name}\": {err}");
+                None
+            }
+        };
+        if let Some(hash) = hash {
+            let original = match self.loaded.lock().unwrap().entry(hash) {
+                Entry::Occupied(entry) => Some(entry.get().clone()),
+                Entry::Vacant(entry) => {
+                    entry.insert(name.clone());
+                    None
+                }
+            };
+            if let Some(original) = original {
+                info!("Skipped font \"{name}\" which has the same content as \"{original}\"");
+                self.duplicates
+                    .lock()
+                    .unwrap()
+                    .push(Duplicate { name, original });
+                return Ok(false);
+            }
+        }
+        if let Err(err) = font.register(self.backend) {
+            if let Some(hash) = hash {
+                self.loaded.lock().unwrap().remove(&hash);
+            }
+            return Err(err);
+        }
+        if self.has_subtitles() {
+            let mut selected = self.selected.lock().unwrap();
+            for face in &font.faces {
+                let id = Arc::new(SelectedFace {
+                    name: name.clone(),
+                    style: face.style,
+                    chars: face.chars.clone(),
+                });
+                selected.add(id, &face.names);
+            }
+        }
+        Ok(true)
+    }
+
+    /// 获取被跳过的重复字体
+    pub fn duplicates(&self) -> Vec<Duplicate> {
+        std::mem::take(&mut *self.duplicates.lock().unwrap())
+    }
+
+    /// 检查字体源 source 中的字体，选出需要加载的字体并记录它们的版本
+    ///
+    /// 结构有问题的字体和输入了字幕时字幕不需要的字体会被跳过。
+    pub fn select_fonts(
+        &self,
+        files: Vec<(String, FontFile)>,
+        source: &str,
+    ) -> Vec<(String, FontFile)> {
+        let inspect_op = |(name, mut f): (String, FontFile)| -> Option<(String, FontFile)> {
+            match f.inspect() {
+                Ok(_) if self.is_required(&display_name(&name, source), source, &f) => {
+                    Some((name, f))
+                }
+                Ok(_) => {
+                    debug!(
+                        "Skipped font \"{name}\" from \"{source}\" which is not required by subtitles"
+                    );
+                    None
+                }
+                Err(err) => {
+                    warn!("Skipped font \"{name}\" from \"{source}\" failed to load: {err}");
+                    None
+                }
+            }
+        };
+        cfg_if! {
+            if #[cfg(feature = "parallel")] {
+                let files: Vec<_> = files.into_par_iter().filter_map(inspect_op).collect();
+            } else {
+                let files: Vec<_> = files.into_iter().filter_map(inspect_op).collect();
+            }
+        }
+        for (name, f) in &files {
+            self.offer(name, source, f);
+        }
+        files
+    }
+
+    /// 记录字体源 source 中名为 name 的字体的版本，之后由 select_versions 和其他字体比较
+    pub fn offer(&self, name: &str, source: &str, font: &FontFile) {
+        let candidate = Candidate {
+            name: display_name(name, source),
+            faces: font
+                .faces
+                .iter()
+                .map(|face| (style_key(face), face.version.clone()))
+                .collect(),
+        };
+        self.candidates.lock().unwrap().push(candidate);
+    }
+
+    /// 在所有字体源选出的字体之间比较版本，记录被更新版本取代的字体，在加载任何字体源之前调用
+    ///
+    /// 同一家族和样式只保留版本最新的字体文件，版本相同或无法读取名称的字体都会保留，
+    /// 字体集合中的每个字体都被其他文件中的更新版本取代时才会跳过整个文件。
+    pub fn select_versions(&self) {
+        let mut candidates = std::mem::take(&mut *self.candidates.lock().unwrap());
+        // 保持和文件名有关的顺序，使版本相同时的结果和日志稳定
+        candidates.sort_by(|a, b| a.name.cmp(&b.name));
+
+        // 每个家族和样式中版本最新的文件
+        let mut newest: HashMap<&(String, String), usize> = HashMap::new();
+        for (i, candidate) in candidates.iter().enumerate() {
+            for (key, version) in &candidate.faces {
+                let Some(key) = key else {
+                    continue;
+                };
+                let entry = newest.entry(key).or_insert(i);
+                if *version > version_of(&candidates[*entry], key) {
+                    *entry = i;
+                }
+            }
+        }
+
+        let mut superseded_names = self.superseded_names.lock().unwrap();
+        for candidate in &candidates {
+            let newer: Option<Vec<_>> = candidate
+                .faces
+                .iter()
+                .map(|(key, version)| {
+                    let key = key.as_ref()?;
+                    let newer = *newest.get(key)?;
+                    let newer_version = version_of(&candidates[newer], key);
+                    (newer_version > *version).then_some((newer, version, newer_version))
+                })
+                .collect();
+            let Some((newer, version, newer_version)) =
+                newer.and_then(|newer| newer.into_iter().next())
+            else {
+                continue;
+            };
+            let superseded_font = Superseded {
+                name: candidate.name.clone(),
+                version: version.clone(),
+                newer: candidates[newer].name.clone(),
+                newer_version,
+            };
+            info!(
+                "Skipped font \"{}\" [{}] superseded by \"{}\" [{}]",
+                superseded_font.name,
+                superseded_font.version,
+                superseded_font.newer,
+                superseded_font.newer_version
+            );
+            self.superseded.lock().unwrap().push(superseded_font);
+            superseded_names.insert(candidate.name.clone());
+        }
+    }
+
+    /// 获取被更新版本取代的字体
+    pub fn superseded(&self) -> Vec<Superseded> {
+        std::mem::take(&mut *self.superseded.lock().unwrap())
+    }
+}
+
+/// 在日志中显示的字体名称，包含字体源的路径
+fn display_name(name: &str, source: &str) -> String {
+    if name.starts_with(source) {
+        name.to_string()
+    } else {
+        Path::new(source).join(name).to_str().unwrap().to_string()
+    }
+}
+
+/// 用于比较版本的家族和样式名称，不区分大小写
+fn style_key(face: &Face) -> Option<(String, String)> {
+    Some((
+        face.names.family_name()?.to_lowercase(),
+        face.names.style_name().unwrap_or_default().to_lowercase(),
+    ))
+}
+
+/// 字体文件中家族和样式为 key 的字体的版本
+fn version_of(candidate: &Candidate, key: &(String, String)) -> FontVersion {
+    candidate
+        .faces
+        .iter()
+        .find(|(other, _)| other.as_ref() == Some(key))
+        .map(|(_, version)| version.clone())
+        .unwrap_or_default()
+}
+
+/// 去掉输入路径中重复的和被其他输入的文件夹包含的路径
+///
+/// 在读取和哈希字体之前根据规范化的路径判断，文件夹中的压缩包不会被遍历文件夹时加载，所以不算被包含。
+pub fn remove_overlapping(paths: Vec<String>) -> Vec<String> {
+    let canonical: Vec<Option<PathBuf>> = paths.iter().map(|p| fs::canonicalize(p).ok()).collect();
+    let dirs: Vec<(String, PathBuf)> = paths
+        .iter()
+        .zip(&canonical)
+        .filter_map(|(p, c)| {
+            c.as_ref()
+                .filter(|c| c.is_dir())
+                .map(|c| (p.clone(), c.clone()))
+        })
+        .collect();
+    let mut kept: Vec<(String, Option<PathBuf>)> = Vec::new();
+    for (path, canonical) in paths.into_iter().zip(canonical) {
+        let Some(canonical) = canonical else {
+            // 无法规范化的路径交给之后的流程报告错误
+            kept.push((path, None));
+            continue;
+        };
+        if let Some((other, _)) = kept.iter().find(|(_, c)| c.as_ref() == Some(&canonical)) {
+            warn!("Skipped \"{path}\" which is the same as \"{other}\"");
+            continue;
+        }
+        let container = dirs
+            .iter()
+            .find(|(_, dir)| *dir != canonical && canonical.starts_with(dir));
+        if let Some((dir, _)) = container
+            && !is_archive(&canonical)
+        {
+            warn!("Skipped \"{path}\" which is inside \"{dir}\"");
+            continue;
+        }
+        kept.push((path, Some(canonical)));
+    }
+    kept.into_iter().map(|(path, _)| path).collect()
+}
+
+/// 根据文件开头的内容判断文件是否为压缩包
+fn is_archive(path: &Path) -> bool {
+    path.is_file()
+        && File::open(path)
+            .and_then(|mut f| sniff::read_head(&mut f))
+            .is_ok_and(|head| sniff::sniff(&head).is_archive())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        font_source::{BackendCall, RecordingBackend},
+        sfnt::{Font, test_font},
+    };
+    use anyhow::bail;
+    use tempdir::TempDir;
+
+    /// 在测试用的临时文件夹 dir 中写入文件，返回文件的路径
+    fn write(dir: &TempDir, name: &str, data: &[u8]) -> String {
+        let path = dir.path().join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, data).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    /// 注册路径中包含 fail 的字体时失败的后端
+    #[derive(Default)]
+    struct FailingBackend(RecordingBackend);
+
+    impl FontBackend for FailingBackend {
+        fn register(&self, path: &str) -> anyhow::Result<()> {
+            if path.contains("fail") {
+                bail!("unable to register \"{path}\"");
+            }
+            self.0.register(path)
+        }
+
+        fn unregister(&self, path: &str) -> anyhow::Result<()> {
+            self.0.unregister(path)
+        }
+
+        fn notify_changed(&self) {
+            self.0.notify_changed();
+        }
+
+        fn name(&self) -> &'static str {
+            "failing"
+        }
+    }
+
+    #[test]
+    fn duplicate_content() {
+        let temp = TempDir::new("fontloader-test").unwrap();
+        let regular = test_font("Test", "Regular", 400, "a");
+        let a = write(&temp, "a.ttf", &regular);
+        let b = write(&temp, "sub/b.ttf", &regular);
+        let bold = write(&temp, "bold.ttf", &test_font("Test", "Bold", 700, "a"));
+        let source = temp.path().to_str().unwrap();
+
+        let backend = FailingBackend::default();
+        let session = Session::new(&backend);
+        assert!(
+            session
+                .register(&a, source, &FontFile::new(a.clone()))
+                .unwrap()
+        );
+        assert!(
+            !session
+                .register(&b, source, &FontFile::new(b.clone()))
+                .unwrap()
+        );
+        assert!(
+            session
+                .register(&bold, source, &FontFile::new(bold.clone()))
+                .unwrap()
+        );
+        let duplicates = session.duplicates();
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!((&duplicates[0].name, &duplicates[0].original), (&b, &a));
+
+        // 注册失败的字体不会被当作已加载的字体
+        let failed = write(&temp, "fail.ttf", &test_font("Other", "Regular", 400, "a"));
+        let other = write(&temp, "other.ttf", &test_font("Other", "Regular", 400, "a"));
+        let failed_font = FontFile::new(failed.clone());
+        assert!(session.register(&failed, source, &failed_font).is_err());
+        assert!(
+            session
+                .register(&other, source, &FontFile::new(other.clone()))
+                .unwrap()
+        );
+        assert!(session.duplicates().is_empty());
+        assert_eq!(
+            backend.0.calls(),
+            [
+                BackendCall::Register(a),
+                BackendCall::Register(bold),
+                BackendCall::Register(other),
+            ]
+        );
+    }
+
+    /// 修改字体的 head.fontRevision
+    fn with_revision(mut data: Vec<u8>, revision: u32) -> Vec<u8> {
+        let head = Font::parse(&data, 0)
+            .unwrap()
+            .table_record(b"head")
+            .unwrap()
+            .offset as usize;
+        data[head + 4..head + 8].copy_from_slice(&revision.to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn versions_across_sources() {
+        let temp = TempDir::new("fontloader-test").unwrap();
+        let old = write(
+            &temp,
+            "a/Test.ttf",
+            &with_revision(test_font("Test", "Regular", 400, "ab"), 0x10000),
+        );
+        let new = write(
+            &temp,
+            "b/Test.ttf",
+            &with_revision(test_font("Test", "Regular", 400, "a"), 0x20000),
+        );
+        let (a, b) = (temp.path().join("a"), temp.path().join("b"));
+        let (a, b) = (a.to_str().unwrap(), b.to_str().unwrap());
+
+        let backend = RecordingBackend::new();
+        let session = Session::new(&backend);
+        let usage = FontUsage {
+            family: "Test".to_string(),
+            weight: 400,
+            italic: false,
+        };
+        session.require(
+            "ep.ass",
+            vec![FontRun {
+                line: 1,
+                font: usage,
+                text: "ab".to_string(),
+            }],
+        );
+        let old_files = session.select_fonts(vec![(old.clone(), FontFile::new(old.clone()))], a);
+        let new_files = session.select_fonts(vec![(new.clone(), FontFile::new(new.clone()))], b);
+        session.select_versions();
+        let superseded = session.superseded();
+        assert_eq!(superseded.len(), 1);
+        assert_eq!((&superseded[0].name, &superseded[0].newer), (&old, &new));
+
+        // 只有注册了的字体才会被当作字幕选中的字体
+        assert_eq!(session.missing().len(), 1);
+        for (name, f) in &old_files {
+            assert!(!session.register(name, a, f).unwrap());
+        }
+        assert_eq!(session.missing().len(), 1);
+        for (name, f) in &new_files {
+            assert!(session.register(name, b, f).unwrap());
+        }
+        assert!(session.missing().is_empty());
+        let uncovered = session.uncovered();
+        assert_eq!(uncovered.len(), 1);
+        assert_eq!(
+            (&uncovered[0].fonts[..], &uncovered[0].chars[..]),
+            (&[new.clone()][..], &['b'][..])
+        );
+        assert_eq!(backend.calls(), [BackendCall::Register(new)]);
+    }
+
+    #[test]
+    fn overlapping_paths() {
+        let temp = TempDir::new("fontloader-test").unwrap();
+        let dir = temp.path().join("fonts");
+        let font = write(&temp, "fonts/a.ttf", b"font");
+        let archive = write(&temp, "fonts/pack.zip", b"PK\x03\x04 archive");
+        let outside = write(&temp, "b.ttf", b"font");
+        let dir = dir.to_str().unwrap().to_string();
+        let same = format!("{dir}/../fonts");
+        let nested = temp.path().join("fonts/sub");
+        fs::create_dir_all(&nested).unwrap();
+        let nested = nested.to_str().unwrap().to_string();
+        let missing = temp.path().join("missing").to_str().unwrap().to_string();
+
+        let paths = vec![
+            font.clone(),
+            dir.clone(),
+            same,
+            nested,
+            archive.clone(),
+            outside.clone(),
+            outside.clone(),
+            missing.clone(),
+        ];
+        assert_eq!(remove_overlapping(paths), [dir, archive, outside, missing]);
+    }
+}
@@ -0,0 +1,157 @@
+use log::{debug, info};
+use std::{fs, path::Path};
+
+/// 默认的附带字体的位置，相对于拖入的文件所在的文件夹，多个位置用分号分隔
+///
+/// 最后一级可以使用 * 和 ? 通配符，不区分大小写，`..` 表示上一级的发布文件夹。
+const DEFAULT_PATTERNS: &str =
+    "Fonts;fonts;*.fonts.zip;*.7z;../Fonts;../fonts;../*.fonts.zip;../*.7z";
+
+/// 拖入这些扩展名的文件时查找附带的字体
+const TRIGGER_EXTENSIONS: [&str; 7] = ["mkv", "mka", "mks", "mp4", "m4v", "ass", "ssa"];
+
+/// 不是字体源的视频，只用于查找附带的字体
+const VIDEO_ONLY_EXTENSIONS: [&str; 2] = ["mp4", "m4v"];
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| extensions.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+/// 查找附带的字体时使用的位置，可以用环境变量 FONTLOADER_SIDECARS 修改，设置为空时不查找
+fn patterns() -> Vec<String> {
+    std::env::var("FONTLOADER_SIDECARS")
+        .unwrap_or_else(|_| DEFAULT_PATTERNS.to_string())
+        .split(';')
+        .map(|pattern| pattern.trim().to_string())
+        .filter(|pattern| !pattern.is_empty())
+        .collect()
+}
+
+/// 不区分大小写的通配符匹配，* 匹配任意个字符，? 匹配一个字符
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+    // 上一个 * 的位置和它匹配到的名称的位置，失配时让这个 * 多匹配一个字符
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// 查找 path 旁边附带字体的文件夹和压缩包
+fn find_sidecars(path: &Path, patterns: &[String]) -> Vec<String> {
+    let base = path.parent().unwrap_or(Path::new(""));
+    let mut found = Vec::new();
+    for pattern in patterns {
+        let pattern = pattern.replace('\\', "/");
+        let (dir_part, name) = pattern.rsplit_once('/').unwrap_or(("", &pattern));
+        // 去掉 .. 之前的一级，以免日志中出现 Subs/../Fonts 这样的路径
+        let mut dir = base.to_path_buf();
+        for component in dir_part.split('/').filter(|c| !c.is_empty()) {
+            if component == ".." && dir.file_name().is_some() {
+                dir.pop();
+            } else {
+                dir.push(component);
+            }
+        }
+        if !name.contains(['*', '?']) {
+            let candidate = dir.join(name);
+            if candidate.exists() {
+                found.push(candidate.to_str().unwrap().to_string());
+            }
+            continue;
+        }
+        let dir_or_cwd = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            &dir
+        };
+        let Ok(entries) = fs::read_dir(dir_or_cwd) else {
+            continue;
+        };
+        let mut matched: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                entry
+                    .file_name()
+                    .to_str()
+                    .is_some_and(|file_name| wildcard_match(name, file_name))
+            })
+            .map(|entry| dir.join(entry.file_name()).to_str().unwrap().to_string())
+            .filter(|candidate| Path::new(candidate) != path)
+            .collect();
+        matched.sort();
+        found.extend(matched);
+    }
+    found
+}
+
+/// 为拖入的视频和字幕加上旁边附带字体的文件夹和压缩包，并去掉只用于查找附带字体的 MP4 等视频
+///
+/// 找到的路径和输入的路径一样交给 from_path 处理，重复的路径之后会被 remove_overlapping 去掉。
+pub fn with_sidecars(paths: Vec<String>) -> Vec<String> {
+    let patterns = patterns();
+    let mut sidecars = Vec::new();
+    let mut kept = Vec::new();
+    for path in paths {
+        let p = Path::new(&path);
+        if p.is_file() && has_extension(p, &TRIGGER_EXTENSIONS) {
+            for sidecar in find_sidecars(p, &patterns) {
+                if !sidecars.contains(&sidecar) {
+                    info!("Found sidecar fonts \"{sidecar}\" for \"{path}\"");
+                    sidecars.push(sidecar);
+                }
+            }
+            if has_extension(p, &VIDEO_ONLY_EXTENSIONS) {
+                debug!("Used \"{path}\" only to find sidecar fonts");
+                continue;
+            }
+        }
+        kept.push(path);
+    }
+    kept.extend(sidecars);
+    kept
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards() {
+        for (pattern, name, expected) in [
+            ("*.fonts.zip", "[Group] Show.Fonts.zip", true),
+            ("*.fonts.zip", "fonts.zip", false),
+            ("*.7z", "Fonts.7z", true),
+            ("*.7z", "Fonts.7z.001", false),
+            ("Fonts", "fonts", true),
+            ("ep??.ass", "ep01.ass", true),
+            ("ep??.ass", "ep1.ass", false),
+            ("*a*b", "aXbXb", true),
+            ("*", "", true),
+        ] {
+            assert_eq!(wildcard_match(pattern, name), expected, "{pattern} {name}");
+        }
+    }
+}
@@ -0,0 +1,376 @@
+use crate::font_source::path_is_font;
+use log::warn;
+#[cfg(feature = "dir")]
+use std::fs::File;
+use std::{
+    io::{self, Read},
+    path::Path,
+};
+
+/// 判断文件类型时读取的文件开头的长度
+pub const SNIFF_LEN: usize = 1024;
+
+/// PFM 和 FNT 的文件头的长度
+const PFM_HEADER_LEN: usize = 117;
+const FNT_HEADER_LEN: usize = 118;
+
+/// 有明确的文件签名的字体文件扩展名，这些文件的内容必须能被识别为字体
+const STRONG_EXTENSION: [&str; 6] = ["ttf", "otf", "ttc", "otc", "woff", "woff2"];
+
+/// 根据文件开头的内容判断的文件类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentKind {
+    /// TrueType/OpenType 字体
+    Sfnt,
+    /// TTC/OTC 字体集合
+    Collection,
+    Woff,
+    Woff2,
+    /// PostScript Type 1 字体（PFB/PFA）
+    Type1,
+    /// Type 1 字体的度量文件（PFM）
+    Pfm,
+    /// NE 格式的字体资源（FON/FOT）
+    NeFont,
+    /// 原始的 Windows 位图字体（FNT）
+    Fnt,
+    Html,
+    /// ASS/SSA 字幕
+    Subtitle,
+    Executable,
+    Zip,
+    SevenZip,
+    Rar,
+    Tar,
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+    /// Matroska（MKV/MKA/MKS）等 EBML 格式的文件
+    Matroska,
+    Unknown,
+}
+
+impl ContentKind {
+    /// 是否为有明确签名的字体
+    pub fn is_font(&self) -> bool {
+        matches!(
+            self,
+            Self::Sfnt | Self::Collection | Self::Woff | Self::Woff2 | Self::Type1 | Self::NeFont
+        )
+    }
+
+    /// 类型的名称，主要用于日志输出
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Sfnt => "TrueType/OpenType",
+            Self::Collection => "TrueType/OpenType collection",
+            Self::Woff => "WOFF",
+            Self::Woff2 => "WOFF2",
+            Self::Type1 => "Type 1",
+            Self::Pfm => "PFM",
+            Self::NeFont => "NE font resource",
+            Self::Fnt => "FNT",
+            Self::Html => "HTML",
+            Self::Subtitle => "ASS/SSA subtitle",
+            Self::Executable => "executable",
+            Self::Zip => "ZIP",
+            Self::SevenZip => "7z",
+            Self::Rar => "RAR",
+            Self::Tar => "tar",
+            Self::Gzip => "gzip",
+            Self::Bzip2 => "bzip2",
+            Self::Xz => "xz",
+            Self::Zstd => "zstd",
+            Self::Matroska => "Matroska",
+            Self::Unknown => "unknown",
+        }
+    }
+
+    /// 是否为压缩包
+    pub fn is_archive(&self) -> bool {
+        matches!(
+            self,
+            Self::Zip
+                | Self::SevenZip
+                | Self::Rar
+                | Self::Tar
+                | Self::Gzip
+                | Self::Bzip2
+                | Self::Xz
+                | Self::Zstd
+                | Self::Matroska
+        )
+    }
+
+    /// 是否为明确不是字体的文件
+    pub fn is_not_font(&self) -> bool {
+        !self.is_font() && !matches!(self, Self::Pfm | Self::Fnt | Self::Unknown)
+    }
+}
+
+/// 根据文件开头的内容判断文件类型
+pub fn sniff(head: &[u8]) -> ContentKind {
+    let u16_le = |offset: usize| {
+        head.get(offset..offset + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+    };
+    match head {
+        [0x00, 0x01, 0x00, 0x00, ..]
+        | [b'O', b'T', b'T', b'O', ..]
+        | [b't', b'r', b'u', b'e', ..] => {
+            return ContentKind::Sfnt;
+        }
+        [b't', b't', b'c', b'f', ..] => return ContentKind::Collection,
+        [b'w', b'O', b'F', b'F', ..] => return ContentKind::Woff,
+        [b'w', b'O', b'F', b'2', ..] => return ContentKind::Woff2,
+        [0x80, 0x01, ..] => return ContentKind::Type1,
+        [b'P', b'K', 0x03, 0x04, ..] | [b'P', b'K', 0x05, 0x06, ..] => return ContentKind::Zip,
+        [b'7', b'z', 0xBC, 0xAF, 0x27, 0x1C, ..] => return ContentKind::SevenZip,
+        [b'R', b'a', b'r', b'!', 0x1A, 0x07, ..] => return ContentKind::Rar,
+        [0x1F, 0x8B, ..] => return ContentKind::Gzip,
+        [b'B', b'Z', b'h', ..] => return ContentKind::Bzip2,
+        [0xFD, b'7', b'z', b'X', b'Z', 0x00, ..] => return ContentKind::Xz,
+        [0x28, 0xB5, 0x2F, 0xFD, ..] => return ContentKind::Zstd,
+        [0x1A, 0x45, 0xDF, 0xA3, ..] => return ContentKind::Matroska,
+        [b'M', b'Z', ..] => {
+            // e_lfanew 指向的是 NE 头时才是字体资源，PE 头是普通的可执行文件
+            return match u16_le(0x3C)
+                .and_then(|offset| head.get(offset as usize..offset as usize + 2))
+            {
+                Some(b"NE") => ContentKind::NeFont,
+                Some(b"PE") => ContentKind::Executable,
+                _ => ContentKind::Unknown,
+            };
+        }
+        _ => {}
+    }
+    if head.get(257..262) == Some(b"ustar") {
+        return ContentKind::Tar;
+    }
+    if head.starts_with(b"%!PS-AdobeFont") || head.starts_with(b"%!FontType1") {
+        return ContentKind::Type1;
+    }
+
+    // UTF-16 编码的字幕只需要判断开头的 ASCII 字符
+    let text: Vec<u8> = match head {
+        [0xFF, 0xFE, rest @ ..] => rest.iter().step_by(2).copied().collect(),
+        [0xFE, 0xFF, rest @ ..] => rest.iter().skip(1).step_by(2).copied().collect(),
+        _ => head.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(head).to_vec(),
+    };
+    let text = &text[text
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(text.len())..];
+    let text = text[..text.len().min(16)].to_ascii_lowercase();
+    if text.starts_with(b"<!doctype html")
+        || text.starts_with(b"<html")
+        || text.starts_with(b"<head")
+    {
+        return ContentKind::Html;
+    }
+    if text.starts_with(b"[script info]") {
+        return ContentKind::Subtitle;
+    }
+
+    // PFM 的 dfVersion 是 0x0100，FNT 的 dfVersion 是 0x0200 或 0x0300，比文件头短的文件不是这两种格式
+    match u16_le(0) {
+        Some(0x0100) if head.len() >= PFM_HEADER_LEN => ContentKind::Pfm,
+        Some(0x0200 | 0x0300) if head.len() >= FNT_HEADER_LEN => ContentKind::Fnt,
+        _ => ContentKind::Unknown,
+    }
+}
+
+/// 读取最多 SNIFF_LEN 字节的文件开头
+pub fn read_head(reader: &mut (impl Read + ?Sized)) -> io::Result<Vec<u8>> {
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    reader.take(SNIFF_LEN as u64).read_to_end(&mut head)?;
+    Ok(head)
+}
+
+/// 根据扩展名和文件开头的内容检查文件是否为可以加载的字体
+///
+/// 有字体扩展名时内容不能明显是其他类型的文件，没有字体扩展名时内容必须能被识别为字体。
+pub fn is_font(path: &Path, head: &[u8]) -> bool {
+    let kind = sniff(head);
+    let path_str = path.to_str().unwrap();
+    if matches!(kind, ContentKind::Woff | ContentKind::Woff2) && !cfg!(feature = "woff") {
+        warn!(
+            "Skipped \"{path_str}\" which is a {} font and cannot be loaded directly",
+            kind.name()
+        );
+        return false;
+    }
+    if kind.is_font() {
+        return true;
+    }
+    if !path_is_font(path) {
+        return false;
+    }
+    let strong = path
+        .extension()
+        .map(|ext| STRONG_EXTENSION.contains(&ext.to_str().unwrap().to_ascii_lowercase().as_str()))
+        .unwrap_or(false);
+    if kind.is_not_font() {
+        warn!(
+            "Skipped \"{path_str}\" which looks like {} content instead of a font",
+            kind.name()
+        );
+        return false;
+    }
+    if strong {
+        warn!("Skipped \"{path_str}\" which does not have a valid font signature");
+        return false;
+    }
+    true
+}
+
+/// 读取文件开头并检查文件是否为可以加载的字体，是字体时返回文件类型
+#[cfg(feature = "dir")]
+pub fn file_font_kind(path: &Path) -> Option<ContentKind> {
+    match File::open(path).and_then(|mut f| read_head(&mut f)) {
+        Ok(head) => is_font(path, &head).then(|| sniff(&head)),
+        Err(err) => {
+            warn!("Unable to read \"{}\": {err}", path.to_str().unwrap());
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 在 offset 处放入 data 的文件开头
+    fn at(offset: usize, data: &[u8]) -> Vec<u8> {
+        let mut head = vec![0; offset];
+        head.extend_from_slice(data);
+        head
+    }
+
+    /// 以 dfVersion 和 dfSize 开头的 PFM 或 FNT 文件头
+    fn header(version: u16) -> Vec<u8> {
+        let mut head = version.to_le_bytes().to_vec();
+        head.extend_from_slice(&(FNT_HEADER_LEN as u32).to_le_bytes());
+        head.resize(FNT_HEADER_LEN, 0);
+        head
+    }
+
+    /// e_lfanew 指向 signature 的 MZ 文件开头
+    fn mz(signature: &[u8; 2]) -> Vec<u8> {
+        let mut head = at(0x80, signature);
+        head[0..2].copy_from_slice(b"MZ");
+        head[0x3C] = 0x80;
+        head
+    }
+
+    #[test]
+    fn signatures() {
+        for (head, kind) in [
+            (b"\x00\x01\x00\x00\x00\x10".to_vec(), ContentKind::Sfnt),
+            (b"OTTO\x00\x0A".to_vec(), ContentKind::Sfnt),
+            (b"true\x00\x0A".to_vec(), ContentKind::Sfnt),
+            (b"ttcf\x00\x01\x00\x00".to_vec(), ContentKind::Collection),
+            (b"wOFF\x00\x01\x00\x00".to_vec(), ContentKind::Woff),
+            (b"wOF2\x00\x01\x00\x00".to_vec(), ContentKind::Woff2),
+            (b"\x80\x01\x10\x00\x00\x00%!PS".to_vec(), ContentKind::Type1),
+            (b"%!PS-AdobeFont-1.0: Test".to_vec(), ContentKind::Type1),
+            (header(0x0100), ContentKind::Pfm),
+            (header(0x0200), ContentKind::Fnt),
+            (header(0x0300), ContentKind::Fnt),
+            (mz(b"NE"), ContentKind::NeFont),
+            (mz(b"PE"), ContentKind::Executable),
+            (b"PK\x03\x04\x14\x00".to_vec(), ContentKind::Zip),
+            (b"PK\x05\x06\x00\x00".to_vec(), ContentKind::Zip),
+            (
+                b"7z\xBC\xAF\x27\x1C\x00\x04".to_vec(),
+                ContentKind::SevenZip,
+            ),
+            (b"Rar!\x1A\x07\x01\x00".to_vec(), ContentKind::Rar),
+            (at(257, b"ustar\x0000"), ContentKind::Tar),
+            (b"\x1F\x8B\x08\x00".to_vec(), ContentKind::Gzip),
+            (b"BZh91AY".to_vec(), ContentKind::Bzip2),
+            (b"\xFD7zXZ\x00\x00".to_vec(), ContentKind::Xz),
+            (b"\x28\xB5\x2F\xFD\x00".to_vec(), ContentKind::Zstd),
+            (b"\x1A\x45\xDF\xA3\x01".to_vec(), ContentKind::Matroska),
+            (b"\n  <!DOCTYPE html><html>".to_vec(), ContentKind::Html),
+            (b"<HTML><body>".to_vec(), ContentKind::Html),
+            (
+                b"\xEF\xBB\xBF[Script Info]\r\n".to_vec(),
+                ContentKind::Subtitle,
+            ),
+            (
+                "[Script Info]"
+                    .encode_utf16()
+                    .fold(vec![0xFF, 0xFE], |mut head, unit| {
+                        head.extend_from_slice(&unit.to_le_bytes());
+                        head
+                    }),
+                ContentKind::Subtitle,
+            ),
+            (b"plain text".to_vec(), ContentKind::Unknown),
+        ] {
+            assert_eq!(sniff(&head), kind, "{head:02X?}");
+        }
+    }
+
+    #[test]
+    fn truncated_heads() {
+        for head in [
+            &b""[..],
+            b"\x00",
+            b"\x00\x01\x00",
+            b"ttc",
+            b"wOF",
+            b"\x80",
+            b"PK\x03",
+            b"7z\xBC\xAF",
+            b"Rar!",
+            b"\x1F",
+            b"\x1A\x45\xDF",
+            // e_lfanew 不在读取的范围内
+            b"MZ\x90\x00",
+            // 比文件头短的 PFM 和 FNT
+            &header(0x0100)[..PFM_HEADER_LEN - 1],
+            b"\x00\x03\x10\x00",
+        ] {
+            assert_eq!(sniff(head), ContentKind::Unknown, "{head:02X?}");
+            assert!(!is_font(Path::new("font.bin"), head));
+        }
+        // 只有 257 字节以内的 tar 文件无法识别
+        assert_eq!(sniff(&at(257, b"usta")), ContentKind::Unknown);
+    }
+
+    #[test]
+    fn extensions_and_content() {
+        let ttf = b"\x00\x01\x00\x00\x00\x10".to_vec();
+        let zip = b"PK\x03\x04\x14\x00".to_vec();
+        let html = b"<!DOCTYPE html>".to_vec();
+        for (name, head, expected) in [
+            // 内容是字体时不需要字体扩展名
+            ("font.bin", &ttf, true),
+            ("font", &ttf, true),
+            // 改名为 .bin 或 .ttf 的压缩包不是字体
+            ("fonts.bin", &zip, false),
+            ("fonts.ttf", &zip, false),
+            ("fonts.bin", &b"7z\xBC\xAF\x27\x1C".to_vec(), false),
+            ("fonts.bin", &b"Rar!\x1A\x07\x00".to_vec(), false),
+            ("fonts.bin", &at(257, b"ustar"), false),
+            // 保存成 .ttf 的网页不是字体
+            ("font.ttf", &html, false),
+            ("font.fon", &html, false),
+            // 有明确签名的扩展名必须有对应的签名，没有签名的格式只要内容不是其他类型
+            ("font.ttf", &b"\x00\x00\x00\x00".to_vec(), false),
+            ("font.pfm", &header(0x0100), true),
+            ("font.fnt", &header(0x0300), true),
+            ("font.FOT", &mz(b"NE"), true),
+            ("font.fon", &mz(b"PE"), false),
+            ("font.txt", &header(0x0100), false),
+        ] {
+            assert_eq!(is_font(Path::new(name), head), expected, "{name}");
+        }
+        for kind in [ContentKind::Zip, ContentKind::Tar, ContentKind::Matroska] {
+            assert!(kind.is_archive() && kind.is_not_font());
+        }
+    }
+}
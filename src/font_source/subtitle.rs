@@ -0,0 +1,208 @@
+#[cfg(feature = "ass-fonts")]
+use crate::font_source::{TEMPDIR_PREFIX, extract_if_font};
+use crate::{
+    font_source::{FontBackend, FontFile, FontSource, Session, group_companions},
+    subtitle::Subtitle,
+};
+use anyhow::Result;
+use cfg_if::cfg_if;
+use log::{debug, info, warn};
+#[cfg(feature = "parallel")]
+use rayon::iter::{
+    IntoParallelIterator, IntoParallelRefIterator, ParallelExtend, ParallelIterator,
+};
+use std::path::Path;
+#[cfg(feature = "ass-fonts")]
+use tempdir::TempDir;
+
+/// 根据扩展名检查文件是否为 ASS/SSA 字幕
+#[cfg(any(feature = "dir", feature = "subset"))]
+pub fn path_is_subtitle(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| {
+        matches!(
+            ext.to_str().unwrap().to_ascii_lowercase().as_str(),
+            "ass" | "ssa"
+        )
+    })
+}
+
+/// 读取字幕并把其中使用的字体记录到会话中
+pub fn require_subtitle(path: &Path, session: &Session) -> Result<Subtitle> {
+    let subtitle = Subtitle::read(path)?;
+    require_subtitle_fonts(path.to_str().unwrap(), &subtitle, session);
+    Ok(subtitle)
+}
+
+/// 把名为 name 的字幕中使用的字体记录到会话中
+pub fn require_subtitle_fonts(name: &str, subtitle: &Subtitle, session: &Session) {
+    let usages = subtitle.font_usages();
+    info!("Subtitle \"{name}\" uses {} fonts", usages.len());
+    for usage in &usages {
+        debug!("Subtitle \"{name}\" uses font {usage}");
+    }
+    session.require(name, subtitle.font_runs());
+}
+
+/// ASS/SSA 字幕，让其他字体源只加载字幕需要的字体
+///
+/// 字幕的 [Fonts] 部分内嵌的字体会像压缩包中的字体一样解压到临时文件夹后加载。
+pub struct SubtitleFile {
+    path: String,
+    subtitle: Option<Subtitle>,
+    #[cfg(feature = "ass-fonts")]
+    extract: TempDir,
+    /// prepare 选出的需要加载的字体
+    found: Vec<(String, FontFile)>,
+    loaded: Vec<(String, FontFile)>,
+}
+
+impl SubtitleFile {
+    pub fn new(path: String) -> Self {
+        Self {
+            path,
+            subtitle: None,
+            #[cfg(feature = "ass-fonts")]
+            extract: TempDir::new(TEMPDIR_PREFIX).unwrap(),
+            found: Vec::new(),
+            loaded: Vec::new(),
+        }
+    }
+
+    /// 解码内嵌的字体并解压到临时文件夹
+    #[cfg(feature = "ass-fonts")]
+    fn extract(&self, subtitle: &Subtitle) -> Result<Vec<(String, FontFile)>> {
+        let mut extracted = vec![];
+        for (i, font) in subtitle.fonts.iter().enumerate() {
+            let data = match font.decode() {
+                Ok(data) => data,
+                Err(err) => {
+                    warn!(
+                        "Skipped font \"{}\" embedded in \"{}\" failed to decode: {err}",
+                        font.name, self.path
+                    );
+                    continue;
+                }
+            };
+            // 文件名来自字幕，只使用其中的文件名部分，不同的字体也可能使用同一个文件名
+            let file_name = Path::new(&font.name)
+                .file_name()
+                .map_or("font".into(), |name| name.to_os_string());
+            let extract = self.extract.path().join(i.to_string()).join(file_name);
+            let Some(extract) = extract_if_font(Path::new(&font.name), &mut &data[..], &extract)?
+            else {
+                debug!(
+                    "Skipped \"{}\" embedded in \"{}\" which is not a font",
+                    font.name, self.path
+                );
+                continue;
+            };
+            debug!(
+                "Found font \"{}\" embedded in \"{}\" and extracted to \"{}\"",
+                font.name,
+                self.path,
+                extract.to_str().unwrap(),
+            );
+            extracted.push((
+                font.name.clone(),
+                FontFile::new(extract.to_str().unwrap().to_string()),
+            ));
+        }
+        Ok(extracted)
+    }
+
+    #[cfg(not(feature = "ass-fonts"))]
+    fn extract(&self, subtitle: &Subtitle) -> Result<Vec<(String, FontFile)>> {
+        warn!(
+            "Skipped {} fonts embedded in \"{}\" which are not supported in this build",
+            subtitle.fonts.len(),
+            self.path
+        );
+        Ok(Vec::new())
+    }
+}
+
+impl FontSource for SubtitleFile {
+    fn require_fonts(&mut self, session: &Session) -> Result<()> {
+        self.subtitle = Some(require_subtitle(Path::new(&self.path), session)?);
+        Ok(())
+    }
+
+    fn prepare(&mut self, session: &Session) -> Result<()> {
+        let Some(subtitle) = self.subtitle.take() else {
+            return Ok(());
+        };
+        if subtitle.fonts.is_empty() {
+            return Ok(());
+        }
+        debug!("Extracting fonts embedded in subtitle \"{}\"", self.path);
+        let extracted = self.extract(&subtitle)?;
+        let extracted = group_companions(extracted, &self.path);
+        session.attach(&self.path);
+        self.found = session.select_fonts(extracted, &self.path);
+        Ok(())
+    }
+
+    fn load(&mut self, session: &Session) -> Result<()> {
+        let extracted = std::mem::take(&mut self.found);
+
+        let extracted_op = |(name, f): (String, FontFile)| -> Option<(String, FontFile)> {
+            match session.register(&name, &self.path, &f) {
+                Ok(false) => None,
+                Ok(true) => {
+                    info!(
+                        "Extracted font \"{}\" [{}] from \"{}\" and loaded",
+                        name,
+                        f.describe(),
+                        self.path
+                    );
+                    Some((name, f))
+                }
+                Err(err) => {
+                    warn!(
+                        "Skipped font \"{}\" from \"{}\" failed to load: {}",
+                        name, self.path, err
+                    );
+                    None
+                }
+            }
+        };
+        cfg_if! {
+            if #[cfg(feature = "parallel")] {
+                let iter = extracted.into_par_iter();
+                self.loaded.par_extend(iter.filter_map(extracted_op));
+            } else {
+                let iter = extracted.into_iter();
+                self.loaded.extend(iter.filter_map(extracted_op));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn unload(&self, backend: &dyn FontBackend) {
+        cfg_if! {
+            if #[cfg(feature = "parallel")] {
+                let iter = self.loaded.par_iter();
+            } else {
+                let iter = self.loaded.iter();
+            }
+        }
+        iter.for_each(|(name, f)| {
+            f.unload(backend);
+            info!(
+                "Unloaded extracted font \"{}\" [{}] from \"{}\"",
+                name,
+                f.describe(),
+                self.path
+            );
+        });
+    }
+
+    fn get_path(&self) -> &String {
+        &self.path
+    }
+
+    fn get_fonts(&self) -> Vec<&FontFile> {
+        self.loaded.iter().map(|(_, f)| f).collect()
+    }
+}
@@ -0,0 +1,544 @@
+use crate::{
+    font_source::walk_files,
+    library::{
+        cache_home,
+        index::{escape, split},
+        modified,
+    },
+};
+use anyhow::{Result, bail};
+use log::{debug, info, warn};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    env,
+    fs::{self, File, TryLockError},
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex},
+    time::SystemTime,
+};
+use xxhash_rust::xxh3::{Xxh3, xxh3_128};
+
+/// 清单文件开头的格式名称和版本
+const HEADER: &str = "fontloader-cache";
+const VERSION: u32 = 1;
+
+/// 每个压缩包的缓存中的清单文件，修改时间是最后一次使用缓存的时间
+const MANIFEST: &str = "members.tsv";
+
+/// 每个压缩包的缓存中的锁文件，使用缓存的运行在结束前一直持有共享锁
+const LOCK: &str = "in-use";
+
+/// 这次运行中使用过的缓存的锁文件，字体可能还在加载中，任何运行清理时都不会删除
+static LOCKS: LazyLock<Mutex<HashMap<PathBuf, File>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// 解压缓存的文件夹，默认是 cache_home 中的 extracted 文件夹，可以用环境变量 FONTLOADER_CACHE_DIR 修改
+fn cache_dir() -> Result<PathBuf> {
+    if let Some(dir) = env::var_os("FONTLOADER_CACHE_DIR").filter(|dir| !dir.is_empty()) {
+        return Ok(PathBuf::from(dir));
+    }
+    match cache_home() {
+        Some(home) => Ok(home.join("extracted")),
+        None => {
+            bail!("unable to find the cache folder, set FONTLOADER_CACHE_DIR to the cache folder")
+        }
+    }
+}
+
+/// 解压缓存的大小上限（字节），用环境变量 FONTLOADER_CACHE 设置为 MiB 数时启用缓存，没有设置时返回 None
+fn limit() -> Option<u64> {
+    let value = env::var("FONTLOADER_CACHE").ok()?;
+    match value.trim().parse::<u64>() {
+        Ok(0) => None,
+        Ok(mib) => Some(mib.saturating_mul(1024 * 1024)),
+        Err(_) => {
+            warn!("Invalid FONTLOADER_CACHE \"{value}\", the cache size should be in MiB");
+            None
+        }
+    }
+}
+
+/// 一个压缩包在解压缓存中的内容
+pub struct CachedArchive {
+    /// 压缩包的路径，只用于日志
+    archive: String,
+    /// 以压缩包内容的哈希命名的文件夹，成员按在压缩包中的路径解压到其中
+    dir: PathBuf,
+    /// 是否解压过压缩包中的所有字体，此时清单中没有的成员都不是字体
+    complete: bool,
+    /// 已经解压的字体在压缩包中的路径和相对于 dir 的解压后的路径，WOFF/WOFF2 解码后的扩展名会不同
+    members: BTreeMap<String, String>,
+}
+
+/// 启用了解压缓存时读取压缩包在缓存中的内容，没有启用或者无法读取压缩包时返回 None
+///
+/// 缓存按压缩包的内容哈希区分，重命名或者移动过的压缩包仍然可以使用缓存，修改过的压缩包不会使用旧的缓存。
+pub fn open(archive: &str) -> Option<CachedArchive> {
+    limit()?;
+    let cache = match cache_dir() {
+        Ok(cache) => cache,
+        Err(err) => {
+            warn!("{err:#}");
+            return None;
+        }
+    };
+    let hash = match archive_hash(&cache, Path::new(archive)) {
+        Ok(hash) => hash,
+        Err(err) => {
+            debug!("Unable to hash \"{archive}\" for the cache: {err}");
+            return None;
+        }
+    };
+    let dir = cache.join(format!("{hash:032x}"));
+    if let Err(err) = lock(&dir) {
+        debug!(
+            "Unable to lock the cache \"{}\": {err}",
+            dir.to_str().unwrap()
+        );
+        return None;
+    }
+    let mut cached = CachedArchive {
+        archive: archive.to_string(),
+        dir,
+        complete: false,
+        members: BTreeMap::new(),
+    };
+    if let Ok(text) = fs::read_to_string(cached.dir.join(MANIFEST)) {
+        cached.parse(&text);
+    }
+    Some(cached)
+}
+
+impl CachedArchive {
+    /// 解压到缓存时使用的文件夹
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// 缓存中有需要的所有字体时返回字体在压缩包中的路径和缓存中的路径，members 为 None 时需要所有字体
+    pub fn lookup(&self, members: Option<&HashSet<String>>) -> Option<Vec<(String, PathBuf)>> {
+        let covered = match members {
+            None => self.complete,
+            Some(members) => {
+                self.complete
+                    || members
+                        .iter()
+                        .all(|member| self.members.contains_key(member))
+            }
+        };
+        if !covered {
+            return None;
+        }
+        let mut hit = Vec::new();
+        for (member, extracted) in &self.members {
+            if members.is_some_and(|members| !members.contains(member)) {
+                continue;
+            }
+            let path = self.dir.join(extracted);
+            if !path.is_file() {
+                debug!(
+                    "Cached font \"{}\" no longer exists",
+                    path.to_str().unwrap()
+                );
+                return None;
+            }
+            hit.push((member.clone(), path));
+        }
+        // 更新清单的修改时间作为最后一次使用的时间
+        if let Err(err) = File::options()
+            .append(true)
+            .open(self.dir.join(MANIFEST))
+            .and_then(|manifest| manifest.set_modified(SystemTime::now()))
+        {
+            debug!("Unable to update the cache usage time: {err}");
+        }
+        info!(
+            "Using {} cached fonts of \"{}\" without extracting",
+            hit.len(),
+            self.archive
+        );
+        Some(hit)
+    }
+
+    /// 记录解压到缓存中的字体，complete 表示解压了压缩包中的所有字体
+    pub fn store(&mut self, extracted: &[(String, PathBuf)], complete: bool) -> Result<()> {
+        for (member, path) in extracted {
+            let relative = path.strip_prefix(&self.dir)?;
+            self.members
+                .insert(member.clone(), relative.to_str().unwrap().to_string());
+        }
+        self.complete |= complete;
+        fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(MANIFEST);
+        let temp = self.dir.join(format!("{MANIFEST}.tmp"));
+        let mut out = BufWriter::new(File::create(&temp)?);
+        writeln!(out, "{HEADER}\t{VERSION}")?;
+        if self.complete {
+            writeln!(out, "complete")?;
+        }
+        for (member, extracted) in &self.members {
+            writeln!(out, "member\t{}\t{}", escape(member), escape(extracted))?;
+        }
+        out.into_inner().map_err(|err| err.into_error())?;
+        fs::rename(&temp, &path)?;
+        Ok(())
+    }
+
+    /// 读取清单，格式不对时当作没有缓存
+    fn parse(&mut self, text: &str) {
+        let mut lines = text.lines().map(split);
+        if lines.next() != Some(vec![HEADER.to_string(), VERSION.to_string()]) {
+            return;
+        }
+        for fields in lines {
+            match fields.as_slice() {
+                [kind] if kind == "complete" => self.complete = true,
+                [kind, member, extracted] if kind == "member" => {
+                    self.members.insert(member.clone(), extracted.clone());
+                }
+                _ => {
+                    self.complete = false;
+                    self.members.clear();
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// 压缩包的内容哈希，路径、大小和修改时间都和上次相同时直接使用保存在 cache 中的哈希
+///
+/// 哈希保存在以压缩包的绝对路径的哈希命名的 .key 文件中，只有第一次使用和修改过的压缩包才需要读取整个文件。
+fn archive_hash(cache: &Path, archive: &Path) -> io::Result<u128> {
+    let metadata = fs::metadata(archive)?;
+    let (size, mtime) = (metadata.len(), modified(&metadata));
+    let absolute = std::path::absolute(archive)?;
+    let key = cache.join(format!(
+        "{:032x}.key",
+        xxh3_128(absolute.as_os_str().as_encoded_bytes())
+    ));
+    if let Ok(text) = fs::read_to_string(&key)
+        && let [key_size, key_mtime, hash] = split(text.trim_end()).as_slice()
+        && *key_size == size.to_string()
+        && *key_mtime == mtime.to_string()
+        && let Ok(hash) = u128::from_str_radix(hash, 16)
+    {
+        return Ok(hash);
+    }
+    let hash = hash_file(archive)?;
+    debug!("Hashed \"{}\" for the cache", archive.to_str().unwrap());
+    let temp = key.with_extension(format!("key.{}.tmp", std::process::id()));
+    if let Err(err) = fs::create_dir_all(cache)
+        .and_then(|_| fs::write(&temp, format!("{size}\t{mtime}\t{hash:032x}\n")))
+        .and_then(|_| fs::rename(&temp, &key))
+    {
+        debug!(
+            "Unable to save the hash of \"{}\" to the cache: {err}",
+            archive.to_str().unwrap()
+        );
+    }
+    Ok(hash)
+}
+
+/// 对压缩包的缓存 dir 加共享锁并保持到运行结束，其他运行清理缓存时会跳过这个缓存
+fn lock(dir: &Path) -> io::Result<()> {
+    let mut locks = LOCKS.lock().unwrap();
+    if locks.contains_key(dir) {
+        return Ok(());
+    }
+    let path = dir.join(LOCK);
+    loop {
+        fs::create_dir_all(dir)?;
+        let file = match File::options().create(true).append(true).open(&path) {
+            Ok(file) => file,
+            // 其他运行在创建文件夹后删除了这个缓存
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+        file.lock_shared()?;
+        // 其他运行可能在加锁前删除了这个缓存，锁文件已经不在缓存中时重新创建
+        if path.exists() {
+            locks.insert(dir.to_path_buf(), file);
+            return Ok(());
+        }
+    }
+}
+
+/// 删除没有任何运行正在使用的压缩包的缓存 dir，正在使用时返回 false
+fn remove_unused(dir: &Path) -> io::Result<bool> {
+    let lock = match File::options().append(true).open(dir.join(LOCK)) {
+        Ok(lock) => lock,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            fs::remove_dir_all(dir)?;
+            return Ok(true);
+        }
+        Err(err) => return Err(err),
+    };
+    // 删除时一直持有排他锁，其他运行要等删除完成后才能重新创建这个缓存
+    match lock.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => return Ok(false),
+        Err(TryLockError::Error(err)) => return Err(err),
+    }
+    fs::remove_dir_all(dir)?;
+    Ok(true)
+}
+
+/// 文件内容的哈希，逐块读取，不会把整个压缩包读入内存
+fn hash_file(path: &Path) -> io::Result<u128> {
+    let mut file = File::open(path)?;
+    let mut hasher = Xxh3::new();
+    let mut buffer = vec![0; 1024 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher.digest128())
+}
+
+/// 缓存中的一个压缩包
+struct Entry {
+    dir: PathBuf,
+    files: usize,
+    size: u64,
+    /// 最后一次使用的时间，没有清单时是文件夹的修改时间
+    used: SystemTime,
+}
+
+/// 缓存中的所有压缩包，按最后一次使用的时间排序
+fn entries(dir: &Path) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    if !dir.exists() {
+        return Ok(entries);
+    }
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let path = entry.path();
+        let used = fs::metadata(path.join(MANIFEST))
+            .or_else(|_| entry.metadata())
+            .and_then(|metadata| metadata.modified())
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        let (mut files, mut size) = (0, 0);
+        for file in walk_files(path.to_str().unwrap()) {
+            if file.path() == path.join(LOCK) {
+                continue;
+            }
+            files += 1;
+            size += file.metadata().map_or(0, |metadata| metadata.len());
+        }
+        entries.push(Entry {
+            dir: path,
+            files,
+            size,
+            used,
+        });
+    }
+    entries.sort_by_key(|entry| entry.used);
+    Ok(entries)
+}
+
+/// 以 MiB 为单位显示的大小
+fn mib(size: u64) -> String {
+    format!("{:.1} MiB", size as f64 / (1024.0 * 1024.0))
+}
+
+/// 缓存超过大小上限时按最近最少使用的顺序删除压缩包的缓存，正在被任何运行使用的缓存不会删除
+pub fn evict() {
+    let Some(limit) = limit() else {
+        return;
+    };
+    let Ok(dir) = cache_dir() else {
+        return;
+    };
+    let entries = match entries(&dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            warn!(
+                "Unable to read the cache \"{}\": {err}",
+                dir.to_str().unwrap()
+            );
+            return;
+        }
+    };
+    let mut total: u64 = entries.iter().map(|entry| entry.size).sum();
+    let (mut evicted, mut freed) = (0, 0);
+    for entry in entries {
+        if total <= limit {
+            break;
+        }
+        match remove_unused(&entry.dir) {
+            Ok(false) => debug!(
+                "Skipped evicting cache \"{}\" which is in use",
+                entry.dir.to_str().unwrap()
+            ),
+            Ok(true) => {
+                debug!("Evicted cache \"{}\"", entry.dir.to_str().unwrap());
+                total -= entry.size;
+                evicted += 1;
+                freed += entry.size;
+            }
+            Err(err) => warn!(
+                "Unable to evict cache \"{}\": {err}",
+                entry.dir.to_str().unwrap()
+            ),
+        }
+    }
+    if evicted > 0 {
+        info!(
+            "Evicted {evicted} least recently used archives ({}) from the cache",
+            mib(freed)
+        );
+    }
+}
+
+/// 显示缓存的位置、大小和使用情况
+pub fn stats() -> Result<()> {
+    let dir = cache_dir()?;
+    let entries = entries(&dir)?;
+    info!("Cache folder \"{}\"", dir.to_str().unwrap());
+    match limit() {
+        Some(limit) => info!("Cache is enabled with a limit of {}", mib(limit)),
+        None => {
+            info!("Cache is disabled, set FONTLOADER_CACHE to the size limit in MiB to enable it")
+        }
+    }
+    info!(
+        "Cached {} files of {} archives in {}",
+        entries.iter().map(|entry| entry.files).sum::<usize>(),
+        entries.len(),
+        mib(entries.iter().map(|entry| entry.size).sum())
+    );
+    for entry in entries.iter().rev() {
+        let used = entry
+            .used
+            .elapsed()
+            .map_or(0, |elapsed| elapsed.as_secs() / 3600);
+        info!(
+            "Cached archive {}: {} files, {}, used {used} hours ago",
+            entry.dir.file_name().unwrap().to_str().unwrap(),
+            entry.files,
+            mib(entry.size)
+        );
+    }
+    Ok(())
+}
+
+/// 删除缓存中的所有内容，正在被其他运行使用的缓存除外
+pub fn clean() -> Result<()> {
+    let dir = cache_dir()?;
+    let (mut removed, mut size, mut skipped) = (0, 0, 0);
+    for entry in entries(&dir)? {
+        if remove_unused(&entry.dir)? {
+            removed += 1;
+            size += entry.size;
+        } else {
+            skipped += 1;
+        }
+    }
+    if dir.exists() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "key") {
+                fs::remove_file(path)?;
+            }
+        }
+    }
+    info!(
+        "Removed {removed} cached archives ({}) from \"{}\"",
+        mib(size),
+        dir.to_str().unwrap()
+    );
+    if skipped > 0 {
+        warn!("Skipped {skipped} cached archives in use by other runs");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn manifest() {
+        let mut cached = CachedArchive {
+            archive: "Pack.7z".to_string(),
+            dir: PathBuf::from("cache"),
+            complete: false,
+            members: BTreeMap::new(),
+        };
+        cached.parse("fontloader-cache\t1\nmember\tPack/A.ttf\tPack/A.ttf\n");
+        assert!(!cached.complete);
+        assert!(cached.lookup(None).is_none());
+        let wanted = HashSet::from(["Pack/B.ttf".to_string()]);
+        assert!(cached.lookup(Some(&wanted)).is_none());
+
+        cached.parse("fontloader-cache\t1\ncomplete\nmember\tPack/B.woff2\tPack/B.woff2.ttf\n");
+        assert!(cached.complete);
+        assert_eq!(cached.members.len(), 2);
+        assert_eq!(cached.members["Pack/B.woff2"], "Pack/B.woff2.ttf");
+
+        cached.parse("fontloader-cache\t2\ncomplete\n");
+        assert_eq!(cached.members.len(), 2);
+        cached.parse("fontloader-cache\t1\nfile\tPack/A.ttf\n");
+        assert!(!cached.complete && cached.members.is_empty());
+    }
+
+    #[test]
+    fn archive_key() {
+        let temp = TempDir::new("fontloader-test").unwrap();
+        let cache = temp.path();
+        let archive = cache.join("Pack.zip");
+        fs::write(&archive, "first").unwrap();
+        let first = archive_hash(cache, &archive).unwrap();
+        assert_eq!(first, xxh3_128(b"first"));
+
+        // 大小和修改时间没有变化时不会重新读取内容
+        let mtime = fs::metadata(&archive).unwrap().modified().unwrap();
+        fs::write(&archive, "other").unwrap();
+        File::options()
+            .append(true)
+            .open(&archive)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+        assert_eq!(archive_hash(cache, &archive).unwrap(), first);
+
+        fs::write(&archive, "changed").unwrap();
+        assert_eq!(archive_hash(cache, &archive).unwrap(), xxh3_128(b"changed"));
+    }
+
+    #[test]
+    fn locked_entries() {
+        let temp = TempDir::new("fontloader-test").unwrap();
+        let cache = temp.path();
+        let (used, unused, old) = (cache.join("used"), cache.join("unused"), cache.join("old"));
+        lock(&used).unwrap();
+        lock(&unused).unwrap();
+        fs::write(used.join(MANIFEST), "").unwrap();
+        fs::create_dir_all(&old).unwrap();
+
+        // 其他运行持有共享锁时不会删除，没有锁文件的缓存可以直接删除
+        let other = File::open(unused.join(LOCK)).unwrap();
+        LOCKS.lock().unwrap().remove(&unused);
+        other.lock_shared().unwrap();
+        assert!(!remove_unused(&used).unwrap());
+        assert!(!remove_unused(&unused).unwrap());
+        assert!(remove_unused(&old).unwrap());
+        drop(other);
+        assert!(remove_unused(&unused).unwrap());
+        assert!(used.join(MANIFEST).exists() && !unused.exists() && !old.exists());
+
+        let entries = entries(cache).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!((entries[0].files, entries[0].size), (1, 0));
+        LOCKS.lock().unwrap().remove(&used);
+    }
+}
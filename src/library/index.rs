@@ -0,0 +1,322 @@
+use crate::sfnt::{FontNames, FontStyle, NameRecord};
+use anyhow::{Context, Result, anyhow, bail};
+use std::{
+    fs,
+    io::{BufWriter, Write},
+    path::Path,
+    str::FromStr,
+};
+
+/// 索引文件开头的格式名称和版本，格式不兼容时增加版本
+const HEADER: &str = "fontloader-index";
+const VERSION: u32 = 1;
+
+/// 字体库的索引
+///
+/// 保存为 UTF-8 的文本文件，每行是一条用制表符分隔的记录，记录的类型是第一个字段：
+///
+/// ```text
+/// fontloader-index  1
+/// root  <字体库的文件夹>
+/// file  <路径>  <大小>  <修改时间>
+/// font  <压缩包中的路径，不是压缩包时为空>  <内容哈希>
+/// face  <在字体集合中的序号>  <字重>  <是否为斜体>  <宽度>
+/// name  <platform ID>  <language ID>  <name ID>  <名称>
+/// ```
+///
+/// font 属于之前最近的 file，face 和 name 同理，字段中的反斜杠、制表符和换行符会被转义。
+#[derive(Debug, Default)]
+pub struct Index {
+    /// 扫描的字体库的文件夹
+    pub roots: Vec<String>,
+    /// 按路径排序的文件
+    pub files: Vec<IndexedFile>,
+}
+
+/// 字体库中的一个文件，可能是字体文件或包含字体的压缩包
+#[derive(Debug)]
+pub struct IndexedFile {
+    pub path: String,
+    pub size: u64,
+    /// 修改时间，从 UNIX 纪元开始的纳秒数
+    pub mtime: u64,
+    /// 文件本身或压缩包中的字体，没有字体的文件也会记录，以免每次扫描时都重新检查
+    pub fonts: Vec<IndexedFont>,
+}
+
+/// 一个字体文件，可能是压缩包中的文件
+#[derive(Debug)]
+pub struct IndexedFont {
+    /// 在压缩包中的路径，不是压缩包时为 None
+    pub member: Option<String>,
+    /// 文件内容的哈希
+    pub hash: u128,
+    /// 字体集合中会有多个，Type 1 等无法读取名称的字体为空
+    pub faces: Vec<IndexedFace>,
+}
+
+/// 字体文件中的一个字体
+#[derive(Debug)]
+pub struct IndexedFace {
+    /// 在字体集合中的序号，单个字体文件是 0
+    pub index: usize,
+    pub style: FontStyle,
+    pub names: FontNames,
+}
+
+impl Index {
+    /// 读取索引，文件不存在时返回空的索引
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let text = fs::read_to_string(path)?;
+        Self::parse(&text).with_context(|| format!("invalid index \"{}\"", path.to_str().unwrap()))
+    }
+
+    fn parse(text: &str) -> Result<Self> {
+        let mut lines = text.lines().enumerate();
+        match lines.next().map(|(_, line)| split(line)) {
+            Some(fields) if fields.len() == 2 && fields[0] == HEADER => {
+                if fields[1] != VERSION.to_string() {
+                    bail!("unsupported index version {}", fields[1]);
+                }
+            }
+            _ => bail!("not a fontloader index"),
+        }
+        let mut index = Self::default();
+        for (i, line) in lines {
+            let fields = split(line);
+            let error = || anyhow!("invalid record at line {}", i + 1);
+            match (fields[0].as_str(), fields.len()) {
+                ("root", 2) => index.roots.push(fields[1].clone()),
+                ("file", 4) => index.files.push(IndexedFile {
+                    path: fields[1].clone(),
+                    size: number(&fields[2]).ok_or_else(error)?,
+                    mtime: number(&fields[3]).ok_or_else(error)?,
+                    fonts: Vec::new(),
+                }),
+                ("font", 3) => {
+                    let file = index.files.last_mut().ok_or_else(error)?;
+                    file.fonts.push(IndexedFont {
+                        member: Some(fields[1].clone()).filter(|member| !member.is_empty()),
+                        hash: u128::from_str_radix(&fields[2], 16).map_err(|_| error())?,
+                        faces: Vec::new(),
+                    });
+                }
+                ("face", 5) => {
+                    let font = index
+                        .files
+                        .last_mut()
+                        .and_then(|file| file.fonts.last_mut())
+                        .ok_or_else(error)?;
+                    font.faces.push(IndexedFace {
+                        index: number(&fields[1]).ok_or_else(error)?,
+                        style: FontStyle {
+                            weight: number(&fields[2]).ok_or_else(error)?,
+                            italic: fields[3] == "1",
+                            width: number(&fields[4]).ok_or_else(error)?,
+                        },
+                        names: FontNames::default(),
+                    });
+                }
+                ("name", 5) => {
+                    let face = index
+                        .files
+                        .last_mut()
+                        .and_then(|file| file.fonts.last_mut())
+                        .and_then(|font| font.faces.last_mut())
+                        .ok_or_else(error)?;
+                    face.names.records.push(NameRecord {
+                        platform_id: number(&fields[1]).ok_or_else(error)?,
+                        language_id: number(&fields[2]).ok_or_else(error)?,
+                        name_id: number(&fields[3]).ok_or_else(error)?,
+                        value: fields[4].clone(),
+                    });
+                }
+                _ => return Err(error()),
+            }
+        }
+        for face in index
+            .files
+            .iter_mut()
+            .flat_map(|file| &mut file.fonts)
+            .flat_map(|font| &mut font.faces)
+        {
+            face.names = FontNames::from_records(std::mem::take(&mut face.names.records));
+        }
+        Ok(index)
+    }
+
+    /// 保存索引，先写入临时文件再替换，中断时不会留下不完整的索引
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent()
+            && !parent.exists()
+        {
+            fs::create_dir_all(parent)?;
+        }
+        let mut temp = path.as_os_str().to_os_string();
+        temp.push(".tmp");
+        let mut out = BufWriter::new(fs::File::create(&temp)?);
+        self.write(&mut out)?;
+        out.into_inner().map_err(|err| err.into_error())?;
+        fs::rename(&temp, path)?;
+        Ok(())
+    }
+
+    fn write(&self, out: &mut impl Write) -> Result<()> {
+        writeln!(out, "{HEADER}\t{VERSION}")?;
+        for root in &self.roots {
+            writeln!(out, "root\t{}", escape(root))?;
+        }
+        for file in &self.files {
+            writeln!(
+                out,
+                "file\t{}\t{}\t{}",
+                escape(&file.path),
+                file.size,
+                file.mtime
+            )?;
+            for font in &file.fonts {
+                writeln!(
+                    out,
+                    "font\t{}\t{:032x}",
+                    escape(font.member.as_deref().unwrap_or_default()),
+                    font.hash
+                )?;
+                for face in &font.faces {
+                    writeln!(
+                        out,
+                        "face\t{}\t{}\t{}\t{}",
+                        face.index,
+                        face.style.weight,
+                        u8::from(face.style.italic),
+                        face.style.width
+                    )?;
+                    for record in &face.names.records {
+                        writeln!(
+                            out,
+                            "name\t{}\t{}\t{}\t{}",
+                            record.platform_id,
+                            record.language_id,
+                            record.name_id,
+                            escape(&record.value)
+                        )?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 读取记录中的数字字段
+fn number<T: FromStr>(field: &str) -> Option<T> {
+    field.parse().ok()
+}
+
+/// 转义字段中的反斜杠、制表符和换行符
+pub(super) fn escape(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// 分隔一行记录的字段并还原转义的字符
+pub(super) fn split(line: &str) -> Vec<String> {
+    line.split('\t')
+        .map(|field| {
+            let mut unescaped = String::with_capacity(field.len());
+            let mut chars = field.chars();
+            while let Some(c) = chars.next() {
+                if c != '\\' {
+                    unescaped.push(c);
+                    continue;
+                }
+                match chars.next() {
+                    Some('t') => unescaped.push('\t'),
+                    Some('n') => unescaped.push('\n'),
+                    Some('r') => unescaped.push('\r'),
+                    Some(c) => unescaped.push(c),
+                    None => unescaped.push('\\'),
+                }
+            }
+            unescaped
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let record = |name_id, value: &str| NameRecord {
+            platform_id: 3,
+            language_id: 0x0409,
+            name_id,
+            value: value.to_string(),
+        };
+        let index = Index {
+            roots: vec!["D:\\Fonts".to_string()],
+            files: vec![IndexedFile {
+                path: "D:\\Fonts\\Pack.7z".to_string(),
+                size: 1234,
+                mtime: 1_700_000_000_123_456_789,
+                fonts: vec![
+                    IndexedFont {
+                        member: Some("Pack/A\tB.ttc".to_string()),
+                        hash: u128::MAX - 1,
+                        faces: vec![IndexedFace {
+                            index: 1,
+                            style: FontStyle {
+                                weight: 700,
+                                italic: true,
+                                width: 3,
+                            },
+                            names: FontNames::from_records(vec![
+                                record(1, "Family\\Name"),
+                                record(2, "Bold Italic"),
+                            ]),
+                        }],
+                    },
+                    IndexedFont {
+                        member: Some("Pack/readme.pfb".to_string()),
+                        hash: 1,
+                        faces: Vec::new(),
+                    },
+                ],
+            }],
+        };
+        let mut text = Vec::new();
+        index.write(&mut text).unwrap();
+        let parsed = Index::parse(&String::from_utf8(text).unwrap()).unwrap();
+        assert_eq!(parsed.roots, index.roots);
+        let file = &parsed.files[0];
+        assert_eq!(
+            (file.path.as_str(), file.size, file.mtime),
+            ("D:\\Fonts\\Pack.7z", 1234, 1_700_000_000_123_456_789)
+        );
+        assert_eq!(file.fonts.len(), 2);
+        assert_eq!(file.fonts[0].member.as_deref(), Some("Pack/A\tB.ttc"));
+        assert_eq!(file.fonts[0].hash, u128::MAX - 1);
+        assert!(file.fonts[1].faces.is_empty());
+        let face = &file.fonts[0].faces[0];
+        assert_eq!(face.index, 1);
+        assert_eq!(face.style, index.files[0].fonts[0].faces[0].style);
+        assert_eq!(face.names.family.as_deref(), Some("Family\\Name"));
+        assert_eq!(face.names.subfamily.as_deref(), Some("Bold Italic"));
+
+        assert!(Index::parse("fontloader-index\t2\n").is_err());
+        assert!(Index::parse("fontloader-index\t1\nface\t0\t400\t0\t5\n").is_err());
+    }
+}
@@ -0,0 +1,513 @@
+use crate::{
+    font_source::{
+        FontSource, Session, for_each_member,
+        sniff::{self, ContentKind},
+        walk_files,
+    },
+    sfnt,
+};
+use anyhow::{Result, bail};
+use cfg_if::cfg_if;
+use log::{debug, info, warn};
+#[cfg(feature = "parallel")]
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    env, fs,
+    path::{Path, PathBuf},
+    time::{Instant, UNIX_EPOCH},
+};
+use xxhash_rust::xxh3::xxh3_128;
+
+mod index;
+pub use index::{Index, IndexedFace, IndexedFile, IndexedFont};
+
+mod query;
+pub use query::find_families;
+
+#[cfg(feature = "cache")]
+pub mod cache;
+
+/// 保存索引和解压缓存的文件夹
+///
+/// Windows 的 %LOCALAPPDATA% 或其他系统的 $XDG_CACHE_HOME（默认为 ~/.cache）中的 fontloader 文件夹。
+pub fn cache_home() -> Option<PathBuf> {
+    let var = |name: &str| env::var_os(name).filter(|value| !value.is_empty());
+    let cache = if cfg!(windows) {
+        var("LOCALAPPDATA").map(PathBuf::from)
+    } else {
+        var("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| var("HOME").map(|home| Path::new(&home).join(".cache")))
+    };
+    cache.map(|cache| cache.join("fontloader"))
+}
+
+/// 索引文件的路径，默认保存在 cache_home 中，可以用环境变量 FONTLOADER_INDEX 修改
+pub fn index_path() -> Result<PathBuf> {
+    if let Some(path) = env::var_os("FONTLOADER_INDEX").filter(|path| !path.is_empty()) {
+        return Ok(PathBuf::from(path));
+    }
+    match cache_home() {
+        Some(home) => Ok(home.join("index.tsv")),
+        None => bail!("unable to find the cache folder, set FONTLOADER_INDEX to the index path"),
+    }
+}
+
+/// 扫描字体库的文件夹中的字体文件和字体压缩包，保存索引
+///
+/// 输入的文件夹会替换索引中保存的字体库，没有输入文件夹时重新扫描索引中保存的字体库。
+/// 大小和修改时间都没有变化的文件直接使用索引中的记录，只读取新增和修改过的文件。
+pub fn scan(roots: Vec<String>) -> Result<()> {
+    scan_index(&index_path()?, roots).map(|_| ())
+}
+
+/// 扫描前后索引的变化
+#[derive(Debug, PartialEq)]
+struct ScanChanges {
+    /// 直接使用索引中的记录的文件数
+    reused: usize,
+    /// 新增或修改过的文件数
+    updated: usize,
+    /// 从索引中删除的文件数
+    removed: usize,
+    /// 新增的家族
+    added: Vec<String>,
+    /// 删除的家族
+    deleted: Vec<String>,
+}
+
+/// 扫描字体库并保存到索引文件 path
+fn scan_index(path: &Path, roots: Vec<String>) -> Result<ScanChanges> {
+    let old = match Index::load(path) {
+        Ok(old) => old,
+        // 有输入文件夹时可以重新建立无法读取的索引
+        Err(err) if !roots.is_empty() => {
+            warn!("{err:#}, rebuilding the index");
+            Index::default()
+        }
+        Err(err) => return Err(err),
+    };
+    let roots = if roots.is_empty() {
+        if old.roots.is_empty() {
+            bail!("no library folders to scan, run scan with the folders first");
+        }
+        old.roots.clone()
+    } else {
+        let mut absolute = Vec::new();
+        for root in roots {
+            if !Path::new(&root).is_dir() {
+                bail!("library folder \"{root}\" is not a folder");
+            }
+            absolute.push(std::path::absolute(&root)?.to_str().unwrap().to_string());
+        }
+        absolute
+    };
+
+    let start = Instant::now();
+    let old_families = families(&old);
+    let mut unchanged: HashMap<String, IndexedFile> = old
+        .files
+        .into_iter()
+        .map(|file| (file.path.clone(), file))
+        .collect();
+    let mut files = Vec::new();
+    let mut changed = Vec::new();
+    for root in &roots {
+        info!("Scanning library folder \"{root}\"");
+        for entry in walk_files(root) {
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            let (size, mtime) = (metadata.len(), modified(&metadata));
+            let path_str = entry.path().to_str().unwrap();
+            match unchanged.remove(path_str) {
+                Some(file) if file.size == size && file.mtime == mtime => files.push(file),
+                _ => changed.push((entry.into_path(), size, mtime)),
+            }
+        }
+    }
+    // 剩下的记录是已经删除或者不在字体库中的文件
+    let removed = unchanged.len();
+    let reused = files.len();
+    let index_op = |(path, size, mtime): (PathBuf, u64, u64)| index_file(&path, size, mtime);
+    cfg_if! {
+        if #[cfg(feature = "parallel")] {
+            let indexed: Vec<_> = changed.into_par_iter().filter_map(index_op).collect();
+        } else {
+            let indexed: Vec<_> = changed.into_iter().filter_map(index_op).collect();
+        }
+    }
+    let updated = indexed.len();
+    files.extend(indexed);
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    files.dedup_by(|a, b| a.path == b.path);
+    let index = Index { roots, files };
+    index.save(path)?;
+
+    let fonts: Vec<&IndexedFont> = index.files.iter().flat_map(|file| &file.fonts).collect();
+    let faces = fonts.iter().map(|font| font.faces.len()).sum::<usize>();
+    let new_families = families(&index);
+    info!(
+        "Indexed {} font files ({faces} faces) of {} families in {}s to \"{}\"",
+        fonts.len(),
+        new_families.len(),
+        start.elapsed().as_secs_f64(),
+        path.to_str().unwrap()
+    );
+    info!(
+        "Reused {reused} unchanged files, read {updated} new or changed files and removed {removed} files from the index"
+    );
+    let added: Vec<String> = new_families.difference(&old_families).cloned().collect();
+    let deleted: Vec<String> = old_families.difference(&new_families).cloned().collect();
+    info!(
+        "Found {} new families and {} removed families",
+        added.len(),
+        deleted.len()
+    );
+    for family in &added {
+        info!("New family: {family}");
+    }
+    for family in &deleted {
+        info!("Removed family: {family}");
+    }
+    Ok(ScanChanges {
+        reused,
+        updated,
+        removed,
+        added,
+        deleted,
+    })
+}
+
+/// 根据字体库的索引，只从压缩包中解压字幕和 --family 需要的字体
+///
+/// 只使用大小和修改时间都和索引中的记录相同的压缩包的记录，其他压缩包仍然会解压所有字体。
+/// 无法读取名称的 Type 1 等字体无法判断是否需要，总是会被解压。
+pub fn select_members(font_sources: &mut [Box<dyn FontSource>], session: &Session) {
+    if !session.has_subtitles() {
+        return;
+    }
+    match index_path().and_then(|path| Index::load(&path)) {
+        Ok(index) => select_indexed(&index, font_sources, session),
+        Err(err) => debug!("Unable to read the library index: {err:#}"),
+    }
+}
+
+/// 根据索引 index 选出每个压缩包中需要解压的字体
+fn select_indexed(index: &Index, font_sources: &mut [Box<dyn FontSource>], session: &Session) {
+    let files: HashMap<&str, &IndexedFile> = index
+        .files
+        .iter()
+        .map(|file| (file.path.as_str(), file))
+        .collect();
+    for source in font_sources {
+        let path = source.get_path().clone();
+        let Some(file) = std::path::absolute(&path)
+            .ok()
+            .and_then(|absolute| files.get(absolute.to_str().unwrap()).copied())
+        else {
+            continue;
+        };
+        if file.fonts.iter().any(|font| font.member.is_none()) {
+            continue;
+        }
+        let Ok(metadata) = fs::metadata(&path) else {
+            continue;
+        };
+        if metadata.len() != file.size || modified(&metadata) != file.mtime {
+            debug!("Archive \"{path}\" has changed since it was indexed, extracting all fonts");
+            continue;
+        }
+        let mut members = HashSet::new();
+        for font in &file.fonts {
+            let member = font.member.as_ref().unwrap();
+            if font.faces.is_empty()
+                || font
+                    .faces
+                    .iter()
+                    .any(|face| session.requires_names(&face.names))
+            {
+                members.insert(member.clone());
+            } else {
+                debug!(
+                    "Skipped font \"{member}\" from \"{path}\" which is not required by subtitles"
+                );
+                session.skip_unused(member, &path);
+            }
+        }
+        info!(
+            "Selected {} of {} fonts in \"{path}\" to extract using the library index",
+            members.len(),
+            file.fonts.len()
+        );
+        source.select_members(members);
+    }
+}
+
+/// 索引中所有字体的家族名称
+fn families(index: &Index) -> BTreeSet<String> {
+    index
+        .files
+        .iter()
+        .flat_map(|file| &file.fonts)
+        .flat_map(|font| &font.faces)
+        .filter_map(|face| face.names.family_label())
+        .collect()
+}
+
+/// 文件的修改时间，从 UNIX 纪元开始的纳秒数
+fn modified(metadata: &fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |mtime| mtime.as_nanos() as u64)
+}
+
+/// 索引一个文件，字体文件和字体压缩包以外的文件没有字体，无法读取时返回 None
+fn index_file(path: &Path, size: u64, mtime: u64) -> Option<IndexedFile> {
+    let path_str = path.to_str().unwrap();
+    let head = match fs::File::open(path).and_then(|mut f| sniff::read_head(&mut f)) {
+        Ok(head) => head,
+        Err(err) => {
+            warn!("Unable to read \"{path_str}\": {err}");
+            return None;
+        }
+    };
+    let kind = sniff::sniff(&head);
+    let mut fonts = Vec::new();
+    if sniff::is_font(path, &head) {
+        match fs::read(path) {
+            Ok(data) => fonts.push(index_font(path_str, None, &data)),
+            Err(err) => {
+                warn!("Unable to read \"{path_str}\": {err}");
+                return None;
+            }
+        }
+    } else if kind.is_archive() && kind != ContentKind::Matroska {
+        let result = for_each_member(
+            path_str,
+            kind,
+            |_| true,
+            |name, reader| {
+                let head = sniff::read_head(reader)?;
+                if !sniff::is_font(name, &head) {
+                    return Ok(());
+                }
+                let mut data = head;
+                reader.read_to_end(&mut data)?;
+                fonts.push(index_font(path_str, Some(name.to_str().unwrap()), &data));
+                Ok(())
+            },
+        );
+        if let Err(err) = result {
+            warn!("Unable to read archive \"{path_str}\": {err}");
+            return None;
+        }
+    }
+    debug!("Indexed \"{path_str}\" with {} fonts", fonts.len());
+    Some(IndexedFile {
+        path: path_str.to_string(),
+        size,
+        mtime,
+        fonts,
+    })
+}
+
+/// 读取字体中的名称和样式，member 是字体在压缩包 path 中的路径
+///
+/// WOFF/WOFF2 字体会先被解码，无法读取名称的字体也会被记录，只是没有可以查找的名称。
+fn index_font(path: &str, member: Option<&str>, data: &[u8]) -> IndexedFont {
+    let name = match member {
+        Some(member) => Path::new(path).join(member).to_str().unwrap().to_string(),
+        None => path.to_string(),
+    };
+    let hash = xxh3_128(data);
+    #[cfg(feature = "woff")]
+    let decoded = match data.get(0..4) {
+        Some(b"wOFF" | b"wOF2") => match sfnt::decode_web_font(data) {
+            Ok((decoded, _)) => Some(decoded),
+            Err(err) => {
+                warn!("Unable to decode web font \"{name}\": {err}");
+                None
+            }
+        },
+        _ => None,
+    };
+    #[cfg(feature = "woff")]
+    let data = decoded.as_deref().unwrap_or(data);
+    let mut faces = Vec::new();
+    if matches!(
+        sniff::sniff(data),
+        ContentKind::Sfnt | ContentKind::Collection
+    ) {
+        match sfnt::read_faces(data) {
+            Ok(read) => {
+                for face in read {
+                    match face {
+                        Ok(face) => faces.push(IndexedFace {
+                            index: face.index,
+                            style: face.style,
+                            names: face.names,
+                        }),
+                        Err(err) => debug!("Unable to read names from \"{name}\": {err}"),
+                    }
+                }
+            }
+            Err(err) => warn!("Unable to read names from \"{name}\": {err}"),
+        }
+    }
+    debug!("Indexed font \"{name}\" with {} faces", faces.len());
+    IndexedFont {
+        member: member.map(|member| member.to_string()),
+        hash,
+        faces,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn write_font(path: &Path, family: &str) {
+        fs::write(path, sfnt::test_font(family, "Regular", 400, "abc")).unwrap();
+    }
+
+    #[test]
+    fn incremental_scan() {
+        let temp = TempDir::new("fontloader-test").unwrap();
+        let (library, index_path) = (temp.path().join("library"), temp.path().join("index.tsv"));
+        fs::create_dir_all(library.join("sub")).unwrap();
+        write_font(&library.join("a.ttf"), "Test A");
+        write_font(&library.join("sub/b.ttf"), "Test B");
+        write_font(&library.join("keep.ttf"), "Test Keep");
+        fs::write(library.join("notes.txt"), "not a font").unwrap();
+        let roots = vec![library.to_str().unwrap().to_string()];
+
+        let changes = scan_index(&index_path, roots.clone()).unwrap();
+        assert_eq!(
+            changes,
+            ScanChanges {
+                reused: 0,
+                updated: 4,
+                removed: 0,
+                added: vec!["Test A".into(), "Test B".into(), "Test Keep".into()],
+                deleted: vec![],
+            }
+        );
+
+        // 删除、修改和新增文件，没有变化的文件直接使用索引中的记录
+        fs::remove_file(library.join("a.ttf")).unwrap();
+        write_font(&library.join("sub/b.ttf"), "Test Changed");
+        write_font(&library.join("d.ttf"), "Test D");
+        let changes = scan_index(&index_path, vec![]).unwrap();
+        assert_eq!(
+            changes,
+            ScanChanges {
+                reused: 2,
+                updated: 2,
+                removed: 1,
+                added: vec!["Test Changed".into(), "Test D".into()],
+                deleted: vec!["Test A".into(), "Test B".into()],
+            }
+        );
+
+        let index = Index::load(&index_path).unwrap();
+        assert_eq!(index.roots, roots);
+        let paths: Vec<&Path> = index
+            .files
+            .iter()
+            .map(|file| Path::new(&file.path).strip_prefix(&roots[0]).unwrap())
+            .collect();
+        let sub = Path::new("sub").join("b.ttf");
+        assert_eq!(
+            paths,
+            [
+                Path::new("d.ttf"),
+                Path::new("keep.ttf"),
+                Path::new("notes.txt"),
+                &sub
+            ]
+        );
+        assert_eq!(families(&index).len(), 3);
+
+        // 没有变化时不会读取任何文件
+        let changes = scan_index(&index_path, vec![]).unwrap();
+        assert_eq!(
+            (changes.reused, changes.updated, changes.removed),
+            (4, 0, 0)
+        );
+        assert!(changes.added.is_empty() && changes.deleted.is_empty());
+    }
+
+    #[cfg(feature = "archive-zip")]
+    #[test]
+    fn select_indexed_members() {
+        use crate::{
+            font_source::{RecordingBackend, from_path},
+            subtitle::FontUsage,
+        };
+        use std::io::Write;
+        use zip::{ZipWriter, write::SimpleFileOptions};
+
+        let temp = TempDir::new("fontloader-test").unwrap();
+        let library = temp.path().join("library");
+        fs::create_dir_all(&library).unwrap();
+        let zip_path = library.join("Pack.zip");
+        let mut zip = ZipWriter::new(fs::File::create(&zip_path).unwrap());
+        let options = SimpleFileOptions::default();
+        for (name, subfamily, weight) in [("a.ttf", "Regular", 400), ("b.ttf", "Bold", 700)] {
+            zip.start_file(name, options).unwrap();
+            zip.write_all(&sfnt::test_font("Test A", subfamily, weight, "abc"))
+                .unwrap();
+        }
+        zip.start_file("c.ttf", options).unwrap();
+        zip.write_all(&sfnt::test_font("Test C", "Regular", 400, "abc"))
+            .unwrap();
+        zip.finish().unwrap();
+        let index_path = temp.path().join("index.tsv");
+        scan_index(&index_path, vec![library.to_str().unwrap().to_string()]).unwrap();
+
+        // 交换索引中 b.ttf 和 c.ttf 的名称，只有按索引选择成员时 b.ttf 才不会被解压
+        let mut index = Index::load(&index_path).unwrap();
+        let fonts = &mut index.files[0].fonts;
+        assert_eq!(fonts.len(), 3);
+        let (b, c) = fonts.split_at_mut(2);
+        std::mem::swap(&mut b[1].faces, &mut c[0].faces);
+
+        let load = |index: &Index| -> Vec<String> {
+            let backend = RecordingBackend::new();
+            let session = Session::new(&backend);
+            session.require_usages(
+                "test",
+                vec![FontUsage {
+                    family: "Test A".to_string(),
+                    weight: 400,
+                    italic: false,
+                }],
+            );
+            let mut sources = vec![from_path(zip_path.to_str().unwrap().to_string()).unwrap()];
+            select_indexed(index, &mut sources, &session);
+            sources[0].prepare(&session).unwrap();
+            session.select_versions();
+            sources[0].load(&session).unwrap();
+            let mut loaded: Vec<String> = sources[0]
+                .get_fonts()
+                .into_iter()
+                .map(|font| {
+                    let name = Path::new(&font.path).file_name().unwrap();
+                    name.to_str().unwrap().to_string()
+                })
+                .collect();
+            loaded.sort();
+            loaded
+        };
+        assert_eq!(load(&index), ["a.ttf"]);
+
+        // 压缩包在索引后修改过时解压所有字体
+        index.files[0].mtime += 1;
+        assert_eq!(load(&index), ["a.ttf", "b.ttf"]);
+    }
+}
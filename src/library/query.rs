@@ -0,0 +1,284 @@
+use crate::{
+    font_source::matcher::FontMatcher,
+    library::{Index, IndexedFace, IndexedFile, IndexedFont, index_path},
+    sfnt::{NAME_ID_FAMILY, NAME_ID_TYPOGRAPHIC_FAMILY},
+    subtitle::FontUsage,
+};
+use anyhow::{Result, bail};
+use log::{debug, info, warn};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::Path,
+};
+
+/// 没有完全匹配的家族时最多给出的建议数量
+const MAX_SUGGESTIONS: usize = 5;
+
+/// 从字体库中找到的字体
+#[derive(Debug, Default)]
+pub struct Found {
+    /// 包含找到的字体的文件和压缩包，按路径排序
+    pub paths: Vec<String>,
+    /// 找到的每个字体的家族名称和样式，加载时只加载这些字体
+    pub usages: Vec<FontUsage>,
+    /// 完全匹配和模糊匹配都没有找到字体的家族名称
+    pub missing: Vec<String>,
+}
+
+/// 在字体库的索引中按家族名称查找字体
+///
+/// 和加载字幕需要的字体时一样匹配所有语言的家族名称、全名和 typographic family。
+/// 没有完全匹配的字体时，忽略大小写、全角半角、空格和标点比较家族名称，也匹配以查询的名称开头的家族，
+/// 例如 `Noto Serif CJK` 会匹配 `Noto Serif CJK SC` 等所有地区的家族；仍然没有时给出名称相近的家族作为建议。
+pub fn find_families(queries: &[String]) -> Result<Found> {
+    let path = index_path()?;
+    find_in_index(&Index::load(&path)?, queries)
+}
+
+fn find_in_index(index: &Index, queries: &[String]) -> Result<Found> {
+    if index.files.is_empty() {
+        bail!("the font library is not indexed, run scan with the library folders first");
+    }
+    let faces: Vec<(&IndexedFile, &IndexedFont, &IndexedFace)> = index
+        .files
+        .iter()
+        .flat_map(|file| {
+            file.fonts
+                .iter()
+                .flat_map(move |font| font.faces.iter().map(move |face| (file, font, face)))
+        })
+        .collect();
+    let mut matcher = FontMatcher::default();
+    // 模糊匹配时比较的家族名称，值是显示的名称和使用这个名称的字体
+    let mut names: BTreeMap<String, (String, Vec<usize>)> = BTreeMap::new();
+    for (i, (_, _, face)) in faces.iter().enumerate() {
+        matcher.add(i, &face.names);
+        for record in &face.names.records {
+            if !matches!(record.name_id, NAME_ID_FAMILY | NAME_ID_TYPOGRAPHIC_FAMILY) {
+                continue;
+            }
+            let entry = names
+                .entry(fuzzy_words(&record.value).concat())
+                .or_insert_with(|| (record.value.clone(), Vec::new()));
+            if entry.1.last() != Some(&i) {
+                entry.1.push(i);
+            }
+        }
+    }
+
+    let mut found: BTreeSet<usize> = BTreeSet::new();
+    let mut missing = Vec::new();
+    for query in queries {
+        let exact: BTreeSet<usize> = matcher.find(query).iter().map(|(i, _)| *i).collect();
+        if !exact.is_empty() {
+            info!(
+                "Found {} faces of font family {query} in the library",
+                exact.len()
+            );
+            found.extend(exact);
+            continue;
+        }
+        let key = fuzzy_words(query).concat();
+        let similar: Vec<&(String, Vec<usize>)> = names
+            .values()
+            .filter(|(name, _)| fuzzy_match(&key, &fuzzy_words(name)))
+            .collect();
+        if !similar.is_empty() {
+            let mut families: Vec<&str> = similar.iter().map(|(name, _)| name.as_str()).collect();
+            families.dedup();
+            let similar: BTreeSet<usize> = similar
+                .iter()
+                .flat_map(|(_, faces)| faces.iter().copied())
+                .collect();
+            info!(
+                "No font family named {query} in the library, found {} faces of similar families: {}",
+                similar.len(),
+                families.join(", ")
+            );
+            found.extend(similar);
+            continue;
+        }
+        let suggestions = suggest(&key, &names);
+        if suggestions.is_empty() {
+            warn!("No font family named {query} in the library");
+        } else {
+            warn!(
+                "No font family named {query} in the library, did you mean: {}?",
+                suggestions
+                    .iter()
+                    .map(|name| format!("\"{name}\""))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            );
+        }
+        missing.push(query.clone());
+    }
+
+    // 内容相同的字体只从一个位置加载，优先使用不在压缩包中的文件
+    let mut result = Found {
+        missing,
+        ..Found::default()
+    };
+    let mut chosen: HashMap<u128, (&IndexedFile, &IndexedFont)> = HashMap::new();
+    let mut gone: BTreeSet<&str> = BTreeSet::new();
+    for i in found {
+        let (file, font, face) = faces[i];
+        let Some(family) = &face.names.family else {
+            continue;
+        };
+        if !Path::new(&file.path).exists() {
+            gone.insert(&file.path);
+            continue;
+        }
+        debug!("Found font {} in \"{}\"", face.names, file.path);
+        result.usages.push(FontUsage {
+            family: family.clone(),
+            weight: face.style.weight,
+            italic: face.style.italic,
+        });
+        let entry = chosen.entry(font.hash).or_insert((file, font));
+        if entry.1.member.is_some() && font.member.is_none() {
+            *entry = (file, font);
+        }
+    }
+    for path in gone {
+        warn!("File \"{path}\" in the library index no longer exists, scan the library again");
+    }
+    result.paths = chosen
+        .into_values()
+        .map(|(file, _)| file.path.clone())
+        .collect();
+    result.paths.sort();
+    result.paths.dedup();
+    result.usages.sort();
+    result.usages.dedup();
+    Ok(result)
+}
+
+/// 模糊匹配时比较的单词，全角字母和数字转换为半角并转换为小写，空格和标点作为分隔
+fn fuzzy_words(name: &str) -> Vec<String> {
+    let folded: String = name
+        .chars()
+        .map(|c| match c {
+            '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap(),
+            _ => c,
+        })
+        .flat_map(char::to_lowercase)
+        .collect();
+    folded
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_string())
+        .collect()
+}
+
+/// 去掉空格和标点后 key 是否和名称相同，或者是名称开头的几个单词
+fn fuzzy_match(key: &str, words: &[String]) -> bool {
+    if key.is_empty() {
+        return false;
+    }
+    let mut prefix = String::new();
+    words.iter().any(|word| {
+        prefix.push_str(word);
+        prefix == key
+    })
+}
+
+/// 名称相近或者包含 key 的家族，按编辑距离排序
+fn suggest(key: &str, names: &BTreeMap<String, (String, Vec<usize>)>) -> Vec<String> {
+    if key.is_empty() {
+        return Vec::new();
+    }
+    let max_distance = (key.chars().count() / 3).max(2);
+    let mut suggestions: Vec<(usize, &String)> = names
+        .iter()
+        .filter_map(|(name_key, (name, _))| {
+            let distance = edit_distance(key, name_key);
+            (distance <= max_distance || name_key.contains(key)).then_some((distance, name))
+        })
+        .collect();
+    suggestions.sort();
+    let mut names: Vec<String> = Vec::new();
+    for (_, name) in suggestions {
+        if !names.contains(name) {
+            names.push(name.clone());
+        }
+        if names.len() == MAX_SUGGESTIONS {
+            break;
+        }
+    }
+    names
+}
+
+/// 两个字符串的 Levenshtein 编辑距离
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            current.push(
+                (previous[j] + usize::from(ca != *cb))
+                    .min(previous[j + 1] + 1)
+                    .min(current[j] + 1),
+            );
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{library::scan_index, sfnt};
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn fuzzy_names() {
+        let words = fuzzy_words("Noto Serif CJK SC");
+        assert_eq!(words, ["noto", "serif", "cjk", "sc"]);
+        assert!(fuzzy_match("notoserifcjk", &words));
+        assert!(fuzzy_match(
+            &fuzzy_words("ＮＯＴＯ-Serif_CJK").concat(),
+            &words
+        ));
+        assert!(fuzzy_match("notoserifcjksc", &words));
+        assert!(!fuzzy_match("notoserifcj", &words));
+        assert!(!fuzzy_match("", &words));
+        assert_eq!(fuzzy_words("思源黑体 Bold"), ["思源黑体", "bold"]);
+
+        assert_eq!(edit_distance("dejavusans", "dejavusans"), 0);
+        assert_eq!(edit_distance("dejavusan", "dejavusans"), 1);
+        assert_eq!(edit_distance("思源黑体", "思源黒体"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn missing_families() {
+        let temp = TempDir::new("fontloader-test").unwrap();
+        let (library, path) = (temp.path().join("library"), temp.path().join("index.tsv"));
+        fs::create_dir_all(&library).unwrap();
+        fs::write(
+            library.join("a.ttf"),
+            sfnt::test_font("DejaVu Sans", "Regular", 400, "abc"),
+        )
+        .unwrap();
+        scan_index(&path, vec![library.to_str().unwrap().to_string()]).unwrap();
+        let index = Index::load(&path).unwrap();
+
+        let found = find_in_index(&index, &["DejaVu Sans".to_string()]).unwrap();
+        assert_eq!(found.paths.len(), 1);
+        assert!(found.missing.is_empty());
+        // 只有建议的家族也算没有找到，命令行据此以错误退出
+        let queries = [
+            "dejavu".to_string(),
+            "DejaVu Sanz".to_string(),
+            "Nothing".to_string(),
+        ];
+        let found = find_in_index(&index, &queries).unwrap();
+        assert_eq!(found.paths.len(), 1);
+        assert_eq!(found.missing, ["DejaVu Sanz", "Nothing"]);
+    }
+}
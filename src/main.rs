@@ -1,20 +1,37 @@
 use cfg_if::cfg_if;
 use log::{debug, error, info, warn};
 #[cfg(feature = "parallel")]
-use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use rayon::iter::{
+    IntoParallelIterator, IntoParallelRefIterator, IntoParallelRefMutIterator, ParallelIterator,
+};
 use std::{
+    collections::BTreeSet,
     io::{self, Read},
+    path::Path,
     time::Instant,
 };
-use windows::Win32::{
-    Foundation::{LPARAM, WPARAM},
-    UI::WindowsAndMessaging::{
-        HWND_BROADCAST, MB_ICONINFORMATION, MB_OK, MessageBoxW, PostMessageW, WM_FONTCHANGE,
-    },
-};
+#[cfg(windows)]
+use windows::Win32::UI::WindowsAndMessaging::{MB_ICONINFORMATION, MB_OK, MessageBoxW};
+#[cfg(windows)]
 use windows_strings::h;
 
+mod collect;
+mod embed;
 mod font_source;
+#[cfg(feature = "library")]
+mod library;
+#[cfg(feature = "archive-mkv")]
+mod matroska;
+mod sfnt;
+mod subtitle;
+use embed::EmbedMode;
+#[cfg(unix)]
+use font_source::FontconfigBackend;
+#[cfg(windows)]
+use font_source::GdiBackend;
+use font_source::{
+    BackendCall, FontBackend, FontSource, RecordingBackend, Session, remove_overlapping,
+};
 
 fn main() {
     env_logger::Builder::from_env(env_logger::Env::default().filter_or(
@@ -57,7 +74,7 @@ fn main() {
     );
     info!("Repository: {}", env!("CARGO_PKG_REPOSITORY"));
 
-    let args: Vec<String> = if cfg!(debug_assertions) {
+    let mut args: Vec<String> = if cfg!(debug_assertions) {
         vec![
             "sample/DreamHanSerif".to_string(),
             "sample/DreamHanSans".to_string(),
@@ -75,8 +92,95 @@ fn main() {
     } else {
         std::env::args().skip(1).collect()
     };
+    // 扫描模式只扫描字体库并保存索引，之后的参数是字体库的文件夹
+    #[cfg(feature = "library")]
+    if args.first().is_some_and(|arg| arg == "scan") {
+        if let Err(err) = library::scan(args.split_off(1)) {
+            error!("Failed to scan font library: {err}");
+            std::process::exit(1);
+        }
+        return;
+    }
+    // 缓存管理模式显示或者清空压缩包的解压缓存
+    #[cfg(feature = "cache")]
+    if args.first().is_some_and(|arg| arg == "cache") {
+        let result = match args.get(1).map(String::as_str) {
+            Some("stats") => library::cache::stats(),
+            Some("clean") => library::cache::clean(),
+            _ => {
+                error!("Usage: fontloader cache stats|clean");
+                std::process::exit(1);
+            }
+        };
+        if let Err(err) = result {
+            error!("Failed to manage the extraction cache: {err}");
+            std::process::exit(1);
+        }
+        return;
+    }
+    // 检查模式只检查字幕需要的字体是否齐全，不会实际加载字体，有问题时返回非零的退出码
+    let check = args.iter().any(|arg| arg == "--check");
+    args.retain(|arg| arg != "--check");
+    // 收集模式把字幕使用的字体复制到指定的文件夹或 ZIP 压缩包，同样不会实际加载字体
+    let mut collect = None;
+    if let Some(i) = args.iter().position(|arg| arg == "--collect") {
+        args.remove(i);
+        if i >= args.len() {
+            error!("Missing output path after --collect");
+            std::process::exit(2);
+        }
+        collect = Some(args.remove(i));
+    }
+    // 嵌入模式把字幕使用的字体子集化后嵌入字幕，或者和改名后的字幕一起输出到文件夹，同样不会实际加载字体
+    let embed = if args.iter().any(|arg| arg == "--embed-folder") {
+        Some(EmbedMode::Folder)
+    } else if args.iter().any(|arg| arg == "--embed") {
+        Some(EmbedMode::Fonts)
+    } else {
+        None
+    };
+    args.retain(|arg| arg != "--embed" && arg != "--embed-folder");
+    // 按家族名称从字体库的索引中查找字体，可以使用多次，只加载找到的字体
+    let mut families = Vec::new();
+    while let Some(i) = args.iter().position(|arg| arg == "--family") {
+        args.remove(i);
+        if i >= args.len() {
+            error!("Missing family name after --family");
+            std::process::exit(2);
+        }
+        families.push(args.remove(i));
+    }
+    #[cfg(feature = "library")]
+    let family_usages = if families.is_empty() {
+        Vec::new()
+    } else {
+        match library::find_families(&families) {
+            Ok(found) if !found.missing.is_empty() => {
+                error!(
+                    "No fonts found in the library for font families: {}",
+                    found.missing.join(", ")
+                );
+                std::process::exit(2);
+            }
+            Ok(found) => {
+                args.extend(found.paths);
+                found.usages
+            }
+            Err(err) => {
+                error!("Failed to find fonts in the library: {err}");
+                std::process::exit(1);
+            }
+        }
+    };
+    #[cfg(not(feature = "library"))]
+    if !families.is_empty() {
+        error!("Finding fonts in the library is not supported in this build");
+        std::process::exit(2);
+    }
+    let offline = check || collect.is_some() || embed.is_some();
     if args.is_empty() {
         warn!("No input file");
+        #[cfg(windows)]
         unsafe {
             MessageBoxW(
                 None,
@@ -88,8 +192,41 @@ fn main() {
         return;
     }
 
+    // 可以用环境变量 FONTLOADER_BACKEND 选择后端，设置为 recording 时只记录调用而不实际加载字体
+    let recording = RecordingBackend::new();
+    let backend_name = backend_name(offline, std::env::var("FONTLOADER_BACKEND").ok());
+    let system_backend: Option<Box<dyn FontBackend>> = match backend_name.as_str() {
+        #[cfg(windows)]
+        "gdi" => Some(Box::new(GdiBackend)),
+        #[cfg(unix)]
+        "fontconfig" => match FontconfigBackend::new() {
+            Ok(backend) => Some(Box::new(backend)),
+            Err(err) => {
+                error!("Failed to create fontconfig backend: {err}");
+                return;
+            }
+        },
+        "recording" => None,
+        _ => {
+            error!("Unsupported font backend: {backend_name}");
+            return;
+        }
+    };
+    let backend = system_backend.as_deref().unwrap_or(&recording);
+    debug!("Use font backend: {}", backend.name());
+    let mut session = Session::new(backend);
+    session.muxed_subtitles = check || collect.is_some();
+    session.folder_subtitles = offline;
+    #[cfg(feature = "library")]
+    if !family_usages.is_empty() {
+        session.require_usages("--family", family_usages);
+    }
+
+    // 拖入视频或字幕时同时加载旁边附带的字体
+    #[cfg(feature = "sidecar")]
+    let args = font_source::with_sidecars(args);
     let mut font_sources = Vec::new();
-    for arg in args {
+    for arg in remove_overlapping(args) {
         match font_source::from_path(arg.clone()) {
             Some(fs) => {
                 font_sources.push(fs);
@@ -100,7 +237,42 @@ fn main() {
         }
     }
 
+    // 先读取所有字幕需要的字体，之后的字体源只加载其中的字体
+    cfg_if! {
+        if #[cfg(feature = "parallel")] {
+            let iter = font_sources.par_iter_mut();
+        } else {
+            let iter = font_sources.iter_mut();
+        }
+    }
+    iter.for_each(|fs| {
+        if let Err(err) = fs.require_fonts(&session) {
+            error!("Failed to read subtitle \"{}\": {err}", fs.get_path());
+        }
+    });
+    // 字体库的索引中有记录的压缩包只解压需要的字体
+    #[cfg(feature = "library")]
+    library::select_members(&mut font_sources, &session);
+
     let start = Instant::now();
+    // 先选出所有字体源中需要的字体，在所有字体源之间比较版本后再加载
+    cfg_if! {
+        if #[cfg(feature = "parallel")] {
+            let iter = font_sources.into_par_iter();
+        } else {
+            let iter = font_sources.into_iter();
+        }
+    }
+    font_sources = iter
+        .filter_map(|mut fs| match fs.prepare(&session) {
+            Ok(_) => Some(fs),
+            Err(err) => {
+                error!("Failed to read fonts from \"{}\": {err}", fs.get_path());
+                None
+            }
+        })
+        .collect();
+    session.select_versions();
     cfg_if! {
         if #[cfg(feature = "parallel")] {
             let iter = font_sources.into_par_iter();
@@ -109,7 +281,7 @@ fn main() {
         }
     }
     font_sources = iter
-        .filter_map(|mut fs| match fs.load() {
+        .filter_map(|mut fs| match fs.load(&session) {
             Ok(_) => {
                 info!("Loaded font from \"{}\"", fs.get_path());
                 Some(fs)
@@ -120,13 +292,171 @@ fn main() {
             }
         })
         .collect();
-    info!("Loaded font in {}s", start.elapsed().as_secs_f64());
-    debug!("Call PostMessageW WM_FONTCHANGE");
-    unsafe {
-        if let Err(err) = PostMessageW(Some(HWND_BROADCAST), WM_FONTCHANGE, WPARAM(0), LPARAM(0)) {
-            warn!("Failed to call PostMessageW WM_FONTCHANGE: {err}");
+    let (files, faces, families) = summarize(&font_sources);
+    info!(
+        "Loaded {files} font files ({faces} faces) of {} families in {}s",
+        families.len(),
+        start.elapsed().as_secs_f64()
+    );
+    for family in &families {
+        info!("Loaded family: {family}");
+    }
+    #[cfg(feature = "cache")]
+    library::cache::evict();
+    let duplicates = session.duplicates();
+    if !duplicates.is_empty() {
+        info!(
+            "Skipped {} duplicate font files with the same content as loaded ones",
+            duplicates.len()
+        );
+        for duplicate in &duplicates {
+            info!(
+                "Skipped duplicate: \"{}\" (same as \"{}\")",
+                duplicate.name, duplicate.original
+            );
+        }
+    }
+    let superseded = session.superseded();
+    if !superseded.is_empty() {
+        info!(
+            "Skipped {} font files superseded by newer versions",
+            superseded.len()
+        );
+        for font in &superseded {
+            info!(
+                "Skipped superseded: \"{}\" [{}] (newer \"{}\" [{}])",
+                font.name, font.version, font.newer, font.newer_version
+            );
+        }
+    }
+    let missing = session.missing();
+    if !missing.is_empty() {
+        warn!(
+            "Unable to find {} font families required by subtitles",
+            missing.len()
+        );
+        for font in &missing {
+            warn!(
+                "Missing font: {} required by {}",
+                font.usages
+                    .iter()
+                    .map(|usage| usage.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                font.subtitles
+                    .iter()
+                    .map(|subtitle| format!("\"{subtitle}\""))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            );
+            for path in &font.typographic {
+                warn!(
+                    "Font \"{path}\" matches {} only by its typographic family name, which GDI and VSFilter do not use",
+                    font.usages[0].family
+                );
+            }
         }
     }
+    let unused = session.unused();
+    // 只有字幕自带的字体没有被使用时检查才会失败，拖入的字体文件夹中总会有字幕不需要的字体
+    let attached_unused = unused.iter().filter(|font| font.attached).count();
+    if !unused.is_empty() {
+        info!("Skipped {} font files not used by subtitles", unused.len());
+        for font in &unused {
+            if check && font.attached {
+                warn!("Unused attached font: \"{}\"", font.name);
+            } else if check {
+                info!("Unused font: \"{}\"", font.name);
+            } else {
+                debug!("Unused font: \"{}\"", font.name);
+            }
+        }
+    }
+    let uncovered = session.uncovered();
+    for font in &uncovered {
+        warn!(
+            "Font {} ({}) has no glyphs for {} characters used by {}: {}",
+            font.family,
+            font.fonts
+                .iter()
+                .map(|path| format!("\"{path}\""))
+                .collect::<Vec<String>>()
+                .join(", "),
+            font.chars.len(),
+            font.subtitles
+                .iter()
+                .map(|subtitle| format!("\"{subtitle}\""))
+                .collect::<Vec<String>>()
+                .join(", "),
+            font.chars.iter().collect::<String>()
+        );
+    }
+    for font in session.synthetic() {
+        let style = match (font.bold, font.italic) {
+            (true, true) => "bold italic",
+            (true, false) => "bold",
+            _ => "italic",
+        };
+        let message = format!(
+            "No real {style} face for {} required by {}, \"{}\" will be rendered with synthetic {style}",
+            font.usage,
+            font.subtitles
+                .iter()
+                .map(|subtitle| format!("\"{subtitle}\""))
+                .collect::<Vec<String>>()
+                .join(", "),
+            font.font
+        );
+        if check {
+            warn!("{message}");
+        } else {
+            info!("{message}");
+        }
+    }
+    for tofu in session.tofu() {
+        info!(
+            "Missing glyphs in \"{}\" line {}: {} has no glyphs for {}",
+            tofu.subtitle, tofu.line, tofu.family, tofu.chars
+        );
+    }
+
+    let mut failed = false;
+    if let Some(output) = &collect
+        && let Err(err) = collect::collect(&font_sources, &session, Path::new(output))
+    {
+        error!("Failed to collect fonts to \"{output}\": {err}");
+        failed = true;
+    }
+    if let Some(mode) = embed
+        && let Err(err) = embed::embed(&font_sources, &session, mode)
+    {
+        error!("Failed to embed fonts into subtitles: {err}");
+        failed = true;
+    }
+    if check {
+        if !session.has_subtitles() {
+            error!("Check failed: no subtitles to check");
+            failed = true;
+        } else if missing.is_empty() && attached_unused == 0 && uncovered.is_empty() {
+            info!("Check passed");
+        } else {
+            error!(
+                "Check failed: {} missing font families, {attached_unused} unused attached font files, {} font families with missing glyphs",
+                missing.len(),
+                uncovered.len()
+            );
+            failed = true;
+        }
+    }
+    if offline {
+        // 退出前删除临时文件夹
+        drop(font_sources);
+        if failed {
+            std::process::exit(1);
+        }
+        return;
+    }
+    backend.notify_changed();
 
     warn!("Press ENTER to unload fonts");
     let _ = stdin.read(&mut [0u8]).unwrap();
@@ -155,14 +485,85 @@ fn main() {
         }
     }
     iter.for_each(|fs| {
-        fs.unload();
+        fs.unload(backend);
         info!("Unloaded font from \"{}\"", fs.get_path());
     });
-    info!("Unloaded font in {}s", start.elapsed().as_secs_f64());
-    debug!("Call PostMessageW WM_FONTCHANGE");
-    unsafe {
-        if let Err(err) = PostMessageW(Some(HWND_BROADCAST), WM_FONTCHANGE, WPARAM(0), LPARAM(0)) {
-            warn!("Failed to call PostMessageW WM_FONTCHANGE: {err}");
+    info!(
+        "Unloaded {files} font files ({faces} faces) of {} families in {}s",
+        families.len(),
+        start.elapsed().as_secs_f64()
+    );
+    backend.notify_changed();
+
+    let calls = recording.calls();
+    if !calls.is_empty() {
+        info!(
+            "Recorded {} register and {} unregister calls",
+            calls
+                .iter()
+                .filter(|c| matches!(c, BackendCall::Register(_)))
+                .count(),
+            calls
+                .iter()
+                .filter(|c| matches!(c, BackendCall::Unregister(_)))
+                .count(),
+        );
+    }
+}
+
+/// 选择字体后端的名称，env 是环境变量 FONTLOADER_BACKEND 的值
+///
+/// 检查、收集和嵌入字体时退出前不会卸载字体，总是只记录调用而不实际加载字体。
+fn backend_name(offline: bool, env: Option<String>) -> String {
+    if offline {
+        if let Some(env) = env.filter(|env| env != "recording") {
+            warn!(
+                "Ignored FONTLOADER_BACKEND \"{env}\", fonts are never loaded when checking, collecting or embedding"
+            );
+        }
+        return "recording".to_string();
+    }
+    env.unwrap_or_else(|| {
+        if cfg!(windows) {
+            "gdi"
+        } else if cfg!(unix) {
+            "fontconfig"
+        } else {
+            "recording"
+        }
+        .to_string()
+    })
+}
+
+/// 统计已加载的字体文件和字体数量以及家族名称
+fn summarize(font_sources: &[Box<dyn FontSource>]) -> (usize, usize, BTreeSet<String>) {
+    let mut files = 0;
+    let mut faces = 0;
+    let mut families = BTreeSet::new();
+    for fs in font_sources {
+        for f in fs.get_fonts() {
+            files += 1;
+            faces += f.faces.len();
+            families.extend(f.faces.iter().filter_map(|face| face.names.family_label()));
         }
     }
+    (files, faces, families)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offline_backend() {
+        let system = if cfg!(windows) { "gdi" } else { "fontconfig" };
+        // 检查模式中设置的系统后端会被忽略，不会留下没有卸载的字体
+        assert_eq!(backend_name(true, Some(system.to_string())), "recording");
+        assert_eq!(backend_name(true, None), "recording");
+        assert_eq!(
+            backend_name(false, Some("recording".to_string())),
+            "recording"
+        );
+        assert_eq!(backend_name(false, Some(system.to_string())), system);
+    }
 }
@@ -0,0 +1,644 @@
+use anyhow::{Result, bail};
+use log::{debug, warn};
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// 用到的 EBML 元素 ID
+const ID_EBML: u32 = 0x1A45DFA3;
+const ID_SEGMENT: u32 = 0x18538067;
+const ID_SEEK_HEAD: u32 = 0x114D9B74;
+const ID_SEEK: u32 = 0x4DBB;
+const ID_SEEK_ID: u32 = 0x53AB;
+const ID_SEEK_POSITION: u32 = 0x53AC;
+const ID_CLUSTER: u32 = 0x1F43B675;
+const ID_ATTACHMENTS: u32 = 0x1941A469;
+const ID_ATTACHED_FILE: u32 = 0x61A7;
+const ID_FILE_NAME: u32 = 0x466E;
+const ID_FILE_MIME_TYPE: u32 = 0x4660;
+const ID_FILE_DATA: u32 = 0x465C;
+const ID_TRACKS: u32 = 0x1654AE6B;
+const ID_TRACK_ENTRY: u32 = 0xAE;
+const ID_TRACK_NUMBER: u32 = 0xD7;
+const ID_TRACK_TYPE: u32 = 0x83;
+const ID_CODEC_ID: u32 = 0x86;
+const ID_CODEC_PRIVATE: u32 = 0x63A2;
+const ID_CONTENT_ENCODINGS: u32 = 0x6D80;
+const ID_SIMPLE_BLOCK: u32 = 0xA3;
+const ID_BLOCK_GROUP: u32 = 0xA0;
+const ID_BLOCK: u32 = 0xA1;
+
+/// 长度未知的 Cluster 中可以出现的子元素，遇到其他元素说明 Cluster 已经结束
+const CLUSTER_CHILDREN: [u32; 7] = [
+    0xE7,
+    0xA7,
+    0xAB,
+    0x5854,
+    0xAF,
+    ID_SIMPLE_BLOCK,
+    ID_BLOCK_GROUP,
+];
+
+/// TrackType 中字幕轨道的值
+const TRACK_TYPE_SUBTITLE: u64 = 0x11;
+
+/// Matroska 文件中的一个附件，数据在文件中的位置为 offset，长度为 size
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub name: String,
+    pub mime_type: String,
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// Matroska 文件中的一个 ASS/SSA 字幕轨道
+pub struct SubtitleTrack {
+    pub number: u64,
+    /// CodecPrivate 中的文件头，包括 [Script Info] 和样式
+    pub header: String,
+    /// 每个块中的一行对话
+    pub events: Vec<String>,
+}
+
+/// EBML 元素的头，size 为 None 表示长度未知
+struct Element {
+    id: u32,
+    size: Option<u64>,
+    /// 元素数据在文件中的开始位置
+    start: u64,
+}
+
+impl Element {
+    fn end(&self) -> Option<u64> {
+        self.size.map(|size| self.start + size)
+    }
+}
+
+/// 读取一个变长整数，返回值和长度，元素 ID 保留长度标记，元素长度去掉长度标记
+fn read_vint(reader: &mut impl Read, keep_marker: bool) -> io::Result<(u64, usize)> {
+    let mut first = [0u8];
+    reader.read_exact(&mut first)?;
+    let len = first[0].leading_zeros() as usize + 1;
+    if len > 8 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid EBML variable length integer",
+        ));
+    }
+    let mut value = if keep_marker {
+        first[0] as u64
+    } else {
+        (first[0] as u64) & (0xFF >> len)
+    };
+    let mut rest = [0u8; 7];
+    reader.read_exact(&mut rest[..len - 1])?;
+    for &b in &rest[..len - 1] {
+        value = (value << 8) | b as u64;
+    }
+    Ok((value, len))
+}
+
+/// 读取从当前位置开始的元素头
+fn read_element(reader: &mut (impl Read + Seek)) -> io::Result<Element> {
+    let (id, _) = read_vint(reader, true)?;
+    let (size, len) = read_vint(reader, false)?;
+    // 所有数据位都为 1 表示长度未知
+    let unknown = size == (1u64 << (7 * len)) - 1;
+    Ok(Element {
+        id: id as u32,
+        size: (!unknown).then_some(size),
+        start: reader.stream_position()?,
+    })
+}
+
+/// 读取整个元素的数据，用于字符串和字幕的块等较短的元素
+fn read_data(reader: &mut impl Read, element: &Element) -> Result<Vec<u8>> {
+    let Some(size) = element.size.filter(|&size| size <= 0x1000000) else {
+        bail!("unexpected size of element 0x{:X}", element.id);
+    };
+    let mut data = vec![0; size as usize];
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
+
+fn read_uint(reader: &mut impl Read, element: &Element) -> Result<u64> {
+    let data = read_data(reader, element)?;
+    if data.len() > 8 {
+        bail!("unexpected size of element 0x{:X}", element.id);
+    }
+    Ok(data.iter().fold(0, |value, &b| (value << 8) | b as u64))
+}
+
+fn read_string(reader: &mut impl Read, element: &Element) -> Result<String> {
+    let data = read_data(reader, element)?;
+    Ok(String::from_utf8_lossy(&data)
+        .trim_end_matches('\0')
+        .to_string())
+}
+
+/// 遍历 [start, end) 范围内的子元素
+fn for_each_child<R: Read + Seek>(
+    reader: &mut R,
+    start: u64,
+    end: u64,
+    mut op: impl FnMut(&mut R, &Element) -> Result<()>,
+) -> Result<()> {
+    let mut pos = start;
+    while pos < end {
+        reader.seek(SeekFrom::Start(pos))?;
+        let element = read_element(reader)?;
+        let Some(element_end) = element.end().filter(|&e| e <= end) else {
+            bail!(
+                "element 0x{:X} at offset {pos} exceeds its parent",
+                element.id
+            );
+        };
+        op(reader, &element)?;
+        pos = element_end;
+    }
+    Ok(())
+}
+
+/// 读取 Attachments 元素中的所有附件
+fn read_attachments_element(
+    reader: &mut (impl Read + Seek),
+    element: &Element,
+    segment_end: u64,
+) -> Result<Vec<Attachment>> {
+    let Some(end) = element.end() else {
+        bail!("Attachments element with unknown size");
+    };
+    // 附件的数据只记录位置，文件被截断时需要在这里发现
+    if end > segment_end {
+        bail!("truncated Attachments element at offset {}", element.start);
+    }
+    let mut attachments = Vec::new();
+    for_each_child(reader, element.start, end, |reader, file| {
+        if file.id != ID_ATTACHED_FILE {
+            return Ok(());
+        }
+        let mut name = String::new();
+        let mut mime_type = String::new();
+        let mut data = None;
+        for_each_child(reader, file.start, file.end().unwrap(), |reader, child| {
+            match child.id {
+                ID_FILE_NAME => name = read_string(reader, child)?,
+                ID_FILE_MIME_TYPE => mime_type = read_string(reader, child)?,
+                // 附件的数据可能很大，只记录位置
+                ID_FILE_DATA => data = Some((child.start, child.size.unwrap())),
+                _ => {}
+            }
+            Ok(())
+        })?;
+        match data {
+            Some((offset, size)) => attachments.push(Attachment {
+                name,
+                mime_type,
+                offset,
+                size,
+            }),
+            None => warn!("Skipped attachment \"{name}\" without data"),
+        }
+        Ok(())
+    })?;
+    Ok(attachments)
+}
+
+/// 读取 SeekHead 中 Attachments 元素的位置，位置是相对于 Segment 数据开始的偏移
+fn read_seek_head(reader: &mut (impl Read + Seek), element: &Element) -> Result<Option<u64>> {
+    let Some(end) = element.end() else {
+        bail!("SeekHead element with unknown size");
+    };
+    let mut position = None;
+    for_each_child(reader, element.start, end, |reader, seek| {
+        if seek.id != ID_SEEK {
+            return Ok(());
+        }
+        let mut id = None;
+        let mut pos = None;
+        for_each_child(reader, seek.start, seek.end().unwrap(), |reader, child| {
+            match child.id {
+                ID_SEEK_ID => id = Some(read_uint(reader, child)?),
+                ID_SEEK_POSITION => pos = Some(read_uint(reader, child)?),
+                _ => {}
+            }
+            Ok(())
+        })?;
+        if id == Some(ID_ATTACHMENTS as u64) {
+            position = pos;
+        }
+        Ok(())
+    })?;
+    Ok(position)
+}
+
+/// 跳过 EBML 头并读取 Segment 元素的头，返回 Segment 和它的结束位置
+fn read_segment(reader: &mut (impl Read + Seek)) -> Result<(Element, u64)> {
+    let file_end = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    let header = read_element(reader)?;
+    if header.id != ID_EBML {
+        bail!("not an EBML file");
+    }
+    let Some(header_end) = header.end() else {
+        bail!("EBML header with unknown size");
+    };
+    reader.seek(SeekFrom::Start(header_end))?;
+    let segment = read_element(reader)?;
+    if segment.id != ID_SEGMENT {
+        bail!("missing Segment element");
+    }
+    let segment_end = segment.end().unwrap_or(file_end).min(file_end);
+    Ok((segment, segment_end))
+}
+
+/// 读取 Matroska 文件中的所有附件，不读取音视频数据
+///
+/// 依次读取 Segment 的子元素并跳过 Cluster，附件在 Cluster 之后时通过 SeekHead 直接定位。
+pub fn read_attachments(reader: &mut (impl Read + Seek)) -> Result<Vec<Attachment>> {
+    let (segment, segment_end) = read_segment(reader)?;
+
+    let mut seek_attachments = None;
+    let mut pos = segment.start;
+    while pos < segment_end {
+        reader.seek(SeekFrom::Start(pos))?;
+        let element = read_element(reader)?;
+        match element.id {
+            ID_ATTACHMENTS => return read_attachments_element(reader, &element, segment_end),
+            ID_SEEK_HEAD => {
+                if let Some(position) = read_seek_head(reader, &element)? {
+                    seek_attachments = Some(segment.start + position);
+                }
+            }
+            ID_CLUSTER => {
+                if let Some(position) = seek_attachments.filter(|&p| p > pos) {
+                    debug!("Seeking to Attachments element at offset {position}");
+                    pos = position;
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        match element.end() {
+            Some(end) => pos = end,
+            // 长度未知的 Cluster 只能逐个跳过其中的块，只读取块的头
+            None if element.id == ID_CLUSTER => {
+                pos = read_cluster(reader, &element, segment_end, &mut [])?;
+            }
+            None => bail!(
+                "element 0x{:X} at offset {pos} has unknown size",
+                element.id
+            ),
+        }
+    }
+    Ok(Vec::new())
+}
+
+/// 读取 Tracks 元素中的 ASS/SSA 字幕轨道
+fn read_tracks(reader: &mut (impl Read + Seek), element: &Element) -> Result<Vec<SubtitleTrack>> {
+    let Some(end) = element.end() else {
+        bail!("Tracks element with unknown size");
+    };
+    let mut tracks = Vec::new();
+    for_each_child(reader, element.start, end, |reader, entry| {
+        if entry.id != ID_TRACK_ENTRY {
+            return Ok(());
+        }
+        let mut number = 0;
+        let mut track_type = 0;
+        let mut codec_id = String::new();
+        let mut header = String::new();
+        let mut encoded = false;
+        for_each_child(
+            reader,
+            entry.start,
+            entry.end().unwrap(),
+            |reader, child| {
+                match child.id {
+                    ID_TRACK_NUMBER => number = read_uint(reader, child)?,
+                    ID_TRACK_TYPE => track_type = read_uint(reader, child)?,
+                    ID_CODEC_ID => codec_id = read_string(reader, child)?,
+                    ID_CODEC_PRIVATE => header = read_string(reader, child)?,
+                    ID_CONTENT_ENCODINGS => encoded = true,
+                    _ => {}
+                }
+                Ok(())
+            },
+        )?;
+        if track_type != TRACK_TYPE_SUBTITLE
+            || !matches!(
+                codec_id.as_str(),
+                "S_TEXT/ASS" | "S_TEXT/SSA" | "S_ASS" | "S_SSA"
+            )
+        {
+            return Ok(());
+        }
+        if encoded {
+            // 压缩或加密过的字幕很少见，不支持解码
+            warn!("Skipped subtitle track {number} which is compressed or encrypted");
+            return Ok(());
+        }
+        tracks.push(SubtitleTrack {
+            number,
+            header,
+            events: Vec::new(),
+        });
+        Ok(())
+    })?;
+    Ok(tracks)
+}
+
+/// 读取块中属于字幕轨道的对话
+fn read_block(
+    reader: &mut (impl Read + Seek),
+    element: &Element,
+    tracks: &mut [SubtitleTrack],
+) -> Result<()> {
+    let Some(size) = element.size else {
+        bail!("block with unknown size");
+    };
+    let (number, len) = read_vint(reader, false)?;
+    let Some(track) = tracks.iter_mut().find(|t| t.number == number) else {
+        return Ok(());
+    };
+    // 跳过 16 位的时间戳，字幕的块不使用 lacing
+    let mut timestamp_and_flags = [0u8; 3];
+    reader.read_exact(&mut timestamp_and_flags)?;
+    let Some(data_size) = size.checked_sub(len as u64 + 3).filter(|&s| s <= 0x1000000) else {
+        bail!("unexpected size of block at offset {}", element.start);
+    };
+    let mut data = vec![0; data_size as usize];
+    reader.read_exact(&mut data)?;
+    track.events.push(
+        String::from_utf8_lossy(&data)
+            .trim_end_matches('\0')
+            .to_string(),
+    );
+    Ok(())
+}
+
+/// 读取 Cluster 中的所有块，Cluster 的长度未知时读到下一个不属于 Cluster 的元素为止，返回 Cluster 的结束位置
+fn read_cluster(
+    reader: &mut (impl Read + Seek),
+    cluster: &Element,
+    segment_end: u64,
+    tracks: &mut [SubtitleTrack],
+) -> Result<u64> {
+    let end = cluster.end().unwrap_or(segment_end);
+    let mut pos = cluster.start;
+    while pos < end {
+        reader.seek(SeekFrom::Start(pos))?;
+        let element = read_element(reader)?;
+        if cluster.size.is_none() && !CLUSTER_CHILDREN.contains(&element.id) {
+            return Ok(pos);
+        }
+        let Some(element_end) = element.end().filter(|&e| e <= end) else {
+            bail!(
+                "element 0x{:X} at offset {pos} exceeds its parent",
+                element.id
+            );
+        };
+        match element.id {
+            ID_SIMPLE_BLOCK => read_block(reader, &element, tracks)?,
+            ID_BLOCK_GROUP => {
+                for_each_child(reader, element.start, element_end, |reader, child| {
+                    if child.id == ID_BLOCK {
+                        read_block(reader, child, tracks)?;
+                    }
+                    Ok(())
+                })?
+            }
+            _ => {}
+        }
+        pos = element_end;
+    }
+    Ok(end)
+}
+
+/// 读取 Matroska 文件中的所有 ASS/SSA 字幕轨道，需要读取所有 Cluster
+pub fn read_subtitle_tracks(reader: &mut (impl Read + Seek)) -> Result<Vec<SubtitleTrack>> {
+    let (segment, segment_end) = read_segment(reader)?;
+    let mut tracks = Vec::new();
+    let mut pos = segment.start;
+    while pos < segment_end {
+        reader.seek(SeekFrom::Start(pos))?;
+        let element = read_element(reader)?;
+        pos = match element.id {
+            ID_TRACKS => {
+                tracks = read_tracks(reader, &element)?;
+                if tracks.is_empty() {
+                    break;
+                }
+                element.end().unwrap()
+            }
+            ID_CLUSTER => read_cluster(reader, &element, segment_end, &mut tracks)?,
+            _ => match element.end() {
+                Some(end) => end,
+                None => bail!(
+                    "element 0x{:X} at offset {pos} has unknown size",
+                    element.id
+                ),
+            },
+        };
+    }
+    Ok(tracks)
+}
+
+/// 用 ID 和数据构建 EBML 元素，长度总是用 8 字节表示
+#[cfg(test)]
+fn test_element(id: u32, data: &[u8]) -> Vec<u8> {
+    let mut element: Vec<u8> = id
+        .to_be_bytes()
+        .into_iter()
+        .skip_while(|&b| b == 0)
+        .collect();
+    element.push(0x01);
+    element.extend_from_slice(&(data.len() as u64).to_be_bytes()[1..]);
+    element.extend_from_slice(data);
+    element
+}
+
+/// 构建测试用的 Matroska 文件，有一个视频轨道、一个 ASS 字幕轨道和 attachments 中的附件（文件名、MIME 类型和数据）
+///
+/// 字幕的对话依次放在长度未知的 Cluster 的 SimpleBlock 和 BlockGroup 中，附件在 Cluster 之后，
+/// seek 为 true 时可以通过 SeekHead 定位。
+#[cfg(test)]
+pub fn test_mkv(
+    header: &str,
+    events: &[&str],
+    attachments: &[(&str, &str, &[u8])],
+    seek: bool,
+) -> Vec<u8> {
+    let uint = |id, value: u64| test_element(id, &value.to_be_bytes());
+    let string = |id, value: &str| test_element(id, value.as_bytes());
+    let tracks = test_element(
+        ID_TRACKS,
+        &[
+            test_element(
+                ID_TRACK_ENTRY,
+                &[
+                    uint(ID_TRACK_NUMBER, 1),
+                    uint(ID_TRACK_TYPE, 1),
+                    string(ID_CODEC_ID, "V_MPEG4/ISO/AVC"),
+                ]
+                .concat(),
+            ),
+            test_element(
+                ID_TRACK_ENTRY,
+                &[
+                    uint(ID_TRACK_NUMBER, 2),
+                    uint(ID_TRACK_TYPE, TRACK_TYPE_SUBTITLE),
+                    string(ID_CODEC_ID, "S_TEXT/ASS"),
+                    string(ID_CODEC_PRIVATE, header),
+                ]
+                .concat(),
+            ),
+        ]
+        .concat(),
+    );
+
+    // 长度未知的 Cluster，视频轨道的块会被跳过
+    let mut cluster = vec![
+        0x1F, 0x43, 0xB6, 0x75, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    ];
+    cluster.extend(test_element(ID_SIMPLE_BLOCK, &[0x81, 0, 0, 0x80, 0, 0, 0]));
+    for (i, event) in events.iter().enumerate() {
+        let mut block = vec![0x82, 0, 0, 0x80];
+        block.extend_from_slice(event.as_bytes());
+        cluster.extend(if i % 2 == 0 {
+            test_element(ID_SIMPLE_BLOCK, &block)
+        } else {
+            test_element(ID_BLOCK_GROUP, &test_element(ID_BLOCK, &block))
+        });
+    }
+
+    let files: Vec<u8> = attachments
+        .iter()
+        .flat_map(|(name, mime_type, data)| {
+            test_element(
+                ID_ATTACHED_FILE,
+                &[
+                    string(ID_FILE_NAME, name),
+                    string(ID_FILE_MIME_TYPE, mime_type),
+                    test_element(ID_FILE_DATA, data),
+                ]
+                .concat(),
+            )
+        })
+        .collect();
+
+    // SeekPosition 的长度固定，可以先用 0 得到 SeekHead 的长度
+    let seek_head = |position| {
+        test_element(
+            ID_SEEK_HEAD,
+            &test_element(
+                ID_SEEK,
+                &[
+                    uint(ID_SEEK_ID, ID_ATTACHMENTS as u64),
+                    uint(ID_SEEK_POSITION, position),
+                ]
+                .concat(),
+            ),
+        )
+    };
+    let mut segment = if seek {
+        let position = seek_head(0).len() + tracks.len() + cluster.len();
+        seek_head(position as u64)
+    } else {
+        Vec::new()
+    };
+    segment.extend(tracks);
+    segment.extend(cluster);
+    segment.extend(test_element(ID_ATTACHMENTS, &files));
+
+    let mut file = test_element(ID_EBML, &string(0x4282, "matroska"));
+    file.extend(test_element(ID_SEGMENT, &segment));
+    file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const HEADER: &str = "[Script Info]\nScriptType: v4.00+\n\n[V4+ Styles]\nFormat: Name, Fontname\nStyle: Default,Test Sans";
+
+    fn mkv(seek: bool) -> Vec<u8> {
+        test_mkv(
+            HEADER,
+            &[
+                "0,0,Default,,0,0,0,,Hello",
+                "1,0,Default,,0,0,0,,{\\fnOther}World",
+            ],
+            &[
+                ("Test.ttf", "font/ttf", b"font data"),
+                ("cover.jpg", "image/jpeg", b"\xFF\xD8\xFF"),
+            ],
+            seek,
+        )
+    }
+
+    #[test]
+    fn attachments() {
+        for seek in [true, false] {
+            let data = mkv(seek);
+            let attachments = read_attachments(&mut Cursor::new(&data)).unwrap();
+            let found: Vec<(&str, &str, &[u8])> = attachments
+                .iter()
+                .map(|a| {
+                    let start = a.offset as usize;
+                    (
+                        a.name.as_str(),
+                        a.mime_type.as_str(),
+                        &data[start..start + a.size as usize],
+                    )
+                })
+                .collect();
+            assert_eq!(
+                found,
+                [
+                    ("Test.ttf", "font/ttf", &b"font data"[..]),
+                    ("cover.jpg", "image/jpeg", &b"\xFF\xD8\xFF"[..]),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn subtitle_tracks() {
+        let tracks = read_subtitle_tracks(&mut Cursor::new(mkv(true))).unwrap();
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks[0].number, 2);
+        assert_eq!(tracks[0].header, HEADER);
+        assert_eq!(
+            tracks[0].events,
+            [
+                "0,0,Default,,0,0,0,,Hello",
+                "1,0,Default,,0,0,0,,{\\fnOther}World"
+            ]
+        );
+    }
+
+    #[test]
+    fn truncated_file() {
+        let data = mkv(true);
+        // 截断在最后一个附件的数据中，附件的位置超出文件
+        let err = read_attachments(&mut Cursor::new(&data[..data.len() - 2])).unwrap_err();
+        assert!(err.to_string().starts_with("truncated Attachments element"));
+        // 截断在 Cluster 的第二个块中，没有 SeekHead 时读取附件也需要逐个跳过块
+        let in_cluster = |data: &[u8]| {
+            let cluster = data
+                .windows(4)
+                .position(|w| w == [0x1F, 0x43, 0xB6, 0x75])
+                .unwrap();
+            data[..cluster + 40].to_vec()
+        };
+        let Err(err) = read_subtitle_tracks(&mut Cursor::new(in_cluster(&data))) else {
+            panic!("read a truncated cluster");
+        };
+        assert!(err.to_string().contains("exceeds its parent"));
+        let err = read_attachments(&mut Cursor::new(in_cluster(&mkv(false)))).unwrap_err();
+        assert!(err.to_string().contains("exceeds its parent"));
+        // 截断在 EBML 头中
+        assert!(read_attachments(&mut Cursor::new(&data[..6])).is_err());
+        assert!(read_attachments(&mut Cursor::new(b"not a matroska file")).is_err());
+    }
+}
@@ -0,0 +1,215 @@
+use crate::sfnt::{read_u16, read_u32};
+use anyhow::{Result, bail};
+
+/// Symbol 编码的字体把字符映射到 U+F000-U+F0FF，GDI 会把 U+0000-U+00FF 映射到这个范围
+const SYMBOL_BASE: u32 = 0xF000;
+
+/// cmap 表中有字形的字符，保存为按码位排序并且互不重叠的区间
+#[derive(Debug, Clone, Default)]
+pub struct CharMap {
+    ranges: Vec<(u32, u32)>,
+}
+
+/// 遍历 cmap 表中最合适的 Unicode 子表里有字形的字符和对应的字形 ID，有完整 Unicode 的子表时优先使用
+///
+/// 只有 Symbol 编码的子表时，U+F000-U+F0FF 的字符同时也会作为 U+0000-U+00FF 的字符。
+pub fn for_each_glyph(table: &[u8], mut op: impl FnMut(u32, u32)) -> Result<()> {
+    let num_subtables = read_u16(table, 2)? as usize;
+    let mut best: Option<(u8, usize)> = None;
+    for i in 0..num_subtables {
+        let record = 4 + i * 8;
+        let platform_id = read_u16(table, record)?;
+        let encoding_id = read_u16(table, record + 2)?;
+        let offset = read_u32(table, record + 4)? as usize;
+        let priority = match (platform_id, encoding_id) {
+            (3, 10) | (0, 4) | (0, 6) => 3,
+            (3, 1) | (0, _) => 2,
+            (3, 0) => 1,
+            _ => continue,
+        };
+        if best.is_none_or(|(p, _)| priority > p) {
+            best = Some((priority, offset));
+        }
+    }
+    let Some((priority, offset)) = best else {
+        bail!("no Unicode subtable in cmap table");
+    };
+    let mut add = |c: u32, glyph: u32| {
+        if glyph == 0 {
+            return;
+        }
+        op(c, glyph);
+        if priority == 1 && (SYMBOL_BASE..=SYMBOL_BASE + 0xFF).contains(&c) {
+            op(c - SYMBOL_BASE, glyph);
+        }
+    };
+    let subtable = table.get(offset..).unwrap_or_default();
+    match read_u16(subtable, 0)? {
+        0 => {
+            for c in 0..256 {
+                if let Some(&glyph) = subtable.get(6 + c) {
+                    add(c as u32, glyph as u32);
+                }
+            }
+        }
+        4 => {
+            let seg_count = read_u16(subtable, 6)? as usize / 2;
+            let end_codes = 14;
+            let start_codes = end_codes + seg_count * 2 + 2;
+            let id_deltas = start_codes + seg_count * 2;
+            let id_range_offsets = id_deltas + seg_count * 2;
+            for seg in 0..seg_count {
+                let end = read_u16(subtable, end_codes + seg * 2)?;
+                let start = read_u16(subtable, start_codes + seg * 2)?;
+                let delta = read_u16(subtable, id_deltas + seg * 2)?;
+                let range_offset_pos = id_range_offsets + seg * 2;
+                let range_offset = read_u16(subtable, range_offset_pos)? as usize;
+                for c in start..=end.min(0xFFFE) {
+                    let glyph = if range_offset == 0 {
+                        c.wrapping_add(delta)
+                    } else {
+                        let pos = range_offset_pos + range_offset + (c - start) as usize * 2;
+                        match read_u16(subtable, pos)? {
+                            0 => 0,
+                            glyph => glyph.wrapping_add(delta),
+                        }
+                    };
+                    add(c as u32, glyph as u32);
+                }
+            }
+        }
+        6 => {
+            let first = read_u16(subtable, 6)? as u32;
+            let count = read_u16(subtable, 8)? as usize;
+            for i in 0..count {
+                add(first + i as u32, read_u16(subtable, 10 + i * 2)? as u32);
+            }
+        }
+        format @ (12 | 13) => {
+            let num_groups = read_u32(subtable, 12)? as usize;
+            for i in 0..num_groups {
+                let group = 16 + i * 12;
+                let start = read_u32(subtable, group)?;
+                let end = read_u32(subtable, group + 4)?.min(char::MAX as u32);
+                let glyph = read_u32(subtable, group + 8)?;
+                // format 12 中的字形 ID 是递增的，format 13 中所有字符都使用同一个字形
+                for c in start..=end {
+                    add(
+                        c,
+                        if format == 12 {
+                            glyph + (c - start)
+                        } else {
+                            glyph
+                        },
+                    );
+                }
+            }
+        }
+        format => bail!("unsupported cmap subtable format {format}"),
+    }
+    Ok(())
+}
+
+impl CharMap {
+    /// 读取 cmap 表中有字形的字符
+    pub fn parse(table: &[u8]) -> Result<Self> {
+        let mut ranges: Vec<(u32, u32)> = Vec::new();
+        for_each_glyph(table, |c, _| match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == c => *end = c,
+            _ => ranges.push((c, c)),
+        })?;
+        ranges.sort();
+        let mut merged: Vec<(u32, u32)> = Vec::with_capacity(ranges.len());
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some((_, last)) if start <= *last + 1 => *last = (*last).max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        Ok(Self { ranges: merged })
+    }
+
+    /// 字体中是否有这个字符的字形
+    pub fn contains(&self, c: char) -> bool {
+        let c = c as u32;
+        let i = self.ranges.partition_point(|&(start, _)| start <= c);
+        i > 0 && self.ranges[i - 1].1 >= c
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 只有一个子表的 cmap 表
+    fn cmap(platform_id: u16, encoding_id: u16, subtable: &[u8]) -> Vec<u8> {
+        let mut table = Vec::new();
+        for value in [0, 1, platform_id, encoding_id] {
+            table.extend_from_slice(&u16::to_be_bytes(value));
+        }
+        table.extend_from_slice(&12u32.to_be_bytes());
+        table.extend_from_slice(subtable);
+        table
+    }
+
+    fn words(values: &[u16]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_be_bytes()).collect()
+    }
+
+    #[test]
+    fn format_4_with_deltas_and_glyph_array() {
+        // 第一段 A-C 使用 idDelta，第二段 a-c 使用 glyphIdArray，其中 b 没有字形
+        let subtable = words(&[
+            4, 0, 0, 6, 0, 0, 0, // format, length, language, segCountX2 和搜索参数
+            0x43, 0x63, 0xFFFF, // endCode
+            0,      // reservedPad
+            0x41, 0x61, 0xFFFF, // startCode
+            0xFFC0, 0, 1, // idDelta
+            0, 4, 0, // idRangeOffset
+            5, 0, 6, // glyphIdArray
+        ]);
+        let chars = CharMap::parse(&cmap(3, 1, &subtable)).unwrap();
+        for (c, expected) in [
+            ('@', false),
+            ('A', true),
+            ('C', true),
+            ('D', false),
+            ('a', true),
+            ('b', false),
+            ('c', true),
+            ('\u{FFFF}', false),
+        ] {
+            assert_eq!(chars.contains(c), expected, "{c:?}");
+        }
+    }
+
+    #[test]
+    fn format_12_outside_bmp() {
+        let mut subtable = words(&[12, 0]);
+        for value in [40u32, 0, 2, 0x1F600, 0x1F602, 1, 0x20000, 0x20000, 4] {
+            subtable.extend_from_slice(&value.to_be_bytes());
+        }
+        let chars = CharMap::parse(&cmap(3, 10, &subtable)).unwrap();
+        assert!(chars.contains('😀'));
+        assert!(chars.contains('😂'));
+        assert!(!chars.contains('😃'));
+        assert!(chars.contains('\u{20000}'));
+        assert!(!chars.contains('A'));
+    }
+
+    #[test]
+    fn symbol_font() {
+        let subtable = words(&[
+            4, 0, 0, 4, 0, 0, 0, // format, length, language, segCountX2 和搜索参数
+            0xF041, 0xFFFF, // endCode
+            0,      // reservedPad
+            0xF041, 0xFFFF, // startCode
+            0x1000, 1, // idDelta
+            0, 0, // idRangeOffset
+        ]);
+        let chars = CharMap::parse(&cmap(3, 0, &subtable)).unwrap();
+        assert!(chars.contains('\u{F041}'));
+        assert!(chars.contains('A'));
+        assert!(!chars.contains('B'));
+    }
+}
@@ -0,0 +1,202 @@
+use anyhow::{Result, bail};
+
+mod cmap;
+pub use cmap::CharMap;
+
+mod name;
+#[cfg(any(feature = "library", test))]
+pub use name::NameRecord;
+pub use name::{FontNames, NAME_ID_FAMILY, NAME_ID_FULL_NAME, NAME_ID_TYPOGRAPHIC_FAMILY};
+
+mod style;
+pub use style::{FontStyle, WIDTH_NORMAL};
+
+mod validate;
+pub use validate::validate;
+
+mod version;
+pub use version::FontVersion;
+
+#[cfg(feature = "woff")]
+mod woff;
+#[cfg(feature = "woff")]
+mod woff2;
+#[cfg(any(feature = "woff", feature = "subset", test))]
+mod writer;
+#[cfg(test)]
+pub use writer::{Table, build_collection, test_font};
+
+#[cfg(feature = "subset")]
+mod subset;
+#[cfg(feature = "subset")]
+pub use subset::subset;
+
+/// 读取大端序的 u16
+pub fn read_u16(data: &[u8], offset: usize) -> Result<u16> {
+    match data.get(offset..offset + 2) {
+        Some(b) => Ok(u16::from_be_bytes([b[0], b[1]])),
+        None => bail!("unexpected end of data at offset {offset}"),
+    }
+}
+
+/// 读取大端序的 u32
+pub fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    match data.get(offset..offset + 4) {
+        Some(b) => Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]])),
+        None => bail!("unexpected end of data at offset {offset}"),
+    }
+}
+
+/// 计算表的校验和
+pub fn checksum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0u32, |sum, chunk| {
+        let mut word = [0u8; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(u32::from_be_bytes(word))
+    })
+}
+
+/// 字体文件中的一个字体，对于 TTC/OTC 字体集合来说一个文件可以有多个字体
+#[derive(Debug, Clone)]
+pub struct Face {
+    /// 字体在字体集合中的序号，单个字体文件是 0
+    pub index: usize,
+    pub names: FontNames,
+    pub version: FontVersion,
+    pub style: FontStyle,
+    /// 有字形的字符，没有 cmap 表或无法读取时为 None
+    pub chars: Option<CharMap>,
+}
+
+/// 字体文件中每个字体的表目录的偏移，单个字体文件只有一个偏移为 0 的字体
+pub fn face_offsets(data: &[u8]) -> Result<Vec<usize>> {
+    if data.get(0..4) != Some(b"ttcf") {
+        return Ok(vec![0]);
+    }
+    let num_fonts = read_u32(data, 8)? as usize;
+    if num_fonts == 0 {
+        bail!("empty font collection");
+    }
+    (0..num_fonts)
+        .map(|i| read_u32(data, 12 + i * 4).map(|offset| offset as usize))
+        .collect()
+}
+
+/// 读取字体文件中的每个字体，单个字体读取失败不影响其他字体
+pub fn read_faces(data: &[u8]) -> Result<Vec<Result<Face>>> {
+    Ok(face_offsets(data)?
+        .into_iter()
+        .enumerate()
+        .map(|(index, offset)| {
+            let font = Font::parse(data, offset)?;
+            let names = font.names()?;
+            let version = FontVersion {
+                text: names.version.clone(),
+                revision: font.table(b"head")?.and_then(|head| read_u32(head, 4).ok()),
+            };
+            let style = FontStyle::read(&font)?;
+            let chars = match font.table(b"cmap")? {
+                Some(table) => CharMap::parse(table).ok(),
+                None => None,
+            };
+            Ok(Face {
+                index,
+                names,
+                version,
+                style,
+                chars,
+            })
+        })
+        .collect())
+}
+
+/// 把 WOFF/WOFF2 字体解码为 TTF/OTF/TTC，返回字体数据和对应的扩展名
+#[cfg(feature = "woff")]
+pub fn decode_web_font(data: &[u8]) -> Result<(Vec<u8>, &'static str)> {
+    let (flavor, font) = match data.get(0..4) {
+        Some(b"wOFF") => woff::decode(data)?,
+        Some(b"wOF2") => woff2::decode(data)?,
+        _ => bail!("not a WOFF or WOFF2 font"),
+    };
+    let extension = match &flavor.to_be_bytes() {
+        b"OTTO" => "otf",
+        b"ttcf" => "ttc",
+        _ => "ttf",
+    };
+    Ok((font, extension))
+}
+
+/// 表目录中的一项
+#[derive(Debug, Clone, Copy)]
+pub struct TableRecord {
+    pub tag: [u8; 4],
+    pub checksum: u32,
+    pub offset: u32,
+    pub length: u32,
+}
+
+impl TableRecord {
+    pub fn tag_str(&self) -> String {
+        String::from_utf8_lossy(&self.tag).into_owned()
+    }
+}
+
+/// sfnt 格式（TrueType/OpenType）的一个字体
+pub struct Font<'a> {
+    data: &'a [u8],
+    pub tables: Vec<TableRecord>,
+}
+
+impl<'a> Font<'a> {
+    /// 从 offset 处的表目录开始解析字体，offset 对于单个字体文件是 0
+    pub fn parse(data: &'a [u8], offset: usize) -> Result<Self> {
+        let sfnt_version = read_u32(data, offset)?;
+        if !matches!(sfnt_version, 0x00010000 | 0x4F54544F | 0x74727565) {
+            bail!("unknown sfnt version 0x{sfnt_version:08X}");
+        }
+        let num_tables = read_u16(data, offset + 4)? as usize;
+        let mut tables = Vec::with_capacity(num_tables);
+        for i in 0..num_tables {
+            let record = offset + 12 + i * 16;
+            if record + 16 > data.len() {
+                bail!("truncated table directory at offset {record}");
+            }
+            tables.push(TableRecord {
+                tag: data[record..record + 4].try_into().unwrap(),
+                checksum: read_u32(data, record + 4)?,
+                offset: read_u32(data, record + 8)?,
+                length: read_u32(data, record + 12)?,
+            });
+        }
+        Ok(Self { data, tables })
+    }
+
+    /// 获取表的记录
+    pub fn table_record(&self, tag: &[u8; 4]) -> Option<&TableRecord> {
+        self.tables.iter().find(|t| &t.tag == tag)
+    }
+
+    /// 获取表的数据，表不存在时返回 None，超出文件范围时返回错误
+    pub fn table(&self, tag: &[u8; 4]) -> Result<Option<&'a [u8]>> {
+        let Some(record) = self.table_record(tag) else {
+            return Ok(None);
+        };
+        let start = record.offset as usize;
+        let end = start + record.length as usize;
+        match self.data.get(start..end) {
+            Some(table) => Ok(Some(table)),
+            None => bail!(
+                "truncated {} table at offset {start}",
+                record.tag_str().trim_end()
+            ),
+        }
+    }
+
+    /// 读取 name 表中的名称
+    pub fn names(&self) -> Result<FontNames> {
+        match self.table(b"name")? {
+            Some(table) => FontNames::parse(table),
+            None => bail!("missing name table"),
+        }
+    }
+}
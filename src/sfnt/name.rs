@@ -0,0 +1,319 @@
+use crate::sfnt::read_u16;
+use anyhow::Result;
+use encoding_rs::{BIG5, EUC_KR, Encoding, GBK, SHIFT_JIS};
+use std::fmt;
+
+/// 读取的 name ID
+pub const NAME_ID_FAMILY: u16 = 1;
+const NAME_ID_SUBFAMILY: u16 = 2;
+pub const NAME_ID_FULL_NAME: u16 = 4;
+const NAME_ID_VERSION: u16 = 5;
+pub const NAME_ID_TYPOGRAPHIC_FAMILY: u16 = 16;
+const NAME_ID_TYPOGRAPHIC_SUBFAMILY: u16 = 17;
+
+/// Mac Roman 编码中 0x80-0xFF 对应的字符
+const MAC_ROMAN_HIGH: &str = concat!(
+    "ÄÅÇÉÑÖÜáàâäãåçéèêëíìîïñóòôöõúùûü",
+    "†°¢£§•¶ß®©™´¨≠ÆØ∞±≤≥¥µ∂∑∏π∫ªºΩæø",
+    "¿¡¬√ƒ≈∆«»…\u{A0}ÀÃÕŒœ–—“”‘’÷◊ÿŸ⁄€‹›ﬁﬂ",
+    "‡·‚„‰ÂÊÁËÈÍÎÏÌÓÔ\u{F8FF}ÒÚÛÙıˆ˜¯˘˙˚¸˝˛ˇ",
+);
+
+/// name 表中的一条记录
+#[derive(Debug, Clone)]
+pub struct NameRecord {
+    pub platform_id: u16,
+    pub language_id: u16,
+    pub name_id: u16,
+    pub value: String,
+}
+
+impl NameRecord {
+    /// 记录的语言标签，只识别英文、中文、日文和韩文
+    pub fn language(&self) -> Option<&'static str> {
+        match (self.platform_id, self.language_id) {
+            (3, 0x0409) | (1, 0) => Some("en-US"),
+            (3, 0x0804) | (1, 33) => Some("zh-CN"),
+            (3, 0x0404) | (1, 19) => Some("zh-TW"),
+            (3, 0x0C04) => Some("zh-HK"),
+            (3, 0x1004) => Some("zh-SG"),
+            (3, 0x1404) => Some("zh-MO"),
+            (3, 0x0411) | (1, 11) => Some("ja-JP"),
+            (3, 0x0412) | (1, 23) => Some("ko-KR"),
+            (3, id) if id & 0x3FF == 0x09 => Some("en"),
+            _ => None,
+        }
+    }
+
+    /// 是否为英文记录
+    pub fn is_english(&self) -> bool {
+        self.language().is_some_and(|l| l.starts_with("en"))
+    }
+
+    /// 是否为中文或日文记录
+    pub fn is_cjk(&self) -> bool {
+        self.language()
+            .is_some_and(|l| l.starts_with("zh") || l.starts_with("ja"))
+    }
+}
+
+/// 从 name 表中读取的字体名称
+#[derive(Debug, Clone, Default)]
+pub struct FontNames {
+    pub family: Option<String>,
+    pub subfamily: Option<String>,
+    pub full_name: Option<String>,
+    pub typographic_family: Option<String>,
+    pub typographic_subfamily: Option<String>,
+    /// 版本字符串
+    pub version: Option<String>,
+    /// 所有能够解码的家族、样式、全名和版本记录，包括本地化的记录
+    pub records: Vec<NameRecord>,
+}
+
+impl FontNames {
+    pub fn parse(table: &[u8]) -> Result<Self> {
+        let count = read_u16(table, 2)? as usize;
+        let storage = read_u16(table, 4)? as usize;
+        let mut records = Vec::new();
+        for i in 0..count {
+            let record = 6 + i * 12;
+            let platform_id = read_u16(table, record)?;
+            let encoding_id = read_u16(table, record + 2)?;
+            let language_id = read_u16(table, record + 4)?;
+            let name_id = read_u16(table, record + 6)?;
+            if !matches!(
+                name_id,
+                NAME_ID_FAMILY
+                    | NAME_ID_SUBFAMILY
+                    | NAME_ID_FULL_NAME
+                    | NAME_ID_VERSION
+                    | NAME_ID_TYPOGRAPHIC_FAMILY
+                    | NAME_ID_TYPOGRAPHIC_SUBFAMILY
+            ) {
+                continue;
+            }
+            let length = read_u16(table, record + 8)? as usize;
+            let offset = storage + read_u16(table, record + 10)? as usize;
+            let Some(bytes) = table.get(offset..offset + length) else {
+                continue;
+            };
+            let Some(value) = decode(platform_id, encoding_id, bytes) else {
+                continue;
+            };
+            let value = value.trim_end_matches('\0').trim().to_string();
+            if value.is_empty() {
+                continue;
+            }
+            records.push(NameRecord {
+                platform_id,
+                language_id,
+                name_id,
+                value,
+            });
+        }
+
+        Ok(Self::from_records(records))
+    }
+
+    /// 根据名称记录选出各个名称，例如从索引中读取的记录
+    pub fn from_records(records: Vec<NameRecord>) -> Self {
+        let mut names = Self {
+            records,
+            ..Default::default()
+        };
+        names.family = names.preferred(NAME_ID_FAMILY);
+        names.subfamily = names.preferred(NAME_ID_SUBFAMILY);
+        names.full_name = names.preferred(NAME_ID_FULL_NAME);
+        names.typographic_family = names.preferred(NAME_ID_TYPOGRAPHIC_FAMILY);
+        names.typographic_subfamily = names.preferred(NAME_ID_TYPOGRAPHIC_SUBFAMILY);
+        names.version = names.preferred(NAME_ID_VERSION);
+        names
+    }
+
+    /// 选出某个 name ID 最合适的记录，优先使用 Windows 平台的英文记录
+    fn preferred(&self, name_id: u16) -> Option<String> {
+        self.records
+            .iter()
+            .filter(|r| r.name_id == name_id)
+            .min_by_key(|r| match (r.platform_id, r.language_id) {
+                (3, 0x0409) => 0,
+                (3, _) if r.is_english() => 1,
+                (1, 0) => 2,
+                (0, _) => 3,
+                _ => 4,
+            })
+            .map(|r| r.value.clone())
+    }
+
+    /// 家族名称，优先使用 typographic family
+    pub fn family_name(&self) -> Option<&str> {
+        self.typographic_family
+            .as_deref()
+            .or(self.family.as_deref())
+    }
+
+    /// 样式名称，优先使用 typographic subfamily
+    pub fn style_name(&self) -> Option<&str> {
+        self.typographic_subfamily
+            .as_deref()
+            .or(self.subfamily.as_deref())
+    }
+
+    /// 家族名称及其中文和日文名称，用于日志输出
+    pub fn family_label(&self) -> Option<String> {
+        let family = self.family_name()?;
+        let localized = self.localized_families();
+        if localized.is_empty() {
+            Some(family.to_string())
+        } else {
+            Some(format!("{family} ({})", localized.join(", ")))
+        }
+    }
+
+    /// 与英文名称不同的中文和日文家族名称
+    pub fn localized_families(&self) -> Vec<&str> {
+        let family = self.family_name();
+        let mut localized: Vec<&str> = Vec::new();
+        for name_id in [NAME_ID_TYPOGRAPHIC_FAMILY, NAME_ID_FAMILY] {
+            for r in &self.records {
+                if r.name_id == name_id
+                    && r.is_cjk()
+                    && Some(r.value.as_str()) != family
+                    && !localized.contains(&r.value.as_str())
+                {
+                    localized.push(&r.value);
+                }
+            }
+            if !localized.is_empty() {
+                break;
+            }
+        }
+        localized
+    }
+}
+
+impl fmt::Display for FontNames {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.family_name(), self.style_name()) {
+            (Some(family), Some(style)) => write!(f, "{family} {style}")?,
+            (Some(family), None) => write!(f, "{family}")?,
+            (None, _) => write!(f, "{}", self.full_name.as_deref().unwrap_or("(unnamed)"))?,
+        }
+        let localized = self.localized_families();
+        if !localized.is_empty() {
+            write!(f, " ({})", localized.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// 解码名称记录，不支持的编码返回 None
+fn decode(platform_id: u16, encoding_id: u16, bytes: &[u8]) -> Option<String> {
+    match (platform_id, encoding_id) {
+        (0, _) | (3, 0) | (3, 1) | (3, 10) => {
+            let units: Vec<u16> = bytes
+                .chunks_exact(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                .collect();
+            Some(String::from_utf16_lossy(&units))
+        }
+        (1, 0) => Some(
+            bytes
+                .iter()
+                .map(|&b| {
+                    if b < 0x80 {
+                        b as char
+                    } else {
+                        MAC_ROMAN_HIGH.chars().nth(b as usize - 0x80).unwrap()
+                    }
+                })
+                .collect(),
+        ),
+        // Windows 平台的旧式中日韩编码按 16 位存储，单字节的字符前面补 0，和 fontconfig 一样去掉补的 0
+        (3, 2..=5) => {
+            let bytes: Vec<u8> = bytes.iter().copied().filter(|&b| b != 0).collect();
+            Some(
+                legacy_encoding(platform_id, encoding_id)?
+                    .decode_without_bom_handling(&bytes)
+                    .0
+                    .into_owned(),
+            )
+        }
+        (1, 1..=3 | 25) => Some(
+            legacy_encoding(platform_id, encoding_id)?
+                .decode_without_bom_handling(bytes)
+                .0
+                .into_owned(),
+        ),
+        _ => None,
+    }
+}
+
+/// Windows 和 Mac 平台的中日韩编码
+fn legacy_encoding(platform_id: u16, encoding_id: u16) -> Option<&'static Encoding> {
+    match (platform_id, encoding_id) {
+        (3, 2) | (1, 1) => Some(SHIFT_JIS),
+        (3, 3) | (1, 25) => Some(GBK),
+        (3, 4) | (1, 2) => Some(BIG5),
+        (3, 5) | (1, 3) => Some(EUC_KR),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 用 (platform ID, encoding ID, language ID, name ID, 数据) 构建 name 表
+    fn name_table(records: &[(u16, u16, u16, u16, &[u8])]) -> Vec<u8> {
+        let mut table = Vec::new();
+        let mut storage = Vec::new();
+        for value in [0, records.len() as u16, 6 + records.len() as u16 * 12] {
+            table.extend_from_slice(&value.to_be_bytes());
+        }
+        for (platform_id, encoding_id, language_id, name_id, bytes) in records {
+            for value in [
+                *platform_id,
+                *encoding_id,
+                *language_id,
+                *name_id,
+                bytes.len() as u16,
+                storage.len() as u16,
+            ] {
+                table.extend_from_slice(&value.to_be_bytes());
+            }
+            storage.extend_from_slice(bytes);
+        }
+        table.extend_from_slice(&storage);
+        table
+    }
+
+    #[test]
+    fn legacy_cjk_encodings() {
+        let table = name_table(&[
+            (3, 1, 0x0409, 1, &[0, b'H', 0, b'e', 0, b'i']),
+            // GBK 的“黑体”，单字节的 S 前面补 0
+            (3, 3, 0x0804, 1, &[0xBA, 0xDA, 0xCC, 0xE5, 0, b'S']),
+            // Shift-JIS 的“ゴシック”
+            (
+                3,
+                2,
+                0x0411,
+                1,
+                &[0x83, 0x53, 0x83, 0x56, 0x83, 0x62, 0x83, 0x4E],
+            ),
+            // Mac 平台 Big5 的“黑體”
+            (1, 2, 19, 1, &[0xB6, 0xC2, 0xC5, 0xE9]),
+        ]);
+        let names = FontNames::parse(&table).unwrap();
+        assert_eq!(names.family.as_deref(), Some("Hei"));
+        let zh_cn = &names.records[1];
+        assert_eq!(zh_cn.value, "黑体S");
+        assert_eq!(zh_cn.language(), Some("zh-CN"));
+        assert_eq!(names.records[2].value, "ゴシック");
+        assert_eq!(names.records[3].value, "黑體");
+        assert_eq!(names.records[3].language(), Some("zh-TW"));
+        assert_eq!(names.localized_families(), ["黑体S", "ゴシック", "黑體"]);
+    }
+}
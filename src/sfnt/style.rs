@@ -0,0 +1,65 @@
+use crate::sfnt::{Font, read_u16};
+use anyhow::Result;
+
+/// OS/2 表中 fsSelection 的标志位
+const FS_SELECTION_ITALIC: u16 = 0x0001;
+const FS_SELECTION_BOLD: u16 = 0x0020;
+const FS_SELECTION_OBLIQUE: u16 = 0x0200;
+
+/// head 表中 macStyle 的标志位
+const MAC_STYLE_BOLD: u16 = 0x0001;
+const MAC_STYLE_ITALIC: u16 = 0x0002;
+
+/// usWidthClass 中的正常宽度
+pub const WIDTH_NORMAL: u16 = 5;
+
+/// 字体的样式，字重和宽度和 OS/2 表中的 usWeightClass 和 usWidthClass 一样
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FontStyle {
+    pub weight: u16,
+    pub italic: bool,
+    pub width: u16,
+}
+
+impl Default for FontStyle {
+    fn default() -> Self {
+        Self {
+            weight: 400,
+            italic: false,
+            width: WIDTH_NORMAL,
+        }
+    }
+}
+
+impl FontStyle {
+    /// 从 OS/2 表和 head 表中读取样式，没有 OS/2 表时根据 macStyle 判断粗体和斜体
+    ///
+    /// 有些旧字体的 usWeightClass 是 1-9，按 100-900 处理。
+    pub fn read(font: &Font) -> Result<Self> {
+        let mac_style = match font.table(b"head")? {
+            Some(head) => read_u16(head, 44)?,
+            None => 0,
+        };
+        let (weight, width, fs_selection) = match font.table(b"OS/2")? {
+            Some(os2) => (read_u16(os2, 4)?, read_u16(os2, 6)?, read_u16(os2, 62)?),
+            None => (0, WIDTH_NORMAL, 0),
+        };
+        let bold = fs_selection & FS_SELECTION_BOLD != 0 || mac_style & MAC_STYLE_BOLD != 0;
+        let weight = match weight {
+            0 if bold => 700,
+            0 => 400,
+            1..=9 => weight * 100,
+            _ => weight.min(1000),
+        };
+        Ok(Self {
+            weight,
+            italic: fs_selection & (FS_SELECTION_ITALIC | FS_SELECTION_OBLIQUE) != 0
+                || mac_style & MAC_STYLE_ITALIC != 0,
+            width: if (1..=9).contains(&width) {
+                width
+            } else {
+                WIDTH_NORMAL
+            },
+        })
+    }
+}
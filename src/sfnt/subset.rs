@@ -0,0 +1,461 @@
+use crate::sfnt::{
+    Font, cmap, face_offsets, read_u16, read_u32,
+    writer::{Table, build_font},
+};
+use anyhow::{Result, anyhow, bail};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// 子集化后原样保留的表，其他没有重新构建的表（包括 GSUB 和 GPOS 等排版表）中的字形 ID 无法对应，会被去掉
+const COPIED_TABLES: [&[u8; 4]; 5] = [b"OS/2", b"cvt ", b"fpgm", b"prep", b"gasp"];
+
+/// 从原来的 name 表中保留的记录，包括版权、版本、制造商和许可证等信息
+const COPIED_NAME_IDS: [u16; 10] = [0, 5, 7, 8, 9, 10, 11, 12, 13, 14];
+
+/// OS/2 表中 fsType 的标志位
+const FS_TYPE_RESTRICTED: u16 = 0x0002;
+const FS_TYPE_EMBEDDING_ALLOWED: u16 = 0x000C;
+const FS_TYPE_NO_SUBSETTING: u16 = 0x0100;
+
+/// 复合字形中每个部件的标志位
+const ARG_1_AND_2_ARE_WORDS: u16 = 0x0001;
+const WE_HAVE_A_SCALE: u16 = 0x0008;
+const MORE_COMPONENTS: u16 = 0x0020;
+const WE_HAVE_AN_X_AND_Y_SCALE: u16 = 0x0040;
+const WE_HAVE_A_TWO_BY_TWO: u16 = 0x0080;
+
+/// 子集化得到的字体
+pub struct Subset {
+    pub data: Vec<u8>,
+    /// 是否去掉了没有使用的字形，CFF 字体和不允许子集化的字体只会修改名称
+    pub subsetted: bool,
+}
+
+/// 把字体文件中的第 index 个字体子集化为只包含 chars 中的字符的 TrueType 字体，并把家族名称改为 family
+///
+/// 字形 ID 会重新编号，所以 GSUB 和 GPOS 等排版表会被去掉，竖排和连字等功能在子集中不可用。
+/// fsType 禁止嵌入的字体会返回错误。
+pub fn subset(data: &[u8], index: usize, chars: &BTreeSet<char>, family: &str) -> Result<Subset> {
+    let offset = *face_offsets(data)?
+        .get(index)
+        .ok_or_else(|| anyhow!("face #{index} not found"))?;
+    let font = Font::parse(data, offset)?;
+    let fs_type = match font.table(b"OS/2")? {
+        Some(os2) => read_u16(os2, 8)?,
+        None => 0,
+    };
+    if fs_type & FS_TYPE_RESTRICTED != 0 && fs_type & FS_TYPE_EMBEDDING_ALLOWED == 0 {
+        bail!("font does not allow embedding");
+    }
+    let name = build_name(&font, family)?;
+
+    let (Some(glyf), Some(loca)) = (font.table(b"glyf")?, font.table(b"loca")?) else {
+        return rename(data, offset, &font, name);
+    };
+    if fs_type & FS_TYPE_NO_SUBSETTING != 0 {
+        return rename(data, offset, &font, name);
+    }
+    let table = |tag: &[u8; 4]| -> Result<&[u8]> {
+        font.table(tag)?
+            .ok_or_else(|| anyhow!("missing {} table", String::from_utf8_lossy(tag).trim_end()))
+    };
+    let head = table(b"head")?;
+    let hhea = table(b"hhea")?;
+    let hmtx = table(b"hmtx")?;
+    let maxp = table(b"maxp")?;
+    let num_glyphs = read_u16(maxp, 4)? as u32;
+    let long_loca = read_u16(head, 50)? == 1;
+    let glyph = |id: u16| -> Result<&[u8]> {
+        let (start, end) = if long_loca {
+            let i = id as usize * 4;
+            (read_u32(loca, i)? as usize, read_u32(loca, i + 4)? as usize)
+        } else {
+            let i = id as usize * 2;
+            (
+                read_u16(loca, i)? as usize * 2,
+                read_u16(loca, i + 2)? as usize * 2,
+            )
+        };
+        glyf.get(start..end)
+            .ok_or_else(|| anyhow!("glyph {id} is out of the glyf table"))
+    };
+
+    // 需要的字符对应的字形，以及复合字形引用的所有部件
+    let mut mapping: BTreeMap<u32, u16> = BTreeMap::new();
+    cmap::for_each_glyph(table(b"cmap")?, |c, id| {
+        if id < num_glyphs && char::from_u32(c).is_some_and(|c| chars.contains(&c)) {
+            mapping.insert(c, id as u16);
+        }
+    })?;
+    let mut glyphs: BTreeSet<u16> = mapping.values().copied().collect();
+    glyphs.insert(0);
+    let mut queue: Vec<u16> = glyphs.iter().copied().collect();
+    while let Some(id) = queue.pop() {
+        for (_, component) in components(glyph(id)?)? {
+            if (component as u32) < num_glyphs && glyphs.insert(component) {
+                queue.push(component);
+            }
+        }
+    }
+    let new_ids: HashMap<u16, u16> = glyphs
+        .iter()
+        .enumerate()
+        .map(|(new, &old)| (old, new as u16))
+        .collect();
+
+    let mut new_glyf = Vec::new();
+    let mut new_loca = Vec::new();
+    let mut new_hmtx = Vec::new();
+    let num_h_metrics = read_u16(hhea, 34)? as usize;
+    for &id in &glyphs {
+        let mut data = glyph(id)?.to_vec();
+        for (pos, component) in components(&data)? {
+            let new_id = new_ids.get(&component).copied().unwrap_or(0);
+            data[pos..pos + 2].copy_from_slice(&new_id.to_be_bytes());
+        }
+        new_loca.extend_from_slice(&(new_glyf.len() as u32).to_be_bytes());
+        new_glyf.extend_from_slice(&data);
+        new_glyf.resize(new_glyf.len().next_multiple_of(4), 0);
+
+        // 超出 numberOfHMetrics 的字形使用最后一个步进宽度
+        let id = id as usize;
+        let advance = read_u16(hmtx, id.min(num_h_metrics - 1) * 4)?;
+        let lsb = if id < num_h_metrics {
+            read_u16(hmtx, id * 4 + 2)?
+        } else {
+            read_u16(hmtx, num_h_metrics * 4 + (id - num_h_metrics) * 2)?
+        };
+        new_hmtx.extend_from_slice(&advance.to_be_bytes());
+        new_hmtx.extend_from_slice(&lsb.to_be_bytes());
+    }
+    new_loca.extend_from_slice(&(new_glyf.len() as u32).to_be_bytes());
+    let num_glyphs = (glyphs.len() as u16).to_be_bytes();
+
+    let mut new_head = head.to_vec();
+    new_head[50..52].copy_from_slice(&1u16.to_be_bytes());
+    let mut new_hhea = hhea.to_vec();
+    new_hhea[34..36].copy_from_slice(&num_glyphs);
+    let mut new_maxp = maxp.to_vec();
+    new_maxp[4..6].copy_from_slice(&num_glyphs);
+    let new_mapping: BTreeMap<u32, u16> = mapping
+        .into_iter()
+        .map(|(c, id)| (c, new_ids[&id]))
+        .collect();
+
+    let mut tables = vec![
+        Table {
+            tag: *b"head",
+            data: new_head,
+        },
+        Table {
+            tag: *b"hhea",
+            data: new_hhea,
+        },
+        Table {
+            tag: *b"maxp",
+            data: new_maxp,
+        },
+        Table {
+            tag: *b"hmtx",
+            data: new_hmtx,
+        },
+        Table {
+            tag: *b"glyf",
+            data: new_glyf,
+        },
+        Table {
+            tag: *b"loca",
+            data: new_loca,
+        },
+        Table {
+            tag: *b"cmap",
+            data: build_cmap(&new_mapping)?,
+        },
+        Table {
+            tag: *b"name",
+            data: name,
+        },
+    ];
+    // post 表改为不包含字形名称的 3.0 版本
+    if let Some(post) = font.table(b"post")?.filter(|post| post.len() >= 32) {
+        let mut post = post[..32].to_vec();
+        post[0..4].copy_from_slice(&0x00030000u32.to_be_bytes());
+        tables.push(Table {
+            tag: *b"post",
+            data: post,
+        });
+    }
+    for tag in COPIED_TABLES {
+        if let Some(data) = font.table(tag)? {
+            tables.push(Table {
+                tag: *tag,
+                data: data.to_vec(),
+            });
+        }
+    }
+    Ok(Subset {
+        data: build_font(0x00010000, &tables),
+        subsetted: true,
+    })
+}
+
+/// 只替换 name 表，用于无法子集化的字体，数字签名会失效所以去掉 DSIG 表
+fn rename(data: &[u8], offset: usize, font: &Font, name: Vec<u8>) -> Result<Subset> {
+    let mut tables = Vec::new();
+    for record in &font.tables {
+        if matches!(&record.tag, b"name" | b"DSIG") {
+            continue;
+        }
+        tables.push(Table {
+            tag: record.tag,
+            data: font.table(&record.tag)?.unwrap().to_vec(),
+        });
+    }
+    tables.push(Table {
+        tag: *b"name",
+        data: name,
+    });
+    Ok(Subset {
+        data: build_font(read_u32(data, offset)?, &tables),
+        subsetted: false,
+    })
+}
+
+/// 复合字形中每个部件的字形 ID 和它在字形数据中的位置，简单字形没有部件
+fn components(glyph: &[u8]) -> Result<Vec<(usize, u16)>> {
+    let mut components = Vec::new();
+    if glyph.len() < 10 || (read_u16(glyph, 0)? as i16) >= 0 {
+        return Ok(components);
+    }
+    let mut pos = 10;
+    loop {
+        let flags = read_u16(glyph, pos)?;
+        components.push((pos + 2, read_u16(glyph, pos + 2)?));
+        pos += 4;
+        pos += if flags & ARG_1_AND_2_ARE_WORDS != 0 {
+            4
+        } else {
+            2
+        };
+        if flags & WE_HAVE_A_SCALE != 0 {
+            pos += 2;
+        } else if flags & WE_HAVE_AN_X_AND_Y_SCALE != 0 {
+            pos += 4;
+        } else if flags & WE_HAVE_A_TWO_BY_TWO != 0 {
+            pos += 8;
+        }
+        if flags & MORE_COMPONENTS == 0 {
+            return Ok(components);
+        }
+    }
+}
+
+/// 构建包含 format 4 和 format 12 子表的 cmap 表，每一段是字符和字形 ID 都连续的范围
+fn build_cmap(mapping: &BTreeMap<u32, u16>) -> Result<Vec<u8>> {
+    let mut groups: Vec<(u32, u32, u16)> = Vec::new();
+    for (&c, &id) in mapping {
+        match groups.last_mut() {
+            Some((start, end, start_id))
+                if *end + 1 == c && (*start_id as u32 + c - *start) == id as u32 =>
+            {
+                *end = c
+            }
+            _ => groups.push((c, c, id)),
+        }
+    }
+
+    let mut segments: Vec<(u16, u16, u16)> = groups
+        .iter()
+        .filter(|(start, _, _)| *start < 0xFFFF)
+        .map(|&(start, end, id)| {
+            (
+                start as u16,
+                end.min(0xFFFE) as u16,
+                id.wrapping_sub(start as u16),
+            )
+        })
+        .collect();
+    segments.push((0xFFFF, 0xFFFF, 1));
+    let seg_count = segments.len() as u16;
+    let entry_selector = 15 - seg_count.leading_zeros() as u16;
+    let search_range = 2 << entry_selector;
+    let mut format4 = Vec::new();
+    for value in [
+        4,
+        0,
+        0,
+        seg_count * 2,
+        search_range,
+        entry_selector,
+        seg_count * 2 - search_range,
+    ] {
+        format4.extend_from_slice(&value.to_be_bytes());
+    }
+    format4.extend(segments.iter().flat_map(|(_, end, _)| end.to_be_bytes()));
+    format4.extend_from_slice(&[0, 0]);
+    format4.extend(
+        segments
+            .iter()
+            .flat_map(|(start, _, _)| start.to_be_bytes()),
+    );
+    format4.extend(
+        segments
+            .iter()
+            .flat_map(|(_, _, delta)| delta.to_be_bytes()),
+    );
+    format4.extend(segments.iter().flat_map(|_| [0, 0]));
+    let Ok(length) = u16::try_from(format4.len()) else {
+        bail!("too many characters for a format 4 cmap subtable");
+    };
+    format4[2..4].copy_from_slice(&length.to_be_bytes());
+
+    let mut format12 = Vec::new();
+    format12.extend_from_slice(&12u16.to_be_bytes());
+    format12.extend_from_slice(&0u16.to_be_bytes());
+    format12.extend_from_slice(&(16 + groups.len() as u32 * 12).to_be_bytes());
+    format12.extend_from_slice(&0u32.to_be_bytes());
+    format12.extend_from_slice(&(groups.len() as u32).to_be_bytes());
+    for (start, end, id) in &groups {
+        format12.extend_from_slice(&start.to_be_bytes());
+        format12.extend_from_slice(&end.to_be_bytes());
+        format12.extend_from_slice(&(*id as u32).to_be_bytes());
+    }
+
+    let mut table = Vec::new();
+    for value in [0u16, 2, 3, 1] {
+        table.extend_from_slice(&value.to_be_bytes());
+    }
+    table.extend_from_slice(&20u32.to_be_bytes());
+    table.extend_from_slice(&3u16.to_be_bytes());
+    table.extend_from_slice(&10u16.to_be_bytes());
+    table.extend_from_slice(&(20 + format4.len() as u32).to_be_bytes());
+    table.extend_from_slice(&format4);
+    table.extend_from_slice(&format12);
+    Ok(table)
+}
+
+/// 构建家族名称为 family 的 name 表，样式名称和版权等信息来自原来的字体
+fn build_name(font: &Font, family: &str) -> Result<Vec<u8>> {
+    let subfamily = font
+        .names()
+        .ok()
+        .and_then(|names| names.subfamily)
+        .unwrap_or_else(|| "Regular".to_string());
+    let full_name = if subfamily.eq_ignore_ascii_case("Regular") {
+        family.to_string()
+    } else {
+        format!("{family} {subfamily}")
+    };
+    let postscript_name: String = format!("{family}-{subfamily}")
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+        .collect();
+
+    // (platform_id, encoding_id, language_id, name_id, 编码后的名称)
+    let mut records: Vec<(u16, u16, u16, u16, Vec<u8>)> = Vec::new();
+    let table = font.table(b"name")?.unwrap_or_default();
+    if !table.is_empty() {
+        let count = read_u16(table, 2)? as usize;
+        let storage = read_u16(table, 4)? as usize;
+        for i in 0..count {
+            let record = 6 + i * 12;
+            let name_id = read_u16(table, record + 6)?;
+            if !COPIED_NAME_IDS.contains(&name_id) {
+                continue;
+            }
+            let length = read_u16(table, record + 8)? as usize;
+            let offset = storage + read_u16(table, record + 10)? as usize;
+            let Some(bytes) = table.get(offset..offset + length) else {
+                continue;
+            };
+            records.push((
+                read_u16(table, record)?,
+                read_u16(table, record + 2)?,
+                read_u16(table, record + 4)?,
+                name_id,
+                bytes.to_vec(),
+            ));
+        }
+    }
+    for (name_id, value) in [
+        (1, family),
+        (2, subfamily.as_str()),
+        (3, full_name.as_str()),
+        (4, full_name.as_str()),
+        (6, postscript_name.as_str()),
+    ] {
+        let bytes = value.encode_utf16().flat_map(|u| u.to_be_bytes()).collect();
+        records.push((3, 1, 0x0409, name_id, bytes));
+    }
+    records.sort_by_key(|&(platform_id, encoding_id, language_id, name_id, _)| {
+        (platform_id, encoding_id, language_id, name_id)
+    });
+
+    let mut out = Vec::new();
+    let mut storage = Vec::new();
+    out.extend_from_slice(&0u16.to_be_bytes());
+    out.extend_from_slice(&(records.len() as u16).to_be_bytes());
+    out.extend_from_slice(&(6 + records.len() as u16 * 12).to_be_bytes());
+    for (platform_id, encoding_id, language_id, name_id, bytes) in &records {
+        for value in [
+            *platform_id,
+            *encoding_id,
+            *language_id,
+            *name_id,
+            bytes.len() as u16,
+            storage.len() as u16,
+        ] {
+            out.extend_from_slice(&value.to_be_bytes());
+        }
+        storage.extend_from_slice(bytes);
+    }
+    out.extend_from_slice(&storage);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cmap_round_trip() {
+        let mapping = BTreeMap::from([
+            (0x20, 1),
+            (0x41, 2),
+            (0x42, 3),
+            (0x44, 5),
+            (0xFFFF, 6),
+            (0x1F600, 7),
+            (0x1F601, 8),
+        ]);
+        let mut parsed = BTreeMap::new();
+        cmap::for_each_glyph(&build_cmap(&mapping).unwrap(), |c, id| {
+            parsed.insert(c, id as u16);
+        })
+        .unwrap();
+        assert_eq!(parsed, mapping);
+    }
+
+    #[test]
+    fn composite_components() {
+        let mut glyph = vec![0xFF, 0xFF, 0, 0, 0, 0, 0, 0, 0, 0];
+        // 第一个部件的参数是字，带有缩放，第二个部件的参数是字节，带有 2x2 变换
+        for value in [
+            ARG_1_AND_2_ARE_WORDS | WE_HAVE_A_SCALE | MORE_COMPONENTS,
+            7,
+            0,
+            0,
+            0x4000,
+        ] {
+            glyph.extend_from_slice(&value.to_be_bytes());
+        }
+        for value in [WE_HAVE_A_TWO_BY_TWO, 9, 0, 0x4000, 0, 0, 0x4000] {
+            glyph.extend_from_slice(&value.to_be_bytes());
+        }
+        assert_eq!(components(&glyph).unwrap(), vec![(12, 7), (22, 9)]);
+        assert!(
+            components(&[0, 1, 0, 0, 0, 0, 0, 0, 0, 0])
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,327 @@
+use crate::sfnt::{Font, FontNames, checksum, face_offsets, read_u16, read_u32};
+use anyhow::{Result, bail};
+
+/// 加载字体时必须存在的表
+const REQUIRED_TABLES: [&[u8; 4]; 5] = [b"head", b"name", b"cmap", b"OS/2", b"maxp"];
+
+/// head 表中的 magicNumber
+const HEAD_MAGIC: u32 = 0x5F0F3CF5;
+
+/// 检查字体文件中每个字体的结构，返回每个字体不影响加载的问题
+///
+/// 表目录、表的范围、必需的表和字形数据有问题的字体会被拒绝，只有校验和错误的字体仍然可以加载。
+/// 字体集合中的每个字体单独检查，一个字体有问题不影响其他字体。
+pub fn validate(data: &[u8]) -> Result<Vec<Result<Vec<String>>>> {
+    Ok(face_offsets(data)?
+        .into_iter()
+        .map(|offset| validate_font(&Font::parse(data, offset)?))
+        .collect())
+}
+
+/// 检查一个字体
+fn validate_font(font: &Font) -> Result<Vec<String>> {
+    let mut issues = Vec::new();
+    for record in &font.tables {
+        let table = font.table(&record.tag)?.unwrap();
+        let actual = if &record.tag == b"head" && table.len() >= 12 {
+            // 计算 head 表的校验和时 checkSumAdjustment 视为 0
+            checksum(table).wrapping_sub(read_u32(table, 8)?)
+        } else {
+            checksum(table)
+        };
+        if actual != record.checksum {
+            issues.push(format!(
+                "checksum mismatch in {} table (expected 0x{:08X}, got 0x{actual:08X})",
+                record.tag_str().trim_end(),
+                record.checksum
+            ));
+        }
+    }
+    for tag in REQUIRED_TABLES {
+        if font.table_record(tag).is_none() {
+            bail!("missing {} table", String::from_utf8_lossy(tag));
+        }
+    }
+
+    let head = font.table(b"head")?.unwrap();
+    if head.len() < 54 {
+        bail!(
+            "truncated head table, expected 54 bytes but got {}",
+            head.len()
+        );
+    }
+    if read_u32(head, 12)? != HEAD_MAGIC {
+        bail!("invalid magic number in head table");
+    }
+    let index_to_loc_format = read_u16(head, 50)?;
+    if index_to_loc_format > 1 {
+        bail!("invalid indexToLocFormat {index_to_loc_format} in head table");
+    }
+
+    let num_glyphs = read_u16(font.table(b"maxp")?.unwrap(), 4)? as usize;
+    if num_glyphs == 0 {
+        bail!("no glyphs in maxp table");
+    }
+
+    let os2 = font.table(b"OS/2")?.unwrap();
+    if os2.len() < 78 {
+        bail!(
+            "truncated OS/2 table, expected 78 bytes but got {}",
+            os2.len()
+        );
+    }
+
+    let cmap = font.table(b"cmap")?.unwrap();
+    let num_subtables = read_u16(cmap, 2)? as usize;
+    if num_subtables == 0 {
+        bail!("no subtables in cmap table");
+    }
+    for i in 0..num_subtables {
+        let offset = read_u32(cmap, 4 + i * 8 + 4)? as usize;
+        if read_u16(cmap, offset).is_err() {
+            bail!("cmap subtable #{i} at offset {offset} is out of the table");
+        }
+    }
+
+    FontNames::parse(font.table(b"name")?.unwrap())?;
+
+    // CFF/CFF2 字体的字形在 CFF 表中，TrueType 字体需要 glyf 和 loca
+    if font.table_record(b"CFF ").is_none() && font.table_record(b"CFF2").is_none() {
+        validate_glyf(font, num_glyphs, index_to_loc_format)?;
+    }
+    Ok(issues)
+}
+
+/// 检查 loca 表中每个字形的范围都在 glyf 表内
+fn validate_glyf(font: &Font, num_glyphs: usize, index_to_loc_format: u16) -> Result<()> {
+    let Some(loca) = font.table(b"loca")? else {
+        bail!("missing loca table");
+    };
+    let Some(glyf) = font.table(b"glyf")? else {
+        bail!("missing glyf table");
+    };
+    let entry = if index_to_loc_format == 0 { 2 } else { 4 };
+    if loca.len() < (num_glyphs + 1) * entry {
+        bail!(
+            "truncated loca table, expected {} entries for {num_glyphs} glyphs",
+            num_glyphs + 1
+        );
+    }
+    let read_loca = |i: usize| -> Result<usize> {
+        Ok(if index_to_loc_format == 0 {
+            read_u16(loca, i * 2)? as usize * 2
+        } else {
+            read_u32(loca, i * 4)? as usize
+        })
+    };
+    let glyf_offset = font.table_record(b"glyf").unwrap().offset;
+    let mut start = read_loca(0)?;
+    for i in 0..num_glyphs {
+        let end = read_loca(i + 1)?;
+        if end < start {
+            bail!("invalid loca entry for glyph {i}, which ends before it starts");
+        }
+        if end > glyf.len() {
+            bail!(
+                "truncated glyf table at offset {glyf_offset}, glyph {i} ends at {end} but the table has {} bytes",
+                glyf.len()
+            );
+        }
+        start = end;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sfnt::writer::{Table, build_collection, build_font};
+
+    /// 只有一个空字形的最小 TrueType 字体的表
+    fn tables() -> Vec<Table> {
+        let mut head = vec![0u8; 54];
+        head[0..4].copy_from_slice(&0x00010000u32.to_be_bytes());
+        head[12..16].copy_from_slice(&HEAD_MAGIC.to_be_bytes());
+        head[18..20].copy_from_slice(&1000u16.to_be_bytes());
+        let mut maxp = vec![0u8; 6];
+        maxp[0..4].copy_from_slice(&0x00005000u32.to_be_bytes());
+        maxp[4..6].copy_from_slice(&1u16.to_be_bytes());
+        // 一个 format 4 子表，只有结束段
+        let cmap = [
+            0, 0, 0, 1, 0, 3, 0, 1, 0, 0, 0, 12, 0, 4, 0, 24, 0, 0, 0, 2, 0, 2, 0, 0, 0, 0, 0xFF,
+            0xFF, 0, 0, 0xFF, 0xFF, 0, 1, 0, 0,
+        ];
+        let name = [0, 0, 0, 0, 0, 6];
+        vec![
+            Table {
+                tag: *b"head",
+                data: head,
+            },
+            Table {
+                tag: *b"maxp",
+                data: maxp,
+            },
+            Table {
+                tag: *b"OS/2",
+                data: vec![0; 78],
+            },
+            Table {
+                tag: *b"cmap",
+                data: cmap.to_vec(),
+            },
+            Table {
+                tag: *b"name",
+                data: name.to_vec(),
+            },
+            Table {
+                tag: *b"loca",
+                data: vec![0, 0, 0, 0],
+            },
+            Table {
+                tag: *b"glyf",
+                data: Vec::new(),
+            },
+        ]
+    }
+
+    fn font(tables: &[Table]) -> Vec<u8> {
+        build_font(0x00010000, tables)
+    }
+
+    fn error(data: &[u8]) -> String {
+        validate(data)
+            .and_then(|mut faces| faces.remove(0))
+            .unwrap_err()
+            .to_string()
+    }
+
+    fn issues(data: &[u8]) -> Vec<String> {
+        validate(data).unwrap().remove(0).unwrap()
+    }
+
+    /// 修改 tag 表中 offset 处的数据，不更新校验和
+    fn damage(data: &mut [u8], tag: &[u8; 4], offset: usize, value: &[u8]) {
+        let font = Font::parse(data, 0).unwrap();
+        let start = font.table_record(tag).unwrap().offset as usize + offset;
+        data[start..start + value.len()].copy_from_slice(value);
+    }
+
+    #[test]
+    fn valid_font() {
+        assert!(issues(&font(&tables())).is_empty());
+    }
+
+    #[test]
+    fn unknown_sfnt_version() {
+        let mut data = font(&tables());
+        data[0..4].copy_from_slice(b"abcd");
+        assert_eq!(error(&data), "unknown sfnt version 0x61626364");
+    }
+
+    #[test]
+    fn truncated_table_directory() {
+        let data = font(&tables());
+        assert_eq!(error(&data[..40]), "truncated table directory at offset 28");
+    }
+
+    #[test]
+    fn truncated_table() {
+        let mut tables = tables();
+        tables[5].data = vec![0, 0, 0, 4];
+        tables[6].data = vec![0; 8];
+        let data = font(&tables);
+        let glyf = Font::parse(&data, 0)
+            .unwrap()
+            .table_record(b"glyf")
+            .unwrap()
+            .offset;
+        assert_eq!(
+            error(&data[..glyf as usize + 2]),
+            format!("truncated glyf table at offset {glyf}")
+        );
+    }
+
+    #[test]
+    fn missing_required_table() {
+        for tag in REQUIRED_TABLES {
+            let tables: Vec<Table> = tables().into_iter().filter(|t| &t.tag != tag).collect();
+            assert_eq!(
+                error(&font(&tables)),
+                format!("missing {} table", String::from_utf8_lossy(tag))
+            );
+        }
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let mut data = font(&tables());
+        damage(&mut data, b"OS/2", 0, &[0, 1]);
+        let issues = issues(&data);
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].starts_with("checksum mismatch in OS/2 table"));
+    }
+
+    #[test]
+    fn invalid_head() {
+        let mut data = font(&tables());
+        damage(&mut data, b"head", 12, &[0; 4]);
+        assert_eq!(error(&data), "invalid magic number in head table");
+        let mut data = font(&tables());
+        damage(&mut data, b"head", 50, &[0, 2]);
+        assert_eq!(error(&data), "invalid indexToLocFormat 2 in head table");
+    }
+
+    #[test]
+    fn glyph_out_of_glyf() {
+        let mut tables = tables();
+        tables[5].data = vec![0, 0, 0, 4];
+        tables[6].data = vec![0; 8];
+        let mut data = font(&tables);
+        assert!(issues(&data).is_empty());
+        damage(&mut data, b"loca", 2, &[0, 6]);
+        let glyf = Font::parse(&data, 0)
+            .unwrap()
+            .table_record(b"glyf")
+            .unwrap()
+            .offset;
+        assert_eq!(
+            error(&data),
+            format!(
+                "truncated glyf table at offset {glyf}, glyph 0 ends at 12 but the table has 8 bytes"
+            )
+        );
+    }
+
+    #[test]
+    fn cff_font_without_glyf() {
+        let mut tables: Vec<Table> = tables()
+            .into_iter()
+            .filter(|t| &t.tag != b"glyf" && &t.tag != b"loca")
+            .collect();
+        assert_eq!(error(&font(&tables)), "missing loca table");
+        tables.push(Table {
+            tag: *b"CFF ",
+            data: vec![1, 0, 4, 1],
+        });
+        assert!(issues(&build_font(0x4F54544F, &tables)).is_empty());
+    }
+
+    #[test]
+    fn collection_with_broken_face() {
+        let mut tables = tables();
+        tables.push(Table {
+            tag: *b"loca",
+            data: vec![0, 0, 0, 100],
+        });
+        let fonts = [
+            (0x00010000, (0..7).collect()),
+            (0x00010000, vec![0, 1, 2, 3, 4, 6, 7]),
+        ];
+        let data = build_collection(&fonts, &tables);
+        let faces = validate(&data).unwrap();
+        assert_eq!(faces.len(), 2);
+        assert!(faces[0].as_ref().unwrap().is_empty());
+        let err = faces[1].as_ref().unwrap_err().to_string();
+        assert!(err.starts_with("truncated glyf table at offset"));
+    }
+}
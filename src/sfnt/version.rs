@@ -0,0 +1,64 @@
+use std::{cmp::Ordering, fmt};
+
+/// 字体的版本，由 name 表中的版本字符串和 head 表中的 fontRevision 组成
+#[derive(Debug, Clone, Default)]
+pub struct FontVersion {
+    /// name ID 5 的版本字符串，例如 `Version 2.003;PS 2.003;hotconv 1.0.88`
+    pub text: Option<String>,
+    /// head 表中 16.16 定点数的 fontRevision
+    pub revision: Option<u32>,
+}
+
+impl FontVersion {
+    /// 版本字符串中的第一个数字，小数部分去掉末尾的 0 后按字符串比较，例如 2.003 < 2.01 < 2.1
+    fn number(&self) -> Option<(u64, &str)> {
+        let text = self.text.as_deref()?;
+        let start = text.find(|c: char| c.is_ascii_digit())?;
+        let text = &text[start..];
+        let end = text
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(text.len());
+        let major = text[..end].parse().ok()?;
+        let fraction = text[end..]
+            .strip_prefix('.')
+            .map(|f| {
+                let end = f.find(|c: char| !c.is_ascii_digit()).unwrap_or(f.len());
+                f[..end].trim_end_matches('0')
+            })
+            .unwrap_or("");
+        Some((major, fraction))
+    }
+}
+
+/// 优先比较版本字符串中的版本号，无法比较或相同时再比较 fontRevision
+impl Ord for FontVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.number()
+            .cmp(&other.number())
+            .then(self.revision.cmp(&other.revision))
+    }
+}
+
+impl PartialOrd for FontVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for FontVersion {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for FontVersion {}
+
+impl fmt::Display for FontVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.text, self.revision) {
+            (Some(text), _) => write!(f, "{text}"),
+            (None, Some(revision)) => write!(f, "revision {:.3}", revision as f64 / 65536.0),
+            (None, None) => write!(f, "unknown version"),
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use crate::sfnt::{
+    read_u16, read_u32,
+    writer::{Table, build_font},
+};
+use anyhow::{Result, bail};
+use flate2::read::ZlibDecoder;
+use std::io::Read;
+
+/// 把 WOFF 字体解码为 TTF/OTF，返回 sfnt 版本和字体数据
+pub fn decode(data: &[u8]) -> Result<(u32, Vec<u8>)> {
+    if data.get(0..4) != Some(b"wOFF") {
+        bail!("invalid WOFF signature");
+    }
+    let flavor = read_u32(data, 4)?;
+    let num_tables = read_u16(data, 12)? as usize;
+    let mut tables = Vec::with_capacity(num_tables);
+    for i in 0..num_tables {
+        let entry = 44 + i * 20;
+        let tag: [u8; 4] = match data.get(entry..entry + 4) {
+            Some(tag) => tag.try_into().unwrap(),
+            None => bail!("truncated WOFF table directory at offset {entry}"),
+        };
+        let offset = read_u32(data, entry + 4)? as usize;
+        let comp_length = read_u32(data, entry + 8)? as usize;
+        let orig_length = read_u32(data, entry + 12)? as usize;
+        let Some(compressed) = data.get(offset..offset + comp_length) else {
+            bail!(
+                "truncated {} table at offset {offset}",
+                String::from_utf8_lossy(&tag).trim_end()
+            );
+        };
+        let table = if comp_length < orig_length {
+            // 最多多解压一个字节，解压后比声明的长度更长的表不会一直解压下去
+            let mut table = Vec::new();
+            ZlibDecoder::new(compressed)
+                .take(orig_length as u64 + 1)
+                .read_to_end(&mut table)?;
+            table
+        } else {
+            compressed.to_vec()
+        };
+        if table.len() != orig_length {
+            bail!(
+                "{} table decompressed to {} bytes instead of {orig_length}",
+                String::from_utf8_lossy(&tag).trim_end(),
+                table.len()
+            );
+        }
+        tables.push(Table { tag, data: table });
+    }
+    Ok((flavor, build_font(flavor, &tables)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sfnt::{Font, checksum, test_font, validate};
+    use flate2::{Compression, write::ZlibEncoder};
+    use std::io::Write;
+
+    /// 把表压缩为 WOFF，压缩后没有变小的表保持原样
+    fn encode(flavor: u32, tables: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let mut directory = Vec::new();
+        let mut body = Vec::new();
+        let data_start = 44 + 20 * tables.len();
+        for (tag, data) in tables {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+            encoder.write_all(data).unwrap();
+            let compressed = encoder.finish().unwrap();
+            let stored = if compressed.len() < data.len() {
+                compressed
+            } else {
+                data.clone()
+            };
+            directory.extend_from_slice(*tag);
+            for value in [
+                (data_start + body.len()) as u32,
+                stored.len() as u32,
+                data.len() as u32,
+                checksum(data),
+            ] {
+                directory.extend_from_slice(&value.to_be_bytes());
+            }
+            body.extend_from_slice(&stored);
+            body.resize(body.len().next_multiple_of(4), 0);
+        }
+        let mut woff = b"wOFF".to_vec();
+        woff.extend_from_slice(&flavor.to_be_bytes());
+        woff.extend_from_slice(&((data_start + body.len()) as u32).to_be_bytes());
+        woff.extend_from_slice(&(tables.len() as u16).to_be_bytes());
+        woff.extend_from_slice(&[0; 30]);
+        woff.extend_from_slice(&directory);
+        woff.extend_from_slice(&body);
+        woff
+    }
+
+    /// 字体中的所有表，head.checkSumAdjustment 和表的顺序有关，所以清零
+    fn tables(data: &[u8]) -> Vec<([u8; 4], Vec<u8>)> {
+        let font = Font::parse(data, 0).unwrap();
+        font.tables
+            .iter()
+            .map(|record| {
+                let mut table = font.table(&record.tag).unwrap().unwrap().to_vec();
+                if &record.tag == b"head" {
+                    table[8..12].fill(0);
+                }
+                (record.tag, table)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let font = test_font("Test Sans", "Regular", 400, "abc");
+        let original = tables(&font);
+        let woff = encode(
+            0x00010000,
+            &original
+                .iter()
+                .map(|(tag, data)| (tag, data.clone()))
+                .collect::<Vec<_>>(),
+        );
+        let (flavor, decoded) = decode(&woff).unwrap();
+        assert_eq!(flavor, 0x00010000);
+        assert_eq!(tables(&decoded), original);
+        assert!(validate(&decoded).unwrap().iter().all(|face| face.is_ok()));
+
+        let err = decode(&woff[..woff.len() - 8]).unwrap_err();
+        assert!(err.to_string().starts_with("truncated"), "{err}");
+        let err = decode(&woff[..50]).unwrap_err();
+        assert!(
+            err.to_string().starts_with("unexpected end of data"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn oversized_table() {
+        // 1 MiB 的 0 压缩后只有 1 KiB 左右，声明的长度比解压后的短时只会多解压一个字节
+        let woff = encode(0x00010000, &[(b"glyf", vec![0; 1 << 20])]);
+        let comp_length = read_u32(&woff, 44 + 8).unwrap();
+        let mut bomb = woff.clone();
+        bomb[44 + 12..44 + 16].copy_from_slice(&(comp_length + 1).to_be_bytes());
+        let err = decode(&bomb).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "glyf table decompressed to {} bytes instead of {}",
+                comp_length + 2,
+                comp_length + 1
+            )
+        );
+        assert!(decode(&woff).is_ok());
+    }
+}
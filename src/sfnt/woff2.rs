@@ -0,0 +1,706 @@
+use crate::sfnt::{
+    read_u16, read_u32,
+    writer::{Table, build_collection, build_font},
+};
+use anyhow::{Result, bail};
+use brotli_decompressor::Decompressor;
+use std::io::Read;
+
+/// WOFF2 表目录中用序号表示的表
+const KNOWN_TAGS: [&[u8; 4]; 63] = [
+    b"cmap", b"head", b"hhea", b"hmtx", b"maxp", b"name", b"OS/2", b"post", b"cvt ", b"fpgm",
+    b"glyf", b"loca", b"prep", b"CFF ", b"VORG", b"EBDT", b"EBLC", b"gasp", b"hdmx", b"kern",
+    b"LTSH", b"PCLT", b"VDMX", b"vhea", b"vmtx", b"BASE", b"GDEF", b"GPOS", b"GSUB", b"EBSC",
+    b"JSTF", b"MATH", b"CBDT", b"CBLC", b"COLR", b"CPAL", b"SVG ", b"sbix", b"acnt", b"avar",
+    b"bdat", b"bloc", b"bsln", b"cvar", b"fdsc", b"feat", b"fmtx", b"fvar", b"gvar", b"hsty",
+    b"just", b"lcar", b"mort", b"morx", b"opbd", b"prop", b"trak", b"Zapf", b"Silf", b"Glat",
+    b"Gloc", b"Feat", b"Sill",
+];
+
+/// 复合字形的标志位
+const ARG_1_AND_2_ARE_WORDS: u16 = 0x0001;
+const WE_HAVE_A_SCALE: u16 = 0x0008;
+const MORE_COMPONENTS: u16 = 0x0020;
+const WE_HAVE_AN_X_AND_Y_SCALE: u16 = 0x0040;
+const WE_HAVE_A_TWO_BY_TWO: u16 = 0x0080;
+const WE_HAVE_INSTRUCTIONS: u16 = 0x0100;
+
+/// 顺序读取数据的游标
+struct Cursor<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0 }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        match self.data.get(self.offset..self.offset + len) {
+            Some(bytes) => {
+                self.offset += len;
+                Ok(bytes)
+            }
+            None => bail!("unexpected end of WOFF2 data at offset {}", self.offset),
+        }
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn i16(&mut self) -> Result<i16> {
+        Ok(self.u16()? as i16)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// UIntBase128 编码的整数
+    fn base128(&mut self) -> Result<u32> {
+        let mut value: u32 = 0;
+        for i in 0..5 {
+            let b = self.u8()?;
+            if i == 0 && b == 0x80 {
+                bail!("invalid UIntBase128 with leading zeros");
+            }
+            if value & 0xFE000000 != 0 {
+                bail!("UIntBase128 overflow");
+            }
+            value = (value << 7) | (b & 0x7F) as u32;
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        bail!("UIntBase128 longer than 5 bytes")
+    }
+
+    /// 255UInt16 编码的整数
+    fn u255(&mut self) -> Result<u16> {
+        Ok(match self.u8()? {
+            253 => self.u16()?,
+            254 => self.u8()? as u16 + 253 * 2,
+            255 => self.u8()? as u16 + 253,
+            code => code as u16,
+        })
+    }
+}
+
+/// 表目录中的一项
+struct Entry {
+    tag: [u8; 4],
+    orig_length: usize,
+    transform_length: usize,
+    transformed: bool,
+}
+
+/// 把 WOFF2 字体解码为 TTF/OTF/TTC，返回 sfnt 版本和字体数据
+pub fn decode(data: &[u8]) -> Result<(u32, Vec<u8>)> {
+    if data.get(0..4) != Some(b"wOF2") {
+        bail!("invalid WOFF2 signature");
+    }
+    let flavor = read_u32(data, 4)?;
+    let num_tables = read_u16(data, 12)? as usize;
+    let total_compressed_size = read_u32(data, 20)? as usize;
+
+    let mut cursor = Cursor::new(data);
+    cursor.offset = 48;
+    let mut entries = Vec::with_capacity(num_tables);
+    for _ in 0..num_tables {
+        let flags = cursor.u8()?;
+        let tag = match flags & 0x3F {
+            63 => cursor.bytes(4)?.try_into().unwrap(),
+            index => *KNOWN_TAGS[index as usize],
+        };
+        let version = flags >> 6;
+        // glyf 和 loca 的版本 0 表示经过了转换，其他表的版本 0 表示没有转换
+        let transformed = if &tag == b"glyf" || &tag == b"loca" {
+            version == 0
+        } else {
+            version != 0
+        };
+        let orig_length = cursor.base128()? as usize;
+        let transform_length = if transformed {
+            cursor.base128()? as usize
+        } else {
+            orig_length
+        };
+        entries.push(Entry {
+            tag,
+            orig_length,
+            transform_length,
+            transformed,
+        });
+    }
+
+    // 字体集合的每个字体使用的表的序号
+    let mut fonts = Vec::new();
+    if flavor == u32::from_be_bytes(*b"ttcf") {
+        cursor.u32()?;
+        let num_fonts = cursor.u255()?;
+        for _ in 0..num_fonts {
+            let num_tables = cursor.u255()?;
+            let flavor = cursor.u32()?;
+            let indices = (0..num_tables)
+                .map(|_| cursor.u255().map(|i| i as usize))
+                .collect::<Result<Vec<usize>>>()?;
+            if indices.iter().any(|&i| i >= entries.len()) {
+                bail!("invalid table index in WOFF2 collection directory");
+            }
+            fonts.push((flavor, indices));
+        }
+    } else {
+        fonts.push((flavor, (0..entries.len()).collect()));
+    }
+
+    let Some(compressed) = data.get(cursor.offset..cursor.offset + total_compressed_size) else {
+        bail!(
+            "truncated WOFF2 compressed stream at offset {}",
+            cursor.offset
+        );
+    };
+    // 解压后的数据是所有表转换后的数据，最多多解压一个字节，比声明的长度更长时不会一直解压下去
+    let stream_length: u64 = entries
+        .iter()
+        .map(|entry| entry.transform_length as u64)
+        .sum();
+    let mut stream = Vec::new();
+    Decompressor::new(compressed, 4096)
+        .take(stream_length + 1)
+        .read_to_end(&mut stream)?;
+    if stream.len() as u64 != stream_length {
+        bail!(
+            "WOFF2 stream decompressed to {} bytes instead of {stream_length}",
+            stream.len()
+        );
+    }
+
+    let mut raw = Vec::with_capacity(entries.len());
+    let mut offset = 0;
+    for entry in &entries {
+        let Some(table) = stream.get(offset..offset + entry.transform_length) else {
+            bail!(
+                "truncated {} table in WOFF2 stream at offset {offset}",
+                String::from_utf8_lossy(&entry.tag).trim_end()
+            );
+        };
+        raw.push(table);
+        offset += entry.transform_length;
+    }
+
+    let mut tables: Vec<Table> = entries
+        .iter()
+        .zip(&raw)
+        .map(|(entry, table)| Table {
+            tag: entry.tag,
+            data: if entry.transformed {
+                Vec::new()
+            } else {
+                table.to_vec()
+            },
+        })
+        .collect();
+
+    // 每个字体分别还原经过转换的 glyf、loca 和 hmtx
+    for (_, indices) in &fonts {
+        let find = |tag: &[u8; 4]| indices.iter().copied().find(|&i| &entries[i].tag == tag);
+        let mut x_mins = None;
+        if let (Some(glyf), Some(loca)) = (find(b"glyf"), find(b"loca"))
+            && entries[glyf].transformed
+            && tables[glyf].data.is_empty()
+        {
+            let (glyf_data, loca_data, mins) = reconstruct_glyf(raw[glyf])?;
+            if loca_data.len() != entries[loca].orig_length {
+                bail!("reconstructed loca table does not match its original length");
+            }
+            tables[glyf].data = glyf_data;
+            tables[loca].data = loca_data;
+            x_mins = Some(mins);
+        }
+        if let Some(hmtx) = find(b"hmtx")
+            && entries[hmtx].transformed
+            && tables[hmtx].data.is_empty()
+        {
+            let Some(x_mins) = &x_mins else {
+                bail!("transformed hmtx table requires a transformed glyf table");
+            };
+            let Some(hhea) = find(b"hhea") else {
+                bail!("missing hhea table");
+            };
+            let num_h_metrics = read_u16(&tables[hhea].data, 34)? as usize;
+            tables[hmtx].data = reconstruct_hmtx(raw[hmtx], num_h_metrics, x_mins)?;
+        }
+    }
+
+    if fonts.len() == 1 {
+        Ok((flavor, build_font(flavor, &tables)))
+    } else {
+        Ok((flavor, build_collection(&fonts, &tables)))
+    }
+}
+
+/// 还原经过转换的 glyf 表，返回 glyf 表、loca 表和每个字形的 xMin
+fn reconstruct_glyf(data: &[u8]) -> Result<(Vec<u8>, Vec<u8>, Vec<i16>)> {
+    let mut header = Cursor::new(data);
+    header.u16()?;
+    let option_flags = header.u16()?;
+    let num_glyphs = header.u16()? as usize;
+    let index_format = header.u16()?;
+    let mut streams = Vec::with_capacity(7);
+    let mut offset = 36;
+    for _ in 0..7 {
+        let size = header.u32()? as usize;
+        let Some(stream) = data.get(offset..offset + size) else {
+            bail!("truncated glyf transform stream at offset {offset}");
+        };
+        streams.push(stream);
+        offset += size;
+    }
+    let overlap_bitmap = if option_flags & 1 != 0 {
+        data.get(offset..offset + num_glyphs.div_ceil(8))
+    } else {
+        None
+    };
+    let mut n_contours = Cursor::new(streams[0]);
+    let mut n_points = Cursor::new(streams[1]);
+    let mut flags = Cursor::new(streams[2]);
+    let mut glyphs = Cursor::new(streams[3]);
+    let mut composites = Cursor::new(streams[4]);
+    let bbox_bitmap_len = num_glyphs.div_ceil(32) * 4;
+    let bbox_bitmap = match streams[5].get(..bbox_bitmap_len) {
+        Some(bitmap) => bitmap,
+        None => bail!("truncated glyf bbox bitmap"),
+    };
+    let mut bboxes = Cursor::new(&streams[5][bbox_bitmap_len..]);
+    let mut instructions = Cursor::new(streams[6]);
+
+    let mut glyf = Vec::new();
+    let mut loca = Vec::with_capacity(num_glyphs + 1);
+    let mut x_mins = Vec::with_capacity(num_glyphs);
+    for i in 0..num_glyphs {
+        loca.push(glyf.len());
+        let has_bbox = bbox_bitmap[i >> 3] & (0x80 >> (i & 7)) != 0;
+        let contours = n_contours.i16()?;
+        if contours == 0 {
+            if has_bbox {
+                bail!("empty glyph {i} has an explicit bbox");
+            }
+            x_mins.push(0);
+            continue;
+        }
+
+        let mut glyph = Vec::new();
+        if contours == -1 {
+            if !has_bbox {
+                bail!("composite glyph {i} has no explicit bbox");
+            }
+            let bbox = bboxes.bytes(8)?;
+            x_mins.push(i16::from_be_bytes([bbox[0], bbox[1]]));
+            glyph.extend_from_slice(&contours.to_be_bytes());
+            glyph.extend_from_slice(bbox);
+            let mut have_instructions = false;
+            loop {
+                let flags = composites.u16()?;
+                let mut len = 2 + if flags & ARG_1_AND_2_ARE_WORDS != 0 {
+                    4
+                } else {
+                    2
+                };
+                if flags & WE_HAVE_A_SCALE != 0 {
+                    len += 2;
+                } else if flags & WE_HAVE_AN_X_AND_Y_SCALE != 0 {
+                    len += 4;
+                } else if flags & WE_HAVE_A_TWO_BY_TWO != 0 {
+                    len += 8;
+                }
+                glyph.extend_from_slice(&flags.to_be_bytes());
+                glyph.extend_from_slice(composites.bytes(len)?);
+                have_instructions |= flags & WE_HAVE_INSTRUCTIONS != 0;
+                if flags & MORE_COMPONENTS == 0 {
+                    break;
+                }
+            }
+            if have_instructions {
+                let len = glyphs.u255()?;
+                glyph.extend_from_slice(&len.to_be_bytes());
+                glyph.extend_from_slice(instructions.bytes(len as usize)?);
+            }
+        } else if contours > 0 {
+            let mut end_points = Vec::with_capacity(contours as usize);
+            let mut total = 0usize;
+            for _ in 0..contours {
+                total += n_points.u255()? as usize;
+                let end = u16::try_from(total)
+                    .ok()
+                    .and_then(|total| total.checked_sub(1));
+                let Some(end) = end else {
+                    bail!("invalid number of points {total} for glyph {i}");
+                };
+                end_points.push(end);
+            }
+            let mut points = Vec::with_capacity(total);
+            let (mut x, mut y) = (0i32, 0i32);
+            for _ in 0..total {
+                let flag = flags.u8()?;
+                let on_curve = flag & 0x80 == 0;
+                let (dx, dy) = decode_triplet(flag & 0x7F, &mut glyphs)?;
+                x += dx;
+                y += dy;
+                points.push((x, y, on_curve));
+            }
+            let instruction_len = glyphs.u255()?;
+            let instruction_bytes = instructions.bytes(instruction_len as usize)?;
+
+            let bbox = if has_bbox {
+                let b = bboxes.bytes(8)?;
+                [0, 2, 4, 6].map(|o| i16::from_be_bytes([b[o], b[o + 1]]))
+            } else {
+                let x_min = points.iter().map(|p| p.0).min().unwrap_or(0);
+                let y_min = points.iter().map(|p| p.1).min().unwrap_or(0);
+                let x_max = points.iter().map(|p| p.0).max().unwrap_or(0);
+                let y_max = points.iter().map(|p| p.1).max().unwrap_or(0);
+                [x_min, y_min, x_max, y_max].map(|v| v as i16)
+            };
+            x_mins.push(bbox[0]);
+            glyph.extend_from_slice(&contours.to_be_bytes());
+            for v in bbox {
+                glyph.extend_from_slice(&v.to_be_bytes());
+            }
+            for end in end_points {
+                glyph.extend_from_slice(&end.to_be_bytes());
+            }
+            glyph.extend_from_slice(&instruction_len.to_be_bytes());
+            glyph.extend_from_slice(instruction_bytes);
+            // 和常见的字体一样使用短坐标和重复标志写入点，否则 glyf 表可能超出短格式 loca 的范围
+            let overlap = overlap_bitmap.is_some_and(|b| b[i >> 3] & (0x80 >> (i & 7)) != 0);
+            let (mut point_flags, mut xs, mut ys) = (Vec::new(), Vec::new(), Vec::new());
+            let (mut last_flag, mut repeat) = (None, 0u8);
+            let (mut last_x, mut last_y) = (0, 0);
+            for (j, &(x, y, on_curve)) in points.iter().enumerate() {
+                let mut flag = if on_curve { 0x01 } else { 0x00 };
+                if j == 0 && overlap {
+                    flag |= 0x40;
+                }
+                flag |= encode_coordinate(x - last_x, 0x02, 0x10, &mut xs);
+                flag |= encode_coordinate(y - last_y, 0x04, 0x20, &mut ys);
+                (last_x, last_y) = (x, y);
+                if last_flag == Some(flag) && repeat != 255 {
+                    if repeat == 0 {
+                        *point_flags.last_mut().unwrap() |= 0x08;
+                        point_flags.push(1);
+                    } else {
+                        *point_flags.last_mut().unwrap() += 1;
+                    }
+                    repeat += 1;
+                } else {
+                    point_flags.push(flag);
+                    repeat = 0;
+                }
+                last_flag = Some(flag);
+            }
+            glyph.extend_from_slice(&point_flags);
+            glyph.extend_from_slice(&xs);
+            glyph.extend_from_slice(&ys);
+        } else {
+            bail!("invalid number of contours {contours} for glyph {i}");
+        }
+        glyf.extend_from_slice(&glyph);
+        glyf.resize(glyf.len().next_multiple_of(4), 0);
+    }
+    loca.push(glyf.len());
+    if index_format == 0 && glyf.len() > 0x1FFFE {
+        bail!("reconstructed glyf table is too large for short loca format");
+    }
+
+    let loca = if index_format == 0 {
+        loca.iter()
+            .flat_map(|&o| ((o / 2) as u16).to_be_bytes())
+            .collect()
+    } else {
+        loca.iter()
+            .flat_map(|&o| (o as u32).to_be_bytes())
+            .collect()
+    };
+    Ok((glyf, loca, x_mins))
+}
+
+/// 把简单字形中一个点的坐标增量写入 out，返回需要设置的标志位
+fn encode_coordinate(delta: i32, short_flag: u8, same_flag: u8, out: &mut Vec<u8>) -> u8 {
+    if delta == 0 {
+        same_flag
+    } else if (-255..=255).contains(&delta) {
+        out.push(delta.unsigned_abs() as u8);
+        short_flag | if delta > 0 { same_flag } else { 0 }
+    } else {
+        out.extend_from_slice(&(delta as i16).to_be_bytes());
+        0
+    }
+}
+
+/// 解码简单字形中一个点的坐标增量
+fn decode_triplet(flag: u8, glyphs: &mut Cursor) -> Result<(i32, i32)> {
+    let with_sign = |flag: u8, base: i32| if flag & 1 != 0 { base } else { -base };
+    let flag_i = flag as i32;
+    Ok(match flag {
+        0..10 => {
+            let b0 = glyphs.u8()? as i32;
+            (0, with_sign(flag, ((flag_i & 14) << 7) + b0))
+        }
+        10..20 => {
+            let b0 = glyphs.u8()? as i32;
+            (with_sign(flag, (((flag_i - 10) & 14) << 7) + b0), 0)
+        }
+        20..84 => {
+            let b0 = flag_i - 20;
+            let b1 = glyphs.u8()? as i32;
+            (
+                with_sign(flag, 1 + (b0 & 0x30) + (b1 >> 4)),
+                with_sign(flag >> 1, 1 + ((b0 & 0x0C) << 2) + (b1 & 0x0F)),
+            )
+        }
+        84..120 => {
+            let b0 = flag_i - 84;
+            let b = glyphs.bytes(2)?;
+            (
+                with_sign(flag, 1 + ((b0 / 12) << 8) + b[0] as i32),
+                with_sign(flag >> 1, 1 + (((b0 % 12) >> 2) << 8) + b[1] as i32),
+            )
+        }
+        120..124 => {
+            let b = glyphs.bytes(3)?;
+            (
+                with_sign(flag, ((b[0] as i32) << 4) + ((b[1] as i32) >> 4)),
+                with_sign(flag >> 1, (((b[1] as i32) & 0x0F) << 8) + b[2] as i32),
+            )
+        }
+        _ => {
+            let b = glyphs.bytes(4)?;
+            (
+                with_sign(flag, ((b[0] as i32) << 8) + b[1] as i32),
+                with_sign(flag >> 1, ((b[2] as i32) << 8) + b[3] as i32),
+            )
+        }
+    })
+}
+
+/// 还原经过转换的 hmtx 表，省略的 lsb 使用字形的 xMin
+fn reconstruct_hmtx(data: &[u8], num_h_metrics: usize, x_mins: &[i16]) -> Result<Vec<u8>> {
+    let mut cursor = Cursor::new(data);
+    let flags = cursor.u8()?;
+    let num_glyphs = x_mins.len();
+    if num_h_metrics == 0 || num_h_metrics > num_glyphs {
+        bail!("invalid numberOfHMetrics {num_h_metrics}");
+    }
+    let advances = (0..num_h_metrics)
+        .map(|_| cursor.u16())
+        .collect::<Result<Vec<u16>>>()?;
+    let mut lsbs = Vec::with_capacity(num_glyphs);
+    for (i, &x_min) in x_mins.iter().enumerate() {
+        let explicit = if i < num_h_metrics {
+            flags & 1 == 0
+        } else {
+            flags & 2 == 0
+        };
+        lsbs.push(if explicit { cursor.i16()? } else { x_min });
+    }
+    let mut hmtx = Vec::with_capacity(num_h_metrics * 4 + (num_glyphs - num_h_metrics) * 2);
+    for (i, lsb) in lsbs.iter().enumerate() {
+        if i < num_h_metrics {
+            hmtx.extend_from_slice(&advances[i].to_be_bytes());
+        }
+        hmtx.extend_from_slice(&lsb.to_be_bytes());
+    }
+    Ok(hmtx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sfnt::{Font, test_font, validate};
+
+    /// 从最低位开始写入 value 的 bits 位，bit 是已经写入的位数
+    fn push_bits(out: &mut Vec<u8>, bit: &mut usize, value: usize, bits: usize) {
+        for i in 0..bits {
+            if bit.is_multiple_of(8) {
+                out.push(0);
+            }
+            *out.last_mut().unwrap() |= ((value >> i & 1) as u8) << (*bit % 8);
+            *bit += 1;
+        }
+    }
+
+    /// 用不压缩的元数据块写入 Brotli 数据流
+    fn brotli_stored(data: &[u8]) -> Vec<u8> {
+        let (mut out, mut bit) = (Vec::new(), 0);
+        // WBITS 为 16
+        push_bits(&mut out, &mut bit, 0, 1);
+        for chunk in data.chunks(0x10000) {
+            // ISLAST、MNIBBLES、MLEN - 1 和 ISUNCOMPRESSED，之后按字节对齐
+            push_bits(&mut out, &mut bit, 0, 1);
+            push_bits(&mut out, &mut bit, 0, 2);
+            push_bits(&mut out, &mut bit, chunk.len() - 1, 16);
+            push_bits(&mut out, &mut bit, 1, 1);
+            out.extend_from_slice(chunk);
+            bit = out.len() * 8;
+        }
+        // ISLAST 和 ISLASTEMPTY
+        push_bits(&mut out, &mut bit, 1, 1);
+        push_bits(&mut out, &mut bit, 1, 1);
+        out
+    }
+
+    fn base128(mut value: u32, out: &mut Vec<u8>) {
+        let mut bytes = vec![(value & 0x7F) as u8];
+        value >>= 7;
+        while value != 0 {
+            bytes.push((value & 0x7F) as u8 | 0x80);
+            value >>= 7;
+        }
+        out.extend(bytes.iter().rev());
+    }
+
+    /// 用表目录中的每个表的标签、原始长度和转换后的长度以及所有表的数据构建 WOFF2
+    fn encode(entries: &[([u8; 4], usize, Option<usize>)], stream: &[u8]) -> Vec<u8> {
+        let mut directory = Vec::new();
+        for (tag, orig_length, transform_length) in entries {
+            let version = match (tag, transform_length.is_some()) {
+                (b"glyf" | b"loca", true) => 0,
+                (b"glyf" | b"loca", false) => 3,
+                (_, true) => 1,
+                (_, false) => 0,
+            };
+            match KNOWN_TAGS.iter().position(|known| *known == tag) {
+                Some(index) => directory.push(index as u8 | version << 6),
+                None => {
+                    directory.push(63 | version << 6);
+                    directory.extend_from_slice(tag);
+                }
+            }
+            base128(*orig_length as u32, &mut directory);
+            if let Some(transform_length) = transform_length {
+                base128(*transform_length as u32, &mut directory);
+            }
+        }
+        let compressed = brotli_stored(stream);
+        let mut woff2 = b"wOF2".to_vec();
+        woff2.extend_from_slice(&0x00010000u32.to_be_bytes());
+        woff2.extend_from_slice(&((48 + directory.len() + compressed.len()) as u32).to_be_bytes());
+        woff2.extend_from_slice(&(entries.len() as u16).to_be_bytes());
+        woff2.extend_from_slice(&[0; 6]);
+        woff2.extend_from_slice(&(compressed.len() as u32).to_be_bytes());
+        woff2.extend_from_slice(&[0; 24]);
+        woff2.extend_from_slice(&directory);
+        woff2.extend_from_slice(&compressed);
+        woff2
+    }
+
+    /// 转换后的 glyf 表：空字形、三个点的三角形和引用三角形的复合字形，n_points 是三角形的点数
+    fn transformed_glyf(n_points: u8) -> Vec<u8> {
+        let streams: [Vec<u8>; 7] = [
+            vec![0, 0, 0, 1, 0xFF, 0xFF],
+            vec![n_points],
+            // 每个点都使用 4 字节的坐标增量，最低两位是 x 和 y 的符号
+            vec![127, 127, 126],
+            vec![0, 100, 0, 0, 1, 0x90, 0, 0, 0, 200, 2, 0xBC, 0],
+            vec![0, 3, 0, 1, 0, 50, 0, 0],
+            vec![0x20, 0, 0, 0, 0, 100, 0, 0, 1, 0xF4, 2, 0xBC],
+            vec![],
+        ];
+        let mut glyf = Vec::new();
+        for value in [0u16, 0, 3, 1] {
+            glyf.extend_from_slice(&value.to_be_bytes());
+        }
+        for stream in &streams {
+            glyf.extend_from_slice(&(stream.len() as u32).to_be_bytes());
+        }
+        glyf.extend(streams.concat());
+        glyf
+    }
+
+    /// 把 test_font 的 glyf、loca 和 hmtx 替换为转换后的表构建 WOFF2，extra 会加在数据流的最后
+    fn encode_font(glyf: &[u8], extra: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let font = test_font("Test Sans", "Regular", 400, "ab");
+        let parsed = Font::parse(&font, 0).unwrap();
+        let mut entries = Vec::new();
+        let mut stream = Vec::new();
+        for record in &parsed.tables {
+            if matches!(&record.tag, b"glyf" | b"loca" | b"hmtx") {
+                continue;
+            }
+            let data = parsed.table(&record.tag).unwrap().unwrap();
+            entries.push((record.tag, data.len(), None));
+            stream.extend_from_slice(data);
+        }
+        // hmtx 的 lsb 都使用字形的 xMin
+        let hmtx = [3, 0x01, 0xF4, 0x02, 0x58, 0x02, 0xBC];
+        entries.push((*b"glyf", 44, Some(glyf.len())));
+        entries.push((*b"loca", 16, Some(0)));
+        entries.push((*b"hmtx", 12, Some(hmtx.len())));
+        stream.extend_from_slice(glyf);
+        stream.extend_from_slice(&hmtx);
+        stream.extend_from_slice(extra);
+        (encode(&entries, &stream), font)
+    }
+
+    #[test]
+    fn transformed_tables() {
+        let (woff2, font) = encode_font(&transformed_glyf(3), &[]);
+        let (flavor, decoded) = decode(&woff2).unwrap();
+        assert_eq!(flavor, 0x00010000);
+        assert!(validate(&decoded).unwrap().iter().all(|face| face.is_ok()));
+        let decoded = Font::parse(&decoded, 0).unwrap();
+        let table = |tag: &[u8; 4]| decoded.table(tag).unwrap().unwrap();
+
+        let triangle = [
+            0, 1, 0, 100, 0, 0, 1, 0xF4, 2, 0xBC, 0, 2, 0, 0, 0x33, 0x21, 0x03, 100, 0x01, 0x90,
+            200, 0x02, 0xBC, 0,
+        ];
+        let composite = [
+            0xFF, 0xFF, 0, 100, 0, 0, 1, 0xF4, 2, 0xBC, 0, 3, 0, 1, 0, 50, 0, 0, 0, 0,
+        ];
+        assert_eq!(table(b"glyf"), [&triangle[..], &composite[..]].concat());
+        assert_eq!(
+            table(b"loca"),
+            [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 24, 0, 0, 0, 44]
+        );
+        assert_eq!(
+            table(b"hmtx"),
+            [0x01, 0xF4, 0, 0, 0x02, 0x58, 0, 100, 0x02, 0xBC, 0, 100]
+        );
+        let original = Font::parse(&font, 0).unwrap();
+        for tag in [b"cmap", b"hhea", b"maxp", b"name", b"OS/2"] {
+            assert_eq!(table(tag), original.table(tag).unwrap().unwrap());
+        }
+    }
+
+    #[test]
+    fn invalid_input() {
+        let (woff2, _) = encode_font(&transformed_glyf(3), &[]);
+        let err = decode(&woff2[..woff2.len() - 10]).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("truncated WOFF2 compressed stream"),
+            "{err}"
+        );
+
+        // 解压后比表目录中的长度更长
+        let (woff2, _) = encode_font(&transformed_glyf(3), &[0; 100]);
+        let err = decode(&woff2).unwrap_err();
+        assert!(
+            err.to_string().starts_with("WOFF2 stream decompressed to"),
+            "{err}"
+        );
+
+        let (woff2, _) = encode_font(&transformed_glyf(0), &[]);
+        let err = decode(&woff2).unwrap_err();
+        assert_eq!(err.to_string(), "invalid number of points 0 for glyph 1");
+    }
+}
@@ -0,0 +1,189 @@
+use crate::sfnt::checksum;
+
+/// 需要写入字体文件的表
+pub struct Table {
+    pub tag: [u8; 4],
+    pub data: Vec<u8>,
+}
+
+/// 用表构建单个字体文件
+pub fn build_font(sfnt_version: u32, tables: &[Table]) -> Vec<u8> {
+    let indices: Vec<usize> = (0..tables.len()).collect();
+    let mut font = build_collection(&[(sfnt_version, indices)], tables);
+    // 单个字体文件需要计算 head.checkSumAdjustment，此时 head 中的值已经是 0
+    if let Some(offset) = table_offset(&font, 0, b"head") {
+        let adjustment = 0xB1B0AFBAu32.wrapping_sub(checksum(&font));
+        font[offset + 8..offset + 12].copy_from_slice(&adjustment.to_be_bytes());
+    }
+    font
+}
+
+/// 用表构建字体集合，每个字体是 sfnt 版本和它使用的表的序号，多个字体可以共用同一个表
+///
+/// 只有一个字体时不会写入 TTC 头，得到的是单个字体文件。
+pub fn build_collection(fonts: &[(u32, Vec<usize>)], tables: &[Table]) -> Vec<u8> {
+    let is_collection = fonts.len() > 1;
+    let header_len = if is_collection {
+        12 + 4 * fonts.len()
+    } else {
+        0
+    };
+    let directories_len: usize = fonts.iter().map(|(_, t)| 12 + 16 * t.len()).sum();
+
+    // 表的数据放在所有表目录之后，按 4 字节对齐
+    let mut table_offsets = vec![0usize; tables.len()];
+    let mut body = Vec::new();
+    for (i, table) in tables.iter().enumerate() {
+        table_offsets[i] = header_len + directories_len + body.len();
+        let mut data = table.data.clone();
+        if &table.tag == b"head" && data.len() >= 12 {
+            data[8..12].copy_from_slice(&[0; 4]);
+        }
+        body.extend_from_slice(&data);
+        body.resize(body.len().next_multiple_of(4), 0);
+    }
+
+    let mut out = Vec::with_capacity(header_len + directories_len + body.len());
+    if is_collection {
+        out.extend_from_slice(b"ttcf");
+        out.extend_from_slice(&0x00010000u32.to_be_bytes());
+        out.extend_from_slice(&(fonts.len() as u32).to_be_bytes());
+        let mut offset = header_len;
+        for (_, t) in fonts {
+            out.extend_from_slice(&(offset as u32).to_be_bytes());
+            offset += 12 + 16 * t.len();
+        }
+    }
+    for (sfnt_version, indices) in fonts {
+        let mut indices = indices.clone();
+        indices.sort_by_key(|&i| tables[i].tag);
+        let num_tables = indices.len() as u16;
+        let entry_selector = if num_tables == 0 {
+            0
+        } else {
+            15 - num_tables.leading_zeros() as u16
+        };
+        let search_range = (1u16 << entry_selector) * 16;
+        out.extend_from_slice(&sfnt_version.to_be_bytes());
+        out.extend_from_slice(&num_tables.to_be_bytes());
+        out.extend_from_slice(&search_range.to_be_bytes());
+        out.extend_from_slice(&entry_selector.to_be_bytes());
+        out.extend_from_slice(&(num_tables * 16 - search_range).to_be_bytes());
+        for i in indices {
+            let table = &tables[i];
+            let mut data = table.data.clone();
+            if &table.tag == b"head" && data.len() >= 12 {
+                data[8..12].copy_from_slice(&[0; 4]);
+            }
+            out.extend_from_slice(&table.tag);
+            out.extend_from_slice(&checksum(&data).to_be_bytes());
+            out.extend_from_slice(&(table_offsets[i] as u32).to_be_bytes());
+            out.extend_from_slice(&(table.data.len() as u32).to_be_bytes());
+        }
+    }
+    out.extend_from_slice(&body);
+    out
+}
+
+/// 在已经构建好的字体中查找表的偏移
+fn table_offset(font: &[u8], directory: usize, tag: &[u8; 4]) -> Option<usize> {
+    let num_tables = u16::from_be_bytes([font[directory + 4], font[directory + 5]]) as usize;
+    (0..num_tables)
+        .map(|i| directory + 12 + i * 16)
+        .find(|&record| &font[record..record + 4] == tag)
+        .map(|record| {
+            u32::from_be_bytes(font[record + 8..record + 12].try_into().unwrap()) as usize
+        })
+}
+
+/// 测试用的最小 TrueType 字体，chars 中的每个字符都有一个空字形
+///
+/// 家族、样式和全名是 Windows 平台的英文记录，字重大于等于 700 时设置粗体标志，样式中有 Italic 时设置斜体标志。
+#[cfg(test)]
+pub fn test_font(family: &str, subfamily: &str, weight: u16, chars: &str) -> Vec<u8> {
+    let num_glyphs = chars.chars().count() as u16 + 1;
+    let bold = weight >= 700;
+    let italic = subfamily.contains("Italic");
+
+    let mut head = vec![0u8; 54];
+    head[0..4].copy_from_slice(&0x00010000u32.to_be_bytes());
+    head[12..16].copy_from_slice(&0x5F0F3CF5u32.to_be_bytes());
+    head[18..20].copy_from_slice(&1000u16.to_be_bytes());
+    head[44..46].copy_from_slice(&(u16::from(bold) | u16::from(italic) << 1).to_be_bytes());
+    head[50..52].copy_from_slice(&1u16.to_be_bytes());
+    let mut hhea = vec![0u8; 36];
+    hhea[0..4].copy_from_slice(&0x00010000u32.to_be_bytes());
+    hhea[34..36].copy_from_slice(&num_glyphs.to_be_bytes());
+    let mut maxp = vec![0u8; 6];
+    maxp[0..4].copy_from_slice(&0x00005000u32.to_be_bytes());
+    maxp[4..6].copy_from_slice(&num_glyphs.to_be_bytes());
+    let mut os2 = vec![0u8; 78];
+    os2[4..6].copy_from_slice(&weight.to_be_bytes());
+    os2[6..8].copy_from_slice(&5u16.to_be_bytes());
+    let fs_selection = if italic { 0x0001 } else { 0 } | if bold { 0x0020 } else { 0 };
+    os2[62..64].copy_from_slice(&(fs_selection as u16).to_be_bytes());
+
+    // 只有一个 format 12 子表，每个字符是一组
+    let mut cmap = Vec::new();
+    for value in [0u16, 1, 3, 10] {
+        cmap.extend_from_slice(&value.to_be_bytes());
+    }
+    cmap.extend_from_slice(&12u32.to_be_bytes());
+    let groups: Vec<(u32, u32)> = chars
+        .chars()
+        .enumerate()
+        .map(|(i, c)| (c as u32, i as u32 + 1))
+        .collect();
+    cmap.extend_from_slice(&12u16.to_be_bytes());
+    cmap.extend_from_slice(&0u16.to_be_bytes());
+    for value in [16 + 12 * groups.len() as u32, 0, groups.len() as u32] {
+        cmap.extend_from_slice(&value.to_be_bytes());
+    }
+    for (c, glyph) in groups {
+        for value in [c, c, glyph] {
+            cmap.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+
+    let full_name = format!("{family} {subfamily}");
+    let records = [(1, family), (2, subfamily), (4, full_name.as_str())];
+    let mut name = Vec::new();
+    let mut storage = Vec::new();
+    for value in [0, records.len() as u16, 6 + records.len() as u16 * 12] {
+        name.extend_from_slice(&value.to_be_bytes());
+    }
+    for (name_id, value) in records {
+        let bytes: Vec<u8> = value.encode_utf16().flat_map(u16::to_be_bytes).collect();
+        for value in [
+            3,
+            1,
+            0x0409,
+            name_id,
+            bytes.len() as u16,
+            storage.len() as u16,
+        ] {
+            name.extend_from_slice(&value.to_be_bytes());
+        }
+        storage.extend_from_slice(&bytes);
+    }
+    name.extend_from_slice(&storage);
+
+    let tables = [
+        (*b"head", head),
+        (*b"hhea", hhea),
+        (*b"maxp", maxp),
+        (*b"OS/2", os2),
+        (*b"hmtx", vec![0; num_glyphs as usize * 4]),
+        (*b"cmap", cmap),
+        (*b"name", name),
+        (*b"loca", vec![0; (num_glyphs as usize + 1) * 4]),
+        (*b"glyf", Vec::new()),
+    ];
+    build_font(
+        0x00010000,
+        &tables
+            .into_iter()
+            .map(|(tag, data)| Table { tag, data })
+            .collect::<Vec<Table>>(),
+    )
+}
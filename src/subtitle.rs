@@ -0,0 +1,663 @@
+use anyhow::{Result, bail};
+use encoding_rs::{Encoding, GB18030, SHIFT_JIS, UTF_8};
+use std::{collections::BTreeSet, fmt, fs, path::Path};
+
+/// 字幕中使用的一种字体，字重和 GDI 一样是 100-900 的数值
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FontUsage {
+    pub family: String,
+    pub weight: u16,
+    pub italic: bool,
+}
+
+impl fmt::Display for FontUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.family)?;
+        let mut styles = Vec::new();
+        match self.weight {
+            400 => {}
+            700 => styles.push("Bold".to_string()),
+            weight => styles.push(format!("W{weight}")),
+        }
+        if self.italic {
+            styles.push("Italic".to_string());
+        }
+        if !styles.is_empty() {
+            write!(f, " ({})", styles.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+/// [V4+ Styles] 或 [V4 Styles] 中的一个样式
+#[derive(Debug, Clone)]
+struct Style {
+    name: String,
+    font: FontUsage,
+}
+
+/// [Events] 中的一行对话
+#[derive(Debug, Clone)]
+struct Dialogue {
+    /// 在文件中的行号，MKV 中的字幕轨道是对话的序号
+    line: usize,
+    style: String,
+    text: String,
+}
+
+/// 对话中使用同一种字体显示的一段文字
+#[derive(Debug, Clone)]
+pub struct FontRun {
+    pub line: usize,
+    pub font: FontUsage,
+    pub text: String,
+}
+
+impl FontRun {
+    /// 需要字体中有字形的字符，空白字符即使字体中没有字形也能正常显示
+    pub fn chars(&self) -> impl Iterator<Item = char> {
+        self.text
+            .chars()
+            .filter(|c| !c.is_whitespace() && !c.is_control())
+    }
+}
+
+/// [Fonts] 中内嵌的一个字体
+#[derive(Debug, Clone)]
+#[cfg_attr(not(any(feature = "ass-fonts", feature = "subset")), allow(dead_code))]
+pub struct EmbeddedFont {
+    /// fontname 行中的文件名，例如 `arial_0.ttf`
+    pub name: String,
+    /// 编码后的数据，已经去掉了换行
+    data: String,
+}
+
+impl EmbeddedFont {
+    /// 用 SSA 使用的 UUEncode 变体编码字体数据，是 decode 的逆操作
+    #[cfg(feature = "subset")]
+    pub fn encode(name: String, data: &[u8]) -> Self {
+        let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+        for chunk in data.chunks(3) {
+            let b = |i: usize| chunk.get(i).copied().unwrap_or(0);
+            let values = [
+                b(0) >> 2,
+                ((b(0) & 0x03) << 4) | (b(1) >> 4),
+                ((b(1) & 0x0F) << 2) | (b(2) >> 6),
+                b(2) & 0x3F,
+            ];
+            encoded.extend(values[..chunk.len() + 1].iter().map(|&v| (v + 33) as char));
+        }
+        Self {
+            name,
+            data: encoded,
+        }
+    }
+
+    /// [Fonts] 中的 fontname 行和编码后的数据，和 Aegisub 一样每行 80 个字符
+    #[cfg(feature = "subset")]
+    pub fn to_lines(&self) -> String {
+        let mut lines = format!("fontname: {}\n", self.name);
+        // 编码后的数据只有 ASCII 字符
+        for line in self.data.as_bytes().chunks(80) {
+            lines.push_str(std::str::from_utf8(line).unwrap());
+            lines.push('\n');
+        }
+        lines
+    }
+
+    /// 解码 SSA 使用的 UUEncode 变体，每 3 个字节编码为 4 个字符，每个字符是 6 位的值加上 33
+    ///
+    /// 最后不足 3 个字节时，1 个字节编码为 2 个字符，2 个字节编码为 3 个字符。
+    #[cfg_attr(not(feature = "ass-fonts"), allow(dead_code))]
+    pub fn decode(&self) -> Result<Vec<u8>> {
+        let mut values = Vec::with_capacity(self.data.len());
+        for c in self.data.bytes() {
+            if !(33..=96).contains(&c) {
+                bail!("invalid character 0x{c:02X} in embedded font {}", self.name);
+            }
+            values.push(c - 33);
+        }
+        if values.len() % 4 == 1 {
+            bail!("truncated embedded font {}", self.name);
+        }
+        let mut data = Vec::with_capacity(values.len() / 4 * 3 + 2);
+        for chunk in values.chunks(4) {
+            let v = |i: usize| chunk.get(i).copied().unwrap_or(0);
+            let bytes = [
+                (v(0) << 2) | (v(1) >> 4),
+                (v(1) << 4) | (v(2) >> 2),
+                (v(2) << 6) | v(3),
+            ];
+            data.extend_from_slice(&bytes[..chunk.len() - 1]);
+        }
+        Ok(data)
+    }
+}
+
+/// ASS/SSA 字幕中和字体有关的内容
+#[derive(Debug, Clone, Default)]
+pub struct Subtitle {
+    styles: Vec<Style>,
+    dialogues: Vec<Dialogue>,
+    pub fonts: Vec<EmbeddedFont>,
+}
+
+/// 没有 Format 行时使用的字段顺序
+const DEFAULT_STYLE_FORMAT: &str = "Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding";
+const DEFAULT_EVENT_FORMAT: &str =
+    "Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text";
+
+/// 把 Format 行解析为小写的字段名
+fn parse_format(format: &str) -> Vec<String> {
+    format
+        .split(',')
+        .map(|field| field.trim().to_ascii_lowercase())
+        .collect()
+}
+
+/// 样式中 Bold 字段和 \b 标签的值对应的字重，-1 和 1 表示粗体，0 表示常规
+fn parse_weight(value: &str) -> Option<u16> {
+    match value.trim().parse::<i32>().ok()? {
+        0 => Some(400),
+        -1 | 1 => Some(700),
+        weight @ 100..=900 => Some(weight as u16),
+        _ => None,
+    }
+}
+
+/// 没有 BOM 并且不是 UTF-8 的字幕常用的编码，得分相同时使用前面的编码
+const LEGACY_ENCODINGS: [&Encoding; 2] = [GB18030, SHIFT_JIS];
+
+/// 解码得到的文本像正常文字的程度
+///
+/// 替换字符说明数据不是这种编码。GB18030 几乎能解码任何数据，所以还要看文字本身：
+/// 日文通常有假名，而把 GBK 当作 Shift-JIS 解码时会得到字幕中很少出现的半角片假名。
+fn text_score(text: &str) -> i64 {
+    text.chars()
+        .map(|c| match c {
+            '\u{FFFD}' => -100,
+            '\u{FF61}'..='\u{FF9F}' | '\u{E000}'..='\u{F8FF}' => -1,
+            '\u{3040}'..='\u{30FF}' => 1,
+            _ => 0,
+        })
+        .sum()
+}
+
+impl Subtitle {
+    /// 读取字幕文件，根据 BOM 判断编码，没有 BOM 并且不是 UTF-8 时在 GB18030 和 Shift-JIS 中选择解码结果最像正常文字的编码
+    pub fn read(path: &Path) -> Result<Self> {
+        Ok(Self::parse(&Self::read_text(path)?))
+    }
+
+    /// 读取字幕文件的文本，编码的判断方法和 read 相同
+    pub fn read_text(path: &Path) -> Result<String> {
+        let data = fs::read(path)?;
+        let (encoding, bom) = Encoding::for_bom(&data).unwrap_or((UTF_8, 0));
+        let data = &data[bom..];
+        if let Some(text) = encoding.decode_without_bom_handling_and_without_replacement(data) {
+            return Ok(text.into_owned());
+        }
+        let mut best: Option<(i64, String)> = None;
+        for encoding in LEGACY_ENCODINGS {
+            let text = encoding.decode_without_bom_handling(data).0;
+            let score = text_score(&text);
+            if best.as_ref().is_none_or(|(best, _)| score > *best) {
+                best = Some((score, text.into_owned()));
+            }
+        }
+        Ok(best.unwrap().1)
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let mut subtitle = Self::default();
+        let mut section = String::new();
+        let mut style_format = parse_format(DEFAULT_STYLE_FORMAT);
+        let mut event_format = parse_format(DEFAULT_EVENT_FORMAT);
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            // 编码后的字体数据也可能以 [ 开头并以 ] 结尾，但是不会包含小写字母和空格等字符
+            if line.starts_with('[')
+                && line.ends_with(']')
+                && (section != "[fonts]" || line.bytes().any(|c| !(33..=96).contains(&c)))
+            {
+                section = line.to_ascii_lowercase();
+                continue;
+            }
+            if section == "[fonts]" {
+                // 编码后的字体数据中也可能有冒号，只有 fontname 行开始一个新的字体
+                if let Some(name) = line.strip_prefix("fontname:") {
+                    subtitle.fonts.push(EmbeddedFont {
+                        name: name.trim().to_string(),
+                        data: String::new(),
+                    });
+                } else if let Some(font) = subtitle.fonts.last_mut() {
+                    font.data.push_str(line);
+                }
+                continue;
+            }
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let key = key.trim().to_ascii_lowercase();
+            let value = value.trim_start();
+            match (section.as_str(), key.as_str()) {
+                ("[v4+ styles]" | "[v4 styles]", "format") => style_format = parse_format(value),
+                ("[v4+ styles]" | "[v4 styles]", "style") => {
+                    let fields: Vec<&str> = value.splitn(style_format.len(), ',').collect();
+                    let field = |name: &str| {
+                        style_format
+                            .iter()
+                            .position(|f| f == name)
+                            .and_then(|i| fields.get(i))
+                            .map(|v| v.trim())
+                    };
+                    subtitle.styles.push(Style {
+                        // libass 和 VSFilter 都会忽略样式名开头的 *
+                        name: field("name")
+                            .unwrap_or_default()
+                            .trim_start_matches('*')
+                            .to_string(),
+                        font: FontUsage {
+                            family: field("fontname").unwrap_or_default().to_string(),
+                            weight: field("bold").and_then(parse_weight).unwrap_or(400),
+                            italic: field("italic").is_some_and(|v| v != "0"),
+                        },
+                    });
+                }
+                ("[events]", "format") => event_format = parse_format(value),
+                ("[events]", "dialogue") => {
+                    // Text 是最后一个字段，其中可以包含逗号
+                    let fields: Vec<&str> = value.splitn(event_format.len(), ',').collect();
+                    let field = |name: &str| {
+                        event_format
+                            .iter()
+                            .position(|f| f == name)
+                            .and_then(|i| fields.get(i))
+                    };
+                    subtitle.dialogues.push(Dialogue {
+                        line: number + 1,
+                        style: field("style").unwrap_or(&"").trim().to_string(),
+                        text: field("text").unwrap_or(&"").to_string(),
+                    });
+                }
+                _ => {}
+            }
+        }
+        subtitle
+    }
+
+    /// 解析 Matroska 中的 ASS/SSA 字幕轨道
+    ///
+    /// header 是 CodecPrivate 中的文件头，events 是每个块中的一行对话，
+    /// 字段为 ReadOrder, Layer, Style, Name, MarginL, MarginR, MarginV, Effect, Text。
+    #[cfg(feature = "archive-mkv")]
+    pub fn parse_matroska(header: &str, events: &[String]) -> Self {
+        let mut text = header.to_string();
+        text.push_str("\n[Events]\nFormat: ReadOrder, Layer, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n");
+        for event in events {
+            text.push_str("Dialogue: ");
+            text.push_str(event);
+            text.push('\n');
+        }
+        let mut subtitle = Self::parse(&text);
+        for (i, dialogue) in subtitle.dialogues.iter_mut().enumerate() {
+            dialogue.line = i + 1;
+        }
+        subtitle
+    }
+
+    /// 重命名字幕文本中样式的 Fontname 和对话中 \\fn 标签使用的字体，其他内容保持不变
+    ///
+    /// rename 返回字体的新名称，不需要重命名时返回 None，竖排字体名称开头的 @ 会被保留。
+    #[cfg(feature = "subset")]
+    pub fn rename_fonts(text: &str, rename: impl Fn(&str) -> Option<String>) -> String {
+        let rename = |name: &str| {
+            let trimmed = name.trim();
+            let (prefix, family) = match trimmed.strip_prefix('@') {
+                Some(family) => ("@", family),
+                None => ("", trimmed),
+            };
+            rename(family).map(|new| name.replacen(trimmed, &format!("{prefix}{new}"), 1))
+        };
+        let mut output = String::with_capacity(text.len());
+        let mut section = String::new();
+        let mut style_format = parse_format(DEFAULT_STYLE_FORMAT);
+        for raw in text.split_inclusive('\n') {
+            let line = raw.trim_end_matches(['\r', '\n']);
+            let ending = &raw[line.len()..];
+            let trimmed = line.trim();
+            if trimmed.starts_with('[')
+                && trimmed.ends_with(']')
+                && (section != "[fonts]" || trimmed.bytes().any(|c| !(33..=96).contains(&c)))
+            {
+                section = trimmed.to_ascii_lowercase();
+                output.push_str(raw);
+                continue;
+            }
+            let renamed = line.split_once(':').and_then(|(key, value)| {
+                match (section.as_str(), key.trim().to_ascii_lowercase().as_str()) {
+                    ("[v4+ styles]" | "[v4 styles]", "format") => {
+                        style_format = parse_format(value);
+                        None
+                    }
+                    ("[v4+ styles]" | "[v4 styles]", "style") => {
+                        let i = style_format.iter().position(|f| f == "fontname")?;
+                        let mut fields: Vec<String> = value
+                            .splitn(style_format.len(), ',')
+                            .map(str::to_string)
+                            .collect();
+                        fields[i] = rename(fields.get(i)?)?;
+                        Some(format!("{key}:{}", fields.join(",")))
+                    }
+                    ("[events]", "dialogue") => {
+                        let mut renamed = format!("{key}:");
+                        let mut rest = value;
+                        while let Some(start) = rest.find('{') {
+                            let end = rest[start..]
+                                .find('}')
+                                .map_or(rest.len(), |end| start + end);
+                            renamed.push_str(&rest[..=start]);
+                            let tags: Vec<String> = rest[start + 1..end]
+                                .split('\\')
+                                .enumerate()
+                                .map(|(i, tag)| match tag.strip_prefix("fn") {
+                                    Some(name) if i > 0 && !name.trim().is_empty() => rename(name)
+                                        .map_or(tag.to_string(), |new| format!("fn{new}")),
+                                    _ => tag.to_string(),
+                                })
+                                .collect();
+                            renamed.push_str(&tags.join("\\"));
+                            rest = &rest[end..];
+                        }
+                        renamed.push_str(rest);
+                        Some(renamed)
+                    }
+                    _ => None,
+                }
+            });
+            match renamed {
+                Some(renamed) => {
+                    output.push_str(&renamed);
+                    output.push_str(ending);
+                }
+                None => output.push_str(raw),
+            }
+        }
+        output
+    }
+
+    /// 把字体加入字幕文本的 [Fonts] 部分，没有这个部分时添加到文件末尾
+    #[cfg(feature = "subset")]
+    pub fn embed_fonts(text: &str, fonts: &[EmbeddedFont]) -> String {
+        let lines: String = fonts.iter().map(|font| font.to_lines()).collect();
+        let mut output = String::with_capacity(text.len() + lines.len() + 10);
+        let mut embedded = false;
+        for raw in text.split_inclusive('\n') {
+            output.push_str(raw);
+            if !embedded && raw.trim().eq_ignore_ascii_case("[fonts]") {
+                if !raw.ends_with('\n') {
+                    output.push('\n');
+                }
+                output.push_str(&lines);
+                embedded = true;
+            }
+        }
+        if !embedded {
+            if !output.is_empty() && !output.ends_with('\n') {
+                output.push('\n');
+            }
+            output.push_str("\n[Fonts]\n");
+            output.push_str(&lines);
+        }
+        output
+    }
+
+    /// 根据名称查找样式，和 VSFilter 一样不区分大小写，找不到时使用 Default 样式
+    fn style(&self, name: &str) -> Option<&Style> {
+        let name = name.trim_start_matches('*');
+        self.styles
+            .iter()
+            .find(|s| s.name.eq_ignore_ascii_case(name))
+            .or_else(|| {
+                self.styles
+                    .iter()
+                    .find(|s| s.name.eq_ignore_ascii_case("Default"))
+            })
+            .or(self.styles.first())
+    }
+
+    /// 把一行对话拆分为使用同一种字体的文字，去掉特效标签和绘图
+    fn runs(&self, dialogue: &Dialogue) -> Vec<FontRun> {
+        let Some(style) = self.style(&dialogue.style) else {
+            return Vec::new();
+        };
+        let mut runs: Vec<FontRun> = Vec::new();
+        let mut font = style.font.clone();
+        let mut drawing = false;
+        let mut rest = dialogue.text.as_str();
+        while !rest.is_empty() {
+            let (text, tags) = match rest.find('{') {
+                Some(start) => {
+                    let end = rest[start..]
+                        .find('}')
+                        .map_or(rest.len(), |end| start + end);
+                    let tags = &rest[start + 1..end];
+                    let text = &rest[..start];
+                    rest = rest.get(end + 1..).unwrap_or("");
+                    (text, Some(tags))
+                }
+                None => (std::mem::take(&mut rest), None),
+            };
+            if !drawing && !text.is_empty() {
+                let text = text
+                    .replace("\\N", "")
+                    .replace("\\n", "")
+                    .replace("\\h", "\u{A0}");
+                match runs.last_mut() {
+                    Some(last) if last.font == font => last.text.push_str(&text),
+                    _ => runs.push(FontRun {
+                        line: dialogue.line,
+                        font: font.clone(),
+                        text,
+                    }),
+                }
+            }
+            for tag in tags.into_iter().flat_map(split_tags) {
+                let tag = tag.trim();
+                let digits = |prefix: &str| {
+                    tag.strip_prefix(prefix)
+                        .filter(|v| !v.is_empty() && v.chars().all(|c| c.is_ascii_digit()))
+                };
+                if let Some(family) = tag.strip_prefix("fn") {
+                    // \fn 后面为空或者是 0 时恢复样式的字体
+                    let family = family.trim();
+                    font.family = if family.is_empty() || family == "0" {
+                        style.font.family.clone()
+                    } else {
+                        family.to_string()
+                    };
+                } else if let Some(weight) = digits("b") {
+                    font.weight = parse_weight(weight).unwrap_or(font.weight);
+                } else if let Some(italic) = digits("i") {
+                    font.italic = italic != "0";
+                } else if let Some(scale) = digits("p") {
+                    drawing = scale != "0";
+                } else if let Some(name) = tag.strip_prefix('r') {
+                    font = if name.is_empty() {
+                        style.font.clone()
+                    } else {
+                        self.style(name).unwrap_or(style).font.clone()
+                    };
+                }
+            }
+        }
+        runs.retain(|run| !run.text.trim().is_empty());
+        runs
+    }
+
+    /// 所有对话中实际显示的文字和使用的字体，按行号排序
+    pub fn font_runs(&self) -> Vec<FontRun> {
+        self.dialogues
+            .iter()
+            .flat_map(|dialogue| self.runs(dialogue))
+            .filter(|run| !run.font.family.is_empty())
+            .collect()
+    }
+
+    /// 对话中实际显示了文字的所有字体，包括粗体和斜体等变体
+    pub fn font_usages(&self) -> BTreeSet<FontUsage> {
+        self.font_runs().into_iter().map(|run| run.font).collect()
+    }
+}
+
+/// 把一个 {} 中的覆盖标签按 `\` 分开，返回的标签不包含开头的 `\`
+///
+/// `\t(...)` 中的标签作为 t 标签的一部分返回，不会改变使用的字体；只有 t 标签的括号会被匹配，
+/// 字体名称中的括号不影响分开标签。
+fn split_tags(tags: &str) -> Vec<&str> {
+    let mut result = Vec::new();
+    let mut start = None;
+    let mut depth = 0usize;
+    for (i, c) in tags.char_indices() {
+        match c {
+            '(' if depth > 0 || start.is_some_and(|start| tags[start..i].trim() == "t") => {
+                depth += 1
+            }
+            ')' if depth > 0 => depth -= 1,
+            '\\' if depth == 0 => {
+                if let Some(start) = start {
+                    result.push(&tags[start..i]);
+                }
+                start = Some(i + 1);
+            }
+            _ => {}
+        }
+    }
+    if let Some(start) = start {
+        result.push(&tags[start..]);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn usage(family: &str, weight: u16, italic: bool) -> FontUsage {
+        FontUsage {
+            family: family.to_string(),
+            weight,
+            italic,
+        }
+    }
+
+    /// 每段文字和它使用的字体
+    fn runs(text: &str) -> Vec<(String, FontUsage)> {
+        Subtitle::parse(text)
+            .font_runs()
+            .into_iter()
+            .map(|run| (run.text, run.font))
+            .collect()
+    }
+
+    const STYLES: &str = "[V4+ Styles]
+Format: Name, Fontname, Fontsize, Bold, Italic
+Style: *Default,Sans,20,0,0
+Style: Title,Serif,40,-1,1
+
+[Events]
+Format: Layer, Start, End, Style, Text
+";
+
+    #[test]
+    fn override_tags() {
+        let text = format!(
+            "{STYLES}Dialogue: 0,0:00:00.00,0:00:01.00,Default,{{\\fnMono}}a{{\\b1}}b{{\\i1\\b600}}c{{\\r}}d{{\\rTitle}}e{{\\fn}}f{{\\p1}}m 0 0 l 1 1{{\\p0}}g, h\\Ni\n\
+Dialogue: 0,0:00:01.00,0:00:02.00,Default,{{\\fnMono\\t(0,500,\\fnSerif\\b1)\\i1}}j{{\\fn0}}k{{\\fnA (B\\b1}}l\n"
+        );
+        assert_eq!(
+            runs(&text),
+            [
+                ("a".to_string(), usage("Mono", 400, false)),
+                ("b".to_string(), usage("Mono", 700, false)),
+                ("c".to_string(), usage("Mono", 600, true)),
+                ("d".to_string(), usage("Sans", 400, false)),
+                ("e".to_string(), usage("Serif", 700, true)),
+                // \fn 后面为空时恢复的是对话的样式的字体，\p1 和 \p0 之间的绘图不是文字
+                ("fg, hi".to_string(), usage("Sans", 700, true)),
+                // \t(...) 中的 \fn 和 \b 不会改变字体，\fn0 也恢复样式的字体
+                ("j".to_string(), usage("Mono", 400, true)),
+                ("k".to_string(), usage("Sans", 400, true)),
+                ("l".to_string(), usage("A (B", 700, true)),
+            ]
+        );
+    }
+
+    #[test]
+    fn style_inheritance() {
+        let text = format!(
+            "{STYLES}Dialogue: 0,0:00:00.00,0:00:01.00,*title,a
+Dialogue: 0,0:00:00.00,0:00:01.00,Missing,b
+Dialogue: 0,0:00:00.00,0:00:01.00,Title,{{\\b0\\rDefault}}c{{\\rTitle}}d
+
+[V4 Styles]
+Format: Name, Fontname, Bold
+Style: Old,Gothic,-1
+"
+        );
+        assert_eq!(
+            runs(&text),
+            [
+                // 样式名不区分大小写，开头的 * 会被忽略
+                ("a".to_string(), usage("Serif", 700, true)),
+                // 找不到的样式使用 Default 样式
+                ("b".to_string(), usage("Sans", 400, false)),
+                ("c".to_string(), usage("Sans", 400, false)),
+                ("d".to_string(), usage("Serif", 700, true)),
+            ]
+        );
+        let subtitle = Subtitle::parse(&text);
+        assert_eq!(
+            subtitle.style("old").unwrap().font,
+            usage("Gothic", 700, false)
+        );
+    }
+
+    #[cfg(feature = "subset")]
+    #[test]
+    fn uuencode_round_trip() {
+        for len in [0, 1, 2, 3, 4, 5, 61, 256] {
+            let data: Vec<u8> = (0..len).map(|i| (i * 7 % 256) as u8).collect();
+            let font = EmbeddedFont::encode("font_0.ttf".to_string(), &data);
+            let text = Subtitle::embed_fonts("[Script Info]\nTitle: test\n", &[font]);
+            let fonts = Subtitle::parse(&text).fonts;
+            assert_eq!(fonts.len(), 1);
+            assert_eq!(fonts[0].name, "font_0.ttf");
+            assert_eq!(fonts[0].decode().unwrap(), data);
+        }
+        let invalid = EmbeddedFont {
+            name: "bad.ttf".to_string(),
+            data: "!!!!a".to_string(),
+        };
+        assert!(invalid.decode().is_err());
+    }
+
+    #[test]
+    fn legacy_encodings() {
+        let text = "[V4+ Styles]\nStyle: Default,ＭＳ ゴシック\n\n[Events]\nDialogue: 0,0:00:00.00,0:00:01.00,Default,,0,0,0,,こんにちは、世界\n";
+        let chinese = "[V4+ Styles]\nStyle: Default,方正准圆_GBK\n\n[Events]\nDialogue: 0,0:00:00.00,0:00:01.00,Default,,0,0,0,,你好，世界\n";
+        let files = [
+            (text, SHIFT_JIS.encode(text).0.into_owned()),
+            (chinese, GB18030.encode(chinese).0.into_owned()),
+            (text, text.as_bytes().to_vec()),
+            (text, [&[0xEF, 0xBB, 0xBF], text.as_bytes()].concat()),
+        ];
+        let temp = TempDir::new("fontloader-test").unwrap();
+        let path = temp.path().join("legacy.ass");
+        for (expected, data) in files {
+            fs::write(&path, data).unwrap();
+            assert_eq!(Subtitle::read_text(&path).unwrap(), expected);
+        }
+    }
+}